@@ -1,13 +1,13 @@
 #!/bin/bash
 
-LOGFILE=/memvectordb/output.log
+DATA_DIR=/memvectordb
 
-mkdir -p /memvectordb
+mkdir -p $DATA_DIR
 
 if [ "$RESTORE_DB" = "true" ]; then
-    echo "Starting memvectordb with database restoration..." | tee -a $LOGFILE
-    /usr/local/bin/memvectordb --restore-db 2>&1 | tee -a $LOGFILE
+    echo "Starting memvectordb with database restoration..."
+    exec /usr/local/bin/memvectordb serve --data-dir $DATA_DIR --restore
 else
-    echo "Starting memvectordb" | tee -a $LOGFILE
-    /usr/local/bin/memvectordb 2>&1 | tee -a $LOGFILE
+    echo "Starting memvectordb"
+    exec /usr/local/bin/memvectordb serve --data-dir $DATA_DIR
 fi
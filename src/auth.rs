@@ -0,0 +1,434 @@
+use crate::model::{
+    AliasAction, BatchInsertEmbeddingsStruct, CollectionHandlerStruct, CreateCollectionStruct, CreateTextIndexStruct,
+    AlterCollectionStruct, CacheLookupStruct, CachePutStruct, CloneCollectionStruct, DeleteEmbeddingsStruct, GetSimilarityStruct, HybridSearchStruct,
+    InsertEmbeddingStruct, RecommendStruct, RenameCollectionStruct, UpdateAliasesStruct,
+};
+use crate::response::GenericResponse;
+use crate::tenant::{self, SEPARATOR};
+use log::{error, warn};
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use warp::{http::StatusCode, path::FullPath, reject::Reject, Filter, Rejection, Reply};
+
+/// What a key is allowed to do, each role including the ones below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Read collections and run searches.
+    Read,
+    /// Also insert and update embeddings.
+    ReadWrite,
+    /// Also create and delete collections and indexes.
+    Admin,
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "read" => Ok(Role::Read),
+            "read_write" => Ok(Role::ReadWrite),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role '{}'", role)),
+        }
+    }
+}
+
+/// The permissions granted to one API key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKey {
+    pub role: Role,
+    /// The collections the key is limited to, or `None` for every collection.
+    pub collections: Option<HashSet<String>>,
+    /// The tenant whose collections the key is limited to, or `None` for collections outside of tenants.
+    pub tenant: Option<String>,
+}
+
+impl ApiKey {
+    fn allows(&self, role: Role, tenant: Option<&str>, collection_name: &str) -> bool {
+        self.role >= role
+            && self.tenant.as_deref() == tenant
+            && self.collections.as_ref().is_none_or(|collections| collections.contains(collection_name))
+    }
+}
+
+/// The configured API keys and tenants. Authentication is disabled when no keys are configured.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuthConfig {
+    keys: HashMap<String, ApiKey>,
+    tenants: HashSet<String>,
+}
+
+impl AuthConfig {
+    /// Parse API keys given as `key=role[:collection,...]` entries separated by `;`.
+    ///
+    /// # Example
+    ///
+    /// `admin-key=admin;reader-key=read:books,movies` grants the first key admin rights on every
+    /// collection and the second read access to the `books` and `movies` collections only.
+    pub fn parse(keys: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        config.add_keys(keys, None)?;
+        Ok(config)
+    }
+
+    /// Add a tenant with its own API keys, given like those of `parse` and limited to its collections.
+    pub fn add_tenant(&mut self, tenant: &str, keys: &str) -> Result<(), String> {
+        if tenant.is_empty() || tenant.contains(SEPARATOR) {
+            return Err(format!("Tenant name '{}' must be non-empty and can't contain '{}'", tenant, SEPARATOR));
+        }
+        self.tenants.insert(tenant.to_string());
+        self.add_keys(keys, Some(tenant))
+    }
+
+    fn add_keys(&mut self, keys: &str, tenant: Option<&str>) -> Result<(), String> {
+        for entry in keys.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let Some((key, grant)) = entry.split_once('=') else {
+                return Err(format!("API key entry '{}' is missing a role", entry));
+            };
+            let (role, collections) = match grant.split_once(':') {
+                Some((role, collections)) => (role, Some(collections.split(',').map(|name| name.trim().to_string()).collect())),
+                None => (grant, None),
+            };
+            // A key shared by tenants would let one tenant into another's collections.
+            let key = key.trim();
+            if self.keys.get(key).is_some_and(|api_key| api_key.tenant.as_deref() != tenant) {
+                return Err(format!("API key entry '{}' reuses a key of another tenant", entry));
+            }
+            let api_key = ApiKey { role: role.trim().parse()?, collections, tenant: tenant.map(str::to_string) };
+            self.keys.insert(key.to_string(), api_key);
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Check that `tenant`, if any, is configured.
+    pub fn check_tenant(&self, tenant: Option<&str>) -> Result<(), AuthError> {
+        match tenant {
+            Some(tenant) if !self.tenants.contains(tenant) => {
+                warn!("Request rejected for unknown tenant '{}'", tenant);
+                Err(AuthError::UnknownTenant)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that `key` grants at least `role` on the collection `collection_name` of `tenant`,
+    /// or outside of tenants without one.
+    pub fn authorize(&self, key: Option<&str>, role: Role, tenant: Option<&str>, collection_name: &str) -> Result<(), AuthError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let Some(key) = key else {
+            return Err(AuthError::MissingKey);
+        };
+        let Some(api_key) = self.keys.get(key) else {
+            warn!("Request rejected with an unknown API key");
+            return Err(AuthError::InvalidKey);
+        };
+        if !api_key.allows(role, tenant, collection_name) {
+            warn!(
+                "API key with role {:?} denied {:?} access to collection '{}'",
+                api_key.role, role, tenant::qualify(tenant, collection_name)
+            );
+            return Err(AuthError::Forbidden);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthError {
+    MissingKey,
+    InvalidKey,
+    Forbidden,
+    UnknownTenant,
+    /// The path and the `X-Tenant` header select different tenants.
+    ConflictingTenants,
+    /// A collection or alias name contains the tenant separator.
+    InvalidName,
+}
+
+impl Reject for AuthError {}
+
+/// Request bodies naming the collections or aliases they operate on.
+pub trait CollectionScoped {
+    fn collection_names(&self) -> Vec<&str>;
+    /// The same names, to be rewritten into the namespace of a tenant.
+    fn collection_names_mut(&mut self) -> Vec<&mut String>;
+}
+
+macro_rules! impl_collection_scoped {
+    ($($body:ty),*) => {
+        $(impl $crate::auth::CollectionScoped for $body {
+            fn collection_names(&self) -> Vec<&str> {
+                vec![&self.collection_name]
+            }
+
+            fn collection_names_mut(&mut self) -> Vec<&mut String> {
+                vec![&mut self.collection_name]
+            }
+        })*
+    };
+}
+
+pub(crate) use impl_collection_scoped;
+
+impl_collection_scoped!(
+    CreateCollectionStruct,
+    InsertEmbeddingStruct,
+    CollectionHandlerStruct,
+    BatchInsertEmbeddingsStruct,
+    DeleteEmbeddingsStruct,
+    GetSimilarityStruct,
+    RecommendStruct,
+    CreateTextIndexStruct,
+    HybridSearchStruct,
+    AlterCollectionStruct,
+    CacheLookupStruct,
+    CachePutStruct
+);
+
+/// Renaming or copying a collection needs rights on both the old and the new name.
+macro_rules! impl_collection_scoped_pair {
+    ($($body:ty),*) => {
+        $(impl CollectionScoped for $body {
+            fn collection_names(&self) -> Vec<&str> {
+                vec![&self.collection_name, &self.new_name]
+            }
+
+            fn collection_names_mut(&mut self) -> Vec<&mut String> {
+                vec![&mut self.collection_name, &mut self.new_name]
+            }
+        })*
+    };
+}
+
+impl_collection_scoped_pair!(RenameCollectionStruct, CloneCollectionStruct);
+
+impl CollectionScoped for UpdateAliasesStruct {
+    /// Every alias and collection the actions name, since a key must be allowed to change all of them.
+    fn collection_names(&self) -> Vec<&str> {
+        self.actions
+            .iter()
+            .flat_map(|action| match action {
+                AliasAction::CreateAlias { alias, collection_name } => vec![alias.as_str(), collection_name.as_str()],
+                AliasAction::DeleteAlias { alias } => vec![alias.as_str()],
+            })
+            .collect()
+    }
+
+    fn collection_names_mut(&mut self) -> Vec<&mut String> {
+        self.actions
+            .iter_mut()
+            .flat_map(|action| match action {
+                AliasAction::CreateAlias { alias, collection_name } => vec![alias, collection_name],
+                AliasAction::DeleteAlias { alias } => vec![alias],
+            })
+            .collect()
+    }
+}
+
+/// Read the API key from an `Authorization: Bearer <key>` or `X-Api-Key: <key>` header.
+pub fn api_key(authorization: Option<String>, x_api_key: Option<String>) -> Option<String> {
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer ").map(|key| key.trim().to_string()))
+        .or(x_api_key)
+}
+
+/// Read the tenant from a `/tenants/<tenant>/` path prefix or an `X-Tenant: <tenant>` header,
+/// which must agree if both are given.
+fn selected_tenant(path: &str, x_tenant: Option<String>) -> Result<Option<String>, AuthError> {
+    match (tenant::from_path(path), x_tenant) {
+        (Some(from_path), Some(from_header)) if from_path != from_header => Err(AuthError::ConflictingTenants),
+        (Some(from_path), _) => Ok(Some(from_path.to_string())),
+        (None, from_header) => Ok(from_header),
+    }
+}
+
+/// Check that the API key of a request is authorized for `role` on every collection the body
+/// names within the selected tenant, then move those names into the tenant's namespace.
+pub fn authorize_body<T: CollectionScoped>(auth: &AuthConfig, key: Option<&str>, role: Role, tenant: Option<&str>, body: &mut T) -> Result<(), AuthError> {
+    auth.check_tenant(tenant)?;
+    for name in body.collection_names() {
+        if name.contains(SEPARATOR) {
+            warn!("Request rejected for collection name '{}' containing '{}'", name, SEPARATOR);
+            return Err(AuthError::InvalidName);
+        }
+        auth.authorize(key, role, tenant, name)?;
+    }
+    if tenant.is_some() {
+        for name in body.collection_names_mut() {
+            *name = tenant::qualify(tenant, name);
+        }
+    }
+    Ok(())
+}
+
+/// A filter extracting the JSON body of a request once its API key is authorized for `role`
+/// on every collection the body names. Requests for a tenant get the names qualified with it, so
+/// handlers only ever see collections of the tenant.
+pub fn authorized_json<T>(auth: Arc<AuthConfig>, role: Role) -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + CollectionScoped + Send,
+{
+    warp::header::optional::<String>("authorization")
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and(warp::header::optional::<String>("x-tenant"))
+        .and(warp::path::full())
+        .and(warp::body::json::<T>())
+        .and_then(move |authorization, x_api_key, x_tenant, path: FullPath, mut body: T| {
+            let auth = auth.clone();
+            async move {
+                let key = api_key(authorization, x_api_key);
+                let authorized = selected_tenant(path.as_str(), x_tenant)
+                    .and_then(|tenant| authorize_body(&auth, key.as_deref(), role, tenant.as_deref(), &mut body));
+                match authorized {
+                    Ok(()) => Ok(body),
+                    Err(err) => Err(warp::reject::custom(err)),
+                }
+            }
+        })
+}
+
+/// Turn authentication rejections into 401 and 403 responses, passing other rejections through.
+pub async fn handle_auth_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    let Some(err) = rejection.find::<AuthError>() else {
+        return Err(rejection);
+    };
+    let (status, message) = match err {
+        AuthError::MissingKey => (StatusCode::UNAUTHORIZED, "Missing API key"),
+        AuthError::InvalidKey => (StatusCode::UNAUTHORIZED, "Invalid API key"),
+        AuthError::Forbidden => (StatusCode::FORBIDDEN, "API key isn't allowed to perform this operation"),
+        AuthError::UnknownTenant => (StatusCode::NOT_FOUND, "Unknown tenant"),
+        AuthError::ConflictingTenants => (StatusCode::BAD_REQUEST, "The path and the X-Tenant header select different tenants"),
+        AuthError::InvalidName => (StatusCode::BAD_REQUEST, "Collection and alias names can't contain '/'"),
+    };
+    error!("Request rejected: {}", message);
+    let response = GenericResponse { status: "error".to_string(), message: message.to_string() };
+    Ok(warp::reply::with_status(warp::reply::json(&response), status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_api_keys() {
+        let config = AuthConfig::parse("admin-key=admin; reader=read:books,movies").unwrap();
+        assert_eq!(config.keys["admin-key"], ApiKey { role: Role::Admin, collections: None, tenant: None });
+        let books: HashSet<String> = ["books".to_string(), "movies".to_string()].into();
+        assert_eq!(config.keys["reader"], ApiKey { role: Role::Read, collections: Some(books), tenant: None });
+
+        assert!(AuthConfig::parse("key=owner").is_err());
+        assert!(AuthConfig::parse("key").is_err());
+        assert!(!AuthConfig::parse("").unwrap().is_enabled());
+    }
+
+    #[test]
+    fn test_authorize() {
+        let config = AuthConfig::parse("writer=read_write;reader=read:books").unwrap();
+        assert_eq!(config.authorize(None, Role::Read, None, "books"), Err(AuthError::MissingKey));
+        assert_eq!(config.authorize(Some("nope"), Role::Read, None, "books"), Err(AuthError::InvalidKey));
+        assert_eq!(config.authorize(Some("writer"), Role::ReadWrite, None, "books"), Ok(()));
+        assert_eq!(config.authorize(Some("writer"), Role::Admin, None, "books"), Err(AuthError::Forbidden));
+        assert_eq!(config.authorize(Some("reader"), Role::Read, None, "books"), Ok(()));
+        assert_eq!(config.authorize(Some("reader"), Role::Read, None, "movies"), Err(AuthError::Forbidden));
+        assert_eq!(AuthConfig::default().authorize(None, Role::Admin, None, "books"), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_authorized_json_filter() {
+        let config = AuthConfig::parse("reader=read:books").unwrap();
+        let filter = authorized_json::<CollectionHandlerStruct>(Arc::new(config), Role::Read)
+            .map(|body: CollectionHandlerStruct| body.collection_name)
+            .recover(handle_auth_rejection);
+        let body = json!({ "collection_name": "books" });
+
+        let response = warp::test::request().json(&body).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let message: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(message["status"], "error");
+
+        let response = warp::test::request().header("authorization", "Bearer reader").json(&body).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "books");
+
+        let response = warp::test::request()
+            .header("x-api-key", "reader")
+            .json(&json!({ "collection_name": "movies" }))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_authorized_alias_actions() {
+        let config = AuthConfig::parse("owner=admin:books,books_v2,live").unwrap();
+        let filter = authorized_json::<UpdateAliasesStruct>(Arc::new(config), Role::Admin)
+            .map(|body: UpdateAliasesStruct| body.actions.len().to_string())
+            .recover(handle_auth_rejection);
+        let swap = |collection_name: &str| {
+            json!({ "actions": [
+                { "delete_alias": { "alias": "live" } },
+                { "create_alias": { "alias": "live", "collection_name": collection_name } },
+            ] })
+        };
+
+        let response = warp::test::request().header("x-api-key", "owner").json(&swap("books_v2")).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = warp::test::request().header("x-api-key", "owner").json(&swap("movies")).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_tenant_keys() {
+        let mut config = AuthConfig::parse("operator=admin").unwrap();
+        config.add_tenant("team-a", "a-admin=admin;a-reader=read:books").unwrap();
+        config.add_tenant("team-b", "b-admin=admin").unwrap();
+        assert_eq!(config.authorize(Some("a-admin"), Role::Admin, Some("team-a"), "books"), Ok(()));
+        assert_eq!(config.authorize(Some("a-reader"), Role::Read, Some("team-a"), "movies"), Err(AuthError::Forbidden));
+        assert_eq!(config.authorize(Some("a-admin"), Role::Read, Some("team-b"), "books"), Err(AuthError::Forbidden));
+        assert_eq!(config.authorize(Some("a-admin"), Role::Read, None, "books"), Err(AuthError::Forbidden));
+        assert_eq!(config.authorize(Some("operator"), Role::Read, Some("team-a"), "books"), Err(AuthError::Forbidden));
+        assert_eq!(config.check_tenant(Some("team-c")), Err(AuthError::UnknownTenant));
+
+        assert!(config.add_tenant("team-c", "b-admin=read").is_err());
+        assert!(config.add_tenant("team/c", "").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_authorized_json_tenants() {
+        let mut config = AuthConfig::parse("operator=admin").unwrap();
+        config.add_tenant("team-a", "a-reader=read").unwrap();
+        let filter = crate::tenant::skip_path_prefix()
+            .and(warp::path!("get_collection"))
+            .and(authorized_json::<CollectionHandlerStruct>(Arc::new(config), Role::Read))
+            .map(|body: CollectionHandlerStruct| body.collection_name)
+            .recover(handle_auth_rejection);
+        let request = |path: &str, key: &str, collection_name: &str| {
+            warp::test::request().path(path).header("x-api-key", key).json(&json!({ "collection_name": collection_name }))
+        };
+
+        let response = request("/tenants/team-a/get_collection", "a-reader", "books").reply(&filter).await;
+        assert_eq!(response.body(), "team-a/books");
+        let response = request("/get_collection", "a-reader", "books").header("x-tenant", "team-a").reply(&filter).await;
+        assert_eq!(response.body(), "team-a/books");
+        let response = request("/get_collection", "operator", "books").reply(&filter).await;
+        assert_eq!(response.body(), "books");
+
+        let response = request("/get_collection", "a-reader", "books").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = request("/get_collection", "operator", "team-a/books").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = request("/tenants/team-b/get_collection", "a-reader", "books").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = request("/tenants/team-a/get_collection", "a-reader", "books").header("x-tenant", "team-b").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,469 @@
+use crate::mmap_store::MmapStore;
+use crate::model::{CacheDB, Collection, Embedding, Error};
+use crate::segment::{Segment, MIN_SEAL_LEN, SEGMENT_CAPACITY};
+use crate::sparse_index::SparseIndex;
+use crate::partition_index::PartitionIndex;
+use crate::readiness::RestoreProgress;
+use crate::text_index::TextIndex;
+use log::{error, info};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The number of embeddings copied between memory-mapped stores at a time.
+const COPY_BATCH: usize = 1024;
+
+/// Compact every collection whose deleted embeddings make up at least `threshold` of its positions,
+/// checking every `interval` on a background thread once the database is restored.
+pub fn spawn_compaction(db: Arc<Mutex<CacheDB>>, interval: Duration, threshold: f64, progress: Arc<RestoreProgress>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        // Compacting during the restore would copy collections that records still to replay keep changing.
+        if !progress.is_ready() {
+            continue;
+        }
+        let Ok(names) = db.lock().map(|db| due(&db, threshold)) else {
+            return;
+        };
+        for name in names {
+            if let Err(err) = compact(&db, &name) {
+                error!("Failed to compact collection '{}': {}", name, err);
+            }
+        }
+    });
+}
+
+/// The names of the collections due for compaction.
+fn due(db: &CacheDB, threshold: f64) -> Vec<String> {
+    db.collections
+        .iter()
+        .filter(|(_, collection)| {
+            !collection.tombstones.is_empty() && collection.tombstones.len() as f64 >= threshold * collection.position_count() as f64
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Rewrite the embeddings of a collection without the deleted ones.
+///
+/// The database is only locked to take a snapshot of the collection and to swap the compacted
+/// embeddings in, so searches and inserts carry on while the embeddings are copied and reindexed.
+/// If the collection is deleted, recreated or gets a text index meanwhile, the result is dropped
+/// and the next round tries again.
+pub fn compact(db: &Mutex<CacheDB>, name: &str) -> Result<(), Error> {
+    let start = Instant::now();
+    let compaction = {
+        let Ok(mut db) = db.lock() else {
+            return Ok(());
+        };
+        let Some(collection) = db.collections.get_mut(name) else {
+            return Ok(());
+        };
+        Compaction::start(collection)
+    };
+    if compaction.dead.is_empty() {
+        return Ok(());
+    }
+    let dropped = compaction.dead.len();
+    let compacted = compaction.run()?;
+
+    let Ok(mut db) = db.lock() else {
+        compacted.discard();
+        return Ok(());
+    };
+    let Some(collection) = db.collections.get_mut(name) else {
+        compacted.discard();
+        return Ok(());
+    };
+    if compacted.finish(collection)? {
+        info!("Compacted collection '{}', dropping {} deleted embeddings in {:?}", name, dropped, start.elapsed());
+    } else {
+        info!("Skipped compacting collection '{}', which changed during compaction", name);
+    }
+    Ok(())
+}
+
+/// The embeddings of a collection as they were when its compaction started.
+enum Embeddings {
+    Segments(Vec<Arc<Segment>>),
+    Store(MmapStore),
+}
+
+/// A compaction of the positions below `len`, which don't change while it runs.
+struct Compaction {
+    embeddings: Embeddings,
+    len: usize,
+    dead: HashSet<usize>,
+    text_field: Option<String>,
+    partition_key: Option<String>,
+    /// The partitions of the embeddings, whose embeddings are stored together once compacted.
+    partitions: PartitionIndex,
+}
+
+/// The compacted embeddings with their indexes, ready to be swapped into the collection.
+struct Compacted {
+    compaction: Compaction,
+    embeddings: Embeddings,
+    /// The new position of each live embedding below `compaction.len`.
+    positions: Vec<Option<usize>>,
+    live: usize,
+    text_index: Option<TextIndex>,
+    sparse_index: SparseIndex,
+    partition_index: PartitionIndex,
+}
+
+impl Compaction {
+    /// Take a snapshot of the immutable embeddings of the collection and their deleted positions.
+    ///
+    /// The mutable segment is sealed first if it holds at least `MIN_SEAL_LEN` embeddings, so that
+    /// compacting often doesn't pile up small segments. Otherwise it's left out of the compaction.
+    fn start(collection: &mut Collection) -> Self {
+        if collection.embeddings.len() >= MIN_SEAL_LEN {
+            collection.seal();
+        }
+        let len = collection.position_count() - collection.embeddings.len();
+        let embeddings = match &collection.store {
+            Some(store) => Embeddings::Store(store.clone()),
+            None => Embeddings::Segments(collection.sealed.clone()),
+        };
+        Self {
+            embeddings,
+            len,
+            dead: collection.tombstones.iter().copied().filter(|&position| position < len).collect(),
+            text_field: collection.text_index.as_ref().map(|text_index| text_index.field.clone()),
+            partition_key: collection.options.partition_key.clone(),
+            partitions: collection.partition_index.clone(),
+        }
+    }
+
+    /// The live positions below `len`, in the order they're compacted: grouped by partition, and
+    /// in insertion order within each partition.
+    fn live_positions(&self) -> Vec<usize> {
+        let live = |position: &usize| *position < self.len && !self.dead.contains(position);
+        if self.partition_key.is_none() {
+            return (0..self.len).filter(live).collect();
+        }
+        let mut grouped: Vec<usize> = self.partitions.groups().into_iter().flat_map(|positions| positions.iter().copied().filter(live)).collect();
+        // Every embedding of a partitioned collection has a partition, but none is dropped if one doesn't.
+        if grouped.len() < self.len - self.dead.len() {
+            let in_partition: HashSet<usize> = grouped.iter().copied().collect();
+            grouped.extend((0..self.len).filter(|position| live(position) && !in_partition.contains(position)));
+        }
+        grouped
+    }
+
+    /// Copy and reindex the live embeddings, without the database lock.
+    fn run(self) -> Result<Compacted, Error> {
+        let mut positions = vec![None; self.len];
+        let mut text_index = self.text_field.clone().map(TextIndex::new);
+        let mut sparse_index = SparseIndex::default();
+        let mut partition_index = PartitionIndex::default();
+        let partition_key = self.partition_key.as_deref();
+        let mut live = 0;
+        let mut index = |embedding: &Embedding| {
+            add_to_indexes(&mut text_index, &mut sparse_index, &mut partition_index, partition_key, live, embedding);
+            live += 1;
+            live - 1
+        };
+
+        let embeddings = match &self.embeddings {
+            Embeddings::Segments(segments) => {
+                let mut compacted = Vec::new();
+                let mut rows = Vec::with_capacity(SEGMENT_CAPACITY);
+                let all: Vec<&Embedding> = segments.iter().flat_map(|segment| segment.embeddings()).collect();
+                for position in self.live_positions() {
+                    let embedding = all[position];
+                    positions[position] = Some(index(embedding));
+                    rows.push(embedding.clone());
+                    if rows.len() == SEGMENT_CAPACITY {
+                        compacted.push(Arc::new(Segment::new(std::mem::take(&mut rows))));
+                    }
+                }
+                if !rows.is_empty() {
+                    compacted.push(Arc::new(Segment::new(rows)));
+                }
+                Embeddings::Segments(compacted)
+            }
+            Embeddings::Store(store) => {
+                let dir = next_dir(store);
+                let mut compacted = MmapStore::create(&dir, store.dimension()).map_err(|e| {
+                    error!("Failed to create compacted store in '{}': {}", dir.display(), e);
+                    Error::Storage
+                })?;
+                let live_positions = self.live_positions();
+                let copied = live_positions.chunks(COPY_BATCH).try_for_each(|chunk| {
+                    let rows = chunk.iter().map(|&position| store.embedding(position)).collect::<std::io::Result<Vec<_>>>()?;
+                    for (&position, embedding) in chunk.iter().zip(&rows) {
+                        positions[position] = Some(index(embedding));
+                    }
+                    compacted.append(&rows)
+                });
+                if let Err(e) = copied {
+                    error!("Failed to copy embeddings from '{}' to '{}': {}", store.dir().display(), dir.display(), e);
+                    remove_store(compacted);
+                    return Err(Error::Storage);
+                }
+                Embeddings::Store(compacted)
+            }
+        };
+
+        Ok(Compacted { compaction: self, embeddings, positions, live, text_index, sparse_index, partition_index })
+    }
+}
+
+impl Compacted {
+    /// Swap the compacted embeddings into the collection, carrying over the inserts and deletes
+    /// made while the compaction ran.
+    ///
+    /// # Returns
+    ///
+    /// A result telling whether the embeddings were swapped in, which they aren't if the
+    /// collection isn't the one the compaction started from or its text index changed.
+    fn finish(mut self, collection: &mut Collection) -> Result<bool, Error> {
+        let Compaction { embeddings, len, dead, text_field, .. } = &self.compaction;
+        let (unchanged, sealed) = match (embeddings, &collection.store) {
+            (Embeddings::Segments(segments), None) => {
+                let unchanged = collection.sealed.len() >= segments.len() && segments.iter().zip(&collection.sealed).all(|(a, b)| Arc::ptr_eq(a, b));
+                (unchanged, segments.len())
+            }
+            (Embeddings::Store(store), Some(current)) => (current.shares_files(store), 0),
+            _ => (false, 0),
+        };
+        if !unchanged || collection.text_index.as_ref().map(|text_index| &text_index.field) != text_field.as_ref() {
+            self.discard();
+            return Ok(false);
+        }
+
+        // Positions at or after `len` were added during compaction and only move down.
+        let (len, shift) = (*len, len - self.live);
+        let remap = |position: usize| if position < len { self.positions[position] } else { Some(position - shift) };
+        let tombstones = collection.tombstones
+            .iter()
+            .filter(|position| !dead.contains(position))
+            .filter_map(|&position| remap(position))
+            .collect();
+        let expiries = collection.expiries.remap(remap);
+        let usage = collection.usage.remap(remap);
+
+        match &mut self.embeddings {
+            Embeddings::Segments(compacted) => {
+                let added = collection.segments().into_iter().flat_map(|(start, embeddings)| (start..).zip(embeddings)).skip(len);
+                for (position, embedding) in added {
+                    add_to_indexes(
+                        &mut self.text_index,
+                        &mut self.sparse_index,
+                        &mut self.partition_index,
+                        self.compaction.partition_key.as_deref(),
+                        position - shift,
+                        embedding,
+                    );
+                }
+                let newer = collection.sealed.split_off(sealed);
+                collection.sealed = std::mem::take(compacted);
+                collection.sealed.extend(newer);
+            }
+            Embeddings::Store(compacted) => {
+                let current = collection.store.as_ref().map_or(0, MmapStore::len);
+                let added = (len..current).map(|position| collection.embedding_at(position)).collect::<Result<Vec<_>, _>>();
+                let appended = added.and_then(|added| {
+                    compacted.append(&added).map_err(|e| {
+                        error!("Failed to append embeddings to '{}': {}", compacted.dir().display(), e);
+                        Error::Storage
+                    })?;
+                    Ok(added)
+                });
+                let added = match appended {
+                    Ok(added) => added,
+                    Err(err) => {
+                        self.discard();
+                        return Err(err);
+                    }
+                };
+                for (position, embedding) in (len..).zip(&added) {
+                    add_to_indexes(
+                        &mut self.text_index,
+                        &mut self.sparse_index,
+                        &mut self.partition_index,
+                        self.compaction.partition_key.as_deref(),
+                        position - shift,
+                        embedding,
+                    );
+                }
+                let Embeddings::Store(compacted) = std::mem::replace(&mut self.embeddings, Embeddings::Segments(Vec::new())) else {
+                    unreachable!();
+                };
+                if let Some(old) = collection.store.replace(compacted) {
+                    remove_store(old);
+                }
+            }
+        }
+
+        collection.tombstones = tombstones;
+        collection.expiries = expiries;
+        collection.usage = usage;
+        collection.text_index = self.text_index;
+        collection.sparse_index = self.sparse_index;
+        collection.partition_index = self.partition_index;
+        Ok(true)
+    }
+
+    /// Drop the compacted embeddings, removing the files of a compacted store.
+    fn discard(self) {
+        if let Embeddings::Store(store) = self.embeddings {
+            remove_store(store);
+        }
+    }
+}
+
+fn add_to_indexes(
+    text_index: &mut Option<TextIndex>,
+    sparse_index: &mut SparseIndex,
+    partition_index: &mut PartitionIndex,
+    partition_key: Option<&str>,
+    position: usize,
+    embedding: &Embedding,
+) {
+    if let Some(text_index) = text_index.as_mut() {
+        text_index.add(position, embedding);
+    }
+    if let Some(sparse_vector) = &embedding.sparse_vector {
+        sparse_index.add(position, sparse_vector);
+    }
+    if let Some(value) = partition_key.and_then(|key| embedding.metadata.as_ref()?.get(key)) {
+        partition_index.add(position, value);
+    }
+}
+
+/// The directory for the compacted copy of a store, numbered after the directory of the store.
+fn next_dir(store: &MmapStore) -> PathBuf {
+    let dir = store.dir();
+    let generation = dir.extension().and_then(|extension| extension.to_str()?.parse::<u64>().ok()).unwrap_or(0);
+    dir.with_extension((generation + 1).to_string())
+}
+
+fn remove_store(store: MmapStore) {
+    let dir = store.dir().to_path_buf();
+    if let Err(err) = store.remove() {
+        error!("Failed to remove the files in '{}': {}", dir.display(), err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CollectionOptions, Distance, Fusion, SearchParams, Storage};
+    use std::collections::HashMap;
+
+    fn embedding(i: usize) -> Embedding {
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), i.to_string());
+        let mut metadata = HashMap::new();
+        metadata.insert("text".to_string(), format!("document {}", if i.is_multiple_of(3) { "fizz" } else { "plain" }));
+        Embedding { id, vector: vec![i as f32, 1.0], metadata: Some(metadata), ..Default::default() }
+    }
+
+    fn ids(range: impl Iterator<Item = usize>) -> Vec<HashMap<String, String>> {
+        range.map(|i| embedding(i).id).collect()
+    }
+
+    /// Two databases with a collection of the given storage, one to compact and one to compare it with.
+    fn databases(dir: &std::path::Path, storage: Storage) -> [CacheDB; 2] {
+        ["compacted", "reference"].map(|name| {
+            let mut db = CacheDB::new();
+            db.set_collections_dir(dir.join(name)).unwrap();
+            let options = CollectionOptions { storage, ..CollectionOptions::default() };
+            db.create_collection("test".to_string(), 2, Distance::Cosine, options).unwrap();
+            db.create_text_index("test", "text".to_string()).unwrap();
+            db.update_collection("test", (0..SEGMENT_CAPACITY + 100).map(embedding).collect()).unwrap();
+            db.delete_embeddings("test", ids((0..SEGMENT_CAPACITY + 100).step_by(2))).unwrap();
+            db
+        })
+    }
+
+    #[test]
+    fn test_compaction_keeps_changes_made_while_running() {
+        for storage in [Storage::Memory, Storage::Mmap] {
+            let dir = tempfile::tempdir().unwrap();
+            let [mut compacted, mut reference] = databases(dir.path(), storage);
+            assert_eq!(due(&compacted, 0.5), vec!["test".to_string()]);
+            assert!(due(&compacted, 0.6).is_empty());
+
+            let compaction = Compaction::start(compacted.collections.get_mut("test").unwrap());
+            let result = compaction.run().unwrap();
+
+            // Changes made before the compaction is swapped in land on both sides of its snapshot.
+            for db in [&mut compacted, &mut reference] {
+                db.update_collection("test", (SEGMENT_CAPACITY + 100..SEGMENT_CAPACITY + 200).map(embedding).collect()).unwrap();
+                db.delete_embeddings("test", ids([1, 7, SEGMENT_CAPACITY + 101].into_iter())).unwrap();
+                db.update_collection("test", vec![embedding(2)]).unwrap();
+            }
+            assert!(result.finish(compacted.collections.get_mut("test").unwrap()).unwrap());
+
+            let (collection, expected) = (&compacted.collections["test"], &reference.collections["test"]);
+            // The mutable segment of a memory collection is too small to seal, so its deleted embeddings stay.
+            let tombstones = if storage == Storage::Memory { 53 } else { 3 };
+            assert_eq!(collection.tombstones.len(), tombstones);
+            assert_eq!(collection.position_count(), collection.embedding_count() + tombstones);
+            assert_eq!(collection.embedding_count(), expected.embedding_count());
+            assert_eq!(collection.all_embeddings(), expected.all_embeddings());
+            for query in [0.0, 1.0, 5000.0] {
+                let params = SearchParams::default();
+                assert_eq!(collection.get_similarity(&[query, 1.0], 5, &params), expected.get_similarity(&[query, 1.0], 5, &params));
+            }
+            let fusion = Fusion::Rrf { rank_constant: 60.0 };
+            let (collection_results, expected_results) = (
+                collection.hybrid_search(None, Some("fizz"), 1000, fusion, &SearchParams::default()).unwrap(),
+                expected.hybrid_search(None, Some("fizz"), 1000, fusion, &SearchParams::default()).unwrap(),
+            );
+            let sorted_ids = |results: Vec<crate::model::SimilarityResult>| {
+                let mut ids: Vec<String> = results.into_iter().map(|result| result.embedding.id["unique_id"].clone()).collect();
+                ids.sort();
+                ids
+            };
+            assert_eq!(sorted_ids(collection_results), sorted_ids(expected_results));
+
+            if storage == Storage::Mmap {
+                let store_dir = collection.store.as_ref().unwrap().dir();
+                assert_eq!(store_dir, dir.path().join("compacted").join("test.1"));
+                assert!(!dir.path().join("compacted").join("test").exists());
+            }
+
+            // Compacting again drops the deleted embeddings of sealed segments, but leaves a small
+            // mutable segment as it is.
+            let db = Mutex::new(compacted);
+            compact(&db, "test").unwrap();
+            let mutable_tombstones = if storage == Storage::Memory { 51 } else { 0 };
+            assert_eq!(db.lock().unwrap().collections["test"].tombstones.len(), mutable_tombstones);
+
+            // Once the mutable segment grows, it's sealed and compacted too.
+            for db in [&mut *db.lock().unwrap(), &mut reference] {
+                db.update_collection("test", (SEGMENT_CAPACITY + 200..SEGMENT_CAPACITY + 200 + MIN_SEAL_LEN).map(embedding).collect()).unwrap();
+            }
+            compact(&db, "test").unwrap();
+            let (collection, expected) = (&db.lock().unwrap().collections["test"], &reference.collections["test"]);
+            assert!(collection.tombstones.is_empty());
+            assert_eq!(collection.position_count(), expected.embedding_count());
+            assert_eq!(collection.all_embeddings(), expected.all_embeddings());
+        }
+    }
+
+    #[test]
+    fn test_compaction_of_replaced_collection_is_dropped() {
+        for storage in [Storage::Memory, Storage::Mmap] {
+            let dir = tempfile::tempdir().unwrap();
+            let [mut db, _] = databases(dir.path(), storage);
+            let compaction = Compaction::start(db.collections.get_mut("test").unwrap());
+            let result = compaction.run().unwrap();
+
+            db.delete_collection("test").unwrap();
+            let options = CollectionOptions { storage, ..CollectionOptions::default() };
+            db.create_collection("test".to_string(), 2, Distance::Cosine, options).unwrap();
+            db.update_collection("test", vec![embedding(0)]).unwrap();
+            assert!(!result.finish(db.collections.get_mut("test").unwrap()).unwrap());
+
+            assert_eq!(db.collections["test"].all_embeddings(), Ok(vec![embedding(0)]));
+            assert!(!dir.path().join("compacted").join("test.1").exists());
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use crate::auth::AuthConfig;
+use crate::data_log::Durability;
+use crate::logging::LogFormat;
+use crate::model::{EvictionPolicy, Limits, Quota};
+use crate::replay_log::RestoreMode;
+use crate::tls::TlsConfig;
+use clap::{Args, Parser, Subcommand};
+use log::LevelFilter;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Parser, Debug)]
+#[command(name = "memvectordb", version, about = "An in-memory vector database", args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Running without a subcommand is the same as `serve`.
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Start the HTTP and gRPC servers.
+    Serve(ServeArgs),
+}
+
+impl Cli {
+    pub fn serve_args(self) -> ServeArgs {
+        match self.command {
+            Some(Command::Serve(args)) => args,
+            None => self.serve,
+        }
+    }
+}
+
+#[derive(Args, Debug, Default, Clone, PartialEq)]
+pub struct ServeArgs {
+    /// Path of a TOML configuration file.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on.
+    #[arg(long)]
+    pub host: Option<IpAddr>,
+    /// Port to listen on.
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Port to serve the gRPC API on.
+    #[arg(long)]
+    pub grpc_port: Option<u16>,
+    /// Directory holding the data log the database is restored from.
+    #[arg(long)]
+    pub data_dir: Option<PathBuf>,
+    /// Restore the database from the data log before serving.
+    #[arg(long, alias = "restore-db")]
+    pub restore: bool,
+    /// What to do with log entries that can't be restored: strict or lenient.
+    #[arg(long)]
+    pub restore_mode: Option<String>,
+    /// Logging level: off, error, warn, info, debug or trace.
+    #[arg(long)]
+    pub log_level: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read config file '{path}': {source}")]
+    Read { path: PathBuf, source: std::io::Error },
+    #[error("Invalid config file '{path}': {source}")]
+    Parse { path: PathBuf, source: toml::de::Error },
+    #[error("Invalid value '{value}' for environment variable {name}")]
+    InvalidEnv { name: &'static str, value: String },
+    #[error("Port must be between 1 and 65535")]
+    InvalidPort,
+    #[error("The gRPC port must be between 1 and 65535 and differ from the HTTP port")]
+    InvalidGrpcPort,
+    #[error("Invalid log level '{0}', expected off, error, warn, info, debug or trace")]
+    InvalidLogLevel(String),
+    #[error("Invalid log format '{0}', expected text or json")]
+    InvalidLogFormat(String),
+    #[error("Invalid restore mode '{0}', expected strict or lenient")]
+    InvalidRestoreMode(String),
+    #[error("Invalid durability '{0}', expected always, group_commit or buffered")]
+    InvalidDurability(String),
+    #[error("The group commit interval must be at least 1 ms")]
+    InvalidGroupCommitInterval,
+    #[error("The compaction interval must be at least 1 s")]
+    InvalidCompactionInterval,
+    #[error("The compaction threshold must be greater than 0 and at most 1")]
+    InvalidCompactionThreshold,
+    #[error("The expiry sweep interval must be at least 1 s")]
+    InvalidExpirySweepInterval,
+    #[error("Invalid eviction policy '{0}', expected reject_writes, lru, lfu or fifo")]
+    InvalidEvictionPolicy(String),
+    #[error("Invalid API keys: {0}")]
+    InvalidApiKeys(String),
+    #[error("Invalid tenant '{tenant}': {message}")]
+    InvalidTenant { tenant: String, message: String },
+    #[error("Invalid TLS settings: {0}")]
+    InvalidTls(String),
+    #[error("Failed to create data directory '{path}': {source}")]
+    DataDir { path: PathBuf, source: std::io::Error },
+}
+
+/// The settings a config file may contain, all optional.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    host: Option<IpAddr>,
+    port: Option<u16>,
+    grpc_port: Option<u16>,
+    data_dir: Option<PathBuf>,
+    log_file: Option<PathBuf>,
+    log_level: Option<String>,
+    log_format: Option<LogFormat>,
+    restore: Option<bool>,
+    restore_mode: Option<RestoreMode>,
+    durability: Option<Durability>,
+    group_commit_interval_ms: Option<u64>,
+    compaction_interval_secs: Option<u64>,
+    compaction_threshold: Option<f64>,
+    expiry_sweep_interval_secs: Option<u64>,
+    max_vectors: Option<usize>,
+    max_memory_bytes: Option<usize>,
+    eviction_policy: Option<EvictionPolicy>,
+    shutdown_timeout_secs: Option<u64>,
+    snapshot_on_shutdown: Option<bool>,
+    api_keys: Option<String>,
+    #[serde(default)]
+    tls: FileTlsConfig,
+    #[serde(default)]
+    tenants: HashMap<String, FileTenantConfig>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct FileTenantConfig {
+    api_keys: Option<String>,
+    max_collections: Option<usize>,
+    max_vectors: Option<usize>,
+    max_memory_bytes: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct FileTlsConfig {
+    cert_path: Option<String>,
+    key_path: Option<String>,
+    client_ca_path: Option<String>,
+}
+
+/// The validated server configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub addr: SocketAddr,
+    /// Where the gRPC API is served, on the same host as the HTTP API.
+    pub grpc_addr: SocketAddr,
+    pub data_dir: PathBuf,
+    /// A file the diagnostic log is written to besides stdout.
+    pub log_file: Option<PathBuf>,
+    pub log_level: LevelFilter,
+    pub log_format: LogFormat,
+    pub restore: bool,
+    pub restore_mode: RestoreMode,
+    pub durability: Durability,
+    /// How often changes are synced to disk with `Durability::GroupCommit`.
+    pub group_commit_interval: Duration,
+    /// How often collections are checked for deleted embeddings to compact away.
+    pub compaction_interval: Duration,
+    /// The share of a collection's embeddings that must be deleted before it is compacted.
+    pub compaction_threshold: f64,
+    /// How often expired embeddings are deleted.
+    pub expiry_sweep_interval: Duration,
+    /// The limits of every collection together, and the eviction policy of collections without their own.
+    pub limits: Limits,
+    /// How long in-flight requests may take to finish once shutdown starts.
+    pub shutdown_timeout: Duration,
+    /// Whether to replace the data log with a snapshot of the database before exiting.
+    pub snapshot_on_shutdown: bool,
+    pub auth: AuthConfig,
+    /// The quotas of each tenant, whose API keys are part of `auth`.
+    pub tenants: HashMap<String, Quota>,
+    pub tls: Option<TlsConfig>,
+}
+
+impl Config {
+    /// Build the configuration from the config file, environment variables and command line flags,
+    /// each overriding the previous ones, and create the data directory.
+    pub fn load(args: &ServeArgs) -> Result<Self, ConfigError> {
+        let file = match &args.config {
+            Some(path) => read_file(path)?,
+            None => FileConfig::default(),
+        };
+        let config = Self::resolve(file, |name| std::env::var(name).ok(), args)?;
+        std::fs::create_dir_all(&config.data_dir)
+            .map_err(|source| ConfigError::DataDir { path: config.data_dir.clone(), source })?;
+        Ok(config)
+    }
+
+    fn resolve(file: FileConfig, env: impl Fn(&str) -> Option<String>, args: &ServeArgs) -> Result<Self, ConfigError> {
+        let host = args.host.or(parse_env(&env, "MEMVECTORDB_HOST")?).or(file.host).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let port = args.port.or(parse_env(&env, "MEMVECTORDB_PORT")?).or(file.port).unwrap_or(8000);
+        if port == 0 {
+            return Err(ConfigError::InvalidPort);
+        }
+        let grpc_port = args.grpc_port.or(parse_env(&env, "MEMVECTORDB_GRPC_PORT")?).or(file.grpc_port).unwrap_or(50051);
+        if grpc_port == 0 || grpc_port == port {
+            return Err(ConfigError::InvalidGrpcPort);
+        }
+
+        let data_dir = args.data_dir.clone().or(env("MEMVECTORDB_DATA_DIR").map(PathBuf::from)).or(file.data_dir).unwrap_or_else(|| PathBuf::from("."));
+        let log_file = env("MEMVECTORDB_LOG_FILE").map(PathBuf::from).or(file.log_file);
+
+        let log_level = args.log_level.clone().or(env("MEMVECTORDB_LOG_LEVEL")).or(file.log_level).unwrap_or_else(|| "info".to_string());
+        let log_level = log_level.parse().map_err(|_| ConfigError::InvalidLogLevel(log_level))?;
+        let log_format = match env("MEMVECTORDB_LOG_FORMAT") {
+            Some(format) => format.parse().map_err(|_| ConfigError::InvalidLogFormat(format))?,
+            None => file.log_format.unwrap_or_default(),
+        };
+
+        // `RESTORE_DB` predates the config file and restores when set to anything but `false` or `0`.
+        let restore_env = env("RESTORE_DB").map(|value| !matches!(value.as_str(), "false" | "0"));
+        let restore = args.restore || restore_env.or(file.restore).unwrap_or(false);
+        let restore_mode = match args.restore_mode.clone().or(env("MEMVECTORDB_RESTORE_MODE")) {
+            Some(mode) => mode.parse().map_err(|_| ConfigError::InvalidRestoreMode(mode))?,
+            None => file.restore_mode.unwrap_or_default(),
+        };
+
+        let durability = match env("MEMVECTORDB_DURABILITY") {
+            Some(durability) => durability.parse().map_err(|_| ConfigError::InvalidDurability(durability))?,
+            None => file.durability.unwrap_or_default(),
+        };
+        let group_commit_interval = parse_env(&env, "MEMVECTORDB_GROUP_COMMIT_INTERVAL_MS")?.or(file.group_commit_interval_ms).unwrap_or(10);
+        if group_commit_interval == 0 {
+            return Err(ConfigError::InvalidGroupCommitInterval);
+        }
+
+        let compaction_interval = parse_env(&env, "MEMVECTORDB_COMPACTION_INTERVAL_SECS")?.or(file.compaction_interval_secs).unwrap_or(30);
+        if compaction_interval == 0 {
+            return Err(ConfigError::InvalidCompactionInterval);
+        }
+        let compaction_threshold = parse_env(&env, "MEMVECTORDB_COMPACTION_THRESHOLD")?.or(file.compaction_threshold).unwrap_or(0.2);
+        if !(compaction_threshold > 0.0 && compaction_threshold <= 1.0) {
+            return Err(ConfigError::InvalidCompactionThreshold);
+        }
+        let expiry_sweep_interval = parse_env(&env, "MEMVECTORDB_EXPIRY_SWEEP_INTERVAL_SECS")?.or(file.expiry_sweep_interval_secs).unwrap_or(1);
+        if expiry_sweep_interval == 0 {
+            return Err(ConfigError::InvalidExpirySweepInterval);
+        }
+
+        let eviction_policy = match env("MEMVECTORDB_EVICTION_POLICY") {
+            Some(policy) => Some(policy.parse().map_err(|_| ConfigError::InvalidEvictionPolicy(policy))?),
+            None => file.eviction_policy,
+        };
+        let limits = Limits {
+            max_vectors: parse_env(&env, "MEMVECTORDB_MAX_VECTORS")?.or(file.max_vectors),
+            max_memory_bytes: parse_env(&env, "MEMVECTORDB_MAX_MEMORY_BYTES")?.or(file.max_memory_bytes),
+            eviction_policy,
+        };
+
+        let shutdown_timeout = parse_env(&env, "MEMVECTORDB_SHUTDOWN_TIMEOUT_SECS")?.or(file.shutdown_timeout_secs).unwrap_or(30);
+        let snapshot_on_shutdown = parse_env(&env, "MEMVECTORDB_SNAPSHOT_ON_SHUTDOWN")?.or(file.snapshot_on_shutdown).unwrap_or(false);
+
+        let mut auth = match env("API_KEYS").or(file.api_keys) {
+            Some(keys) => AuthConfig::parse(&keys).map_err(ConfigError::InvalidApiKeys)?,
+            None => AuthConfig::default(),
+        };
+        // Tenants are only configured in the file, since each has several settings.
+        let mut tenants = HashMap::new();
+        for (tenant, config) in file.tenants {
+            auth.add_tenant(&tenant, config.api_keys.as_deref().unwrap_or_default())
+                .map_err(|message| ConfigError::InvalidTenant { tenant: tenant.clone(), message })?;
+            let quota = Quota { max_collections: config.max_collections, max_vectors: config.max_vectors, max_memory_bytes: config.max_memory_bytes };
+            tenants.insert(tenant, quota);
+        }
+
+        let tls = TlsConfig::from_paths(
+            env("TLS_CERT_PATH").or(file.tls.cert_path),
+            env("TLS_KEY_PATH").or(file.tls.key_path),
+            env("TLS_CLIENT_CA_PATH").or(file.tls.client_ca_path),
+        )
+        .map_err(ConfigError::InvalidTls)?;
+
+        Ok(Self {
+            addr: SocketAddr::new(host, port),
+            grpc_addr: SocketAddr::new(host, grpc_port),
+            data_dir,
+            log_file,
+            log_level,
+            log_format,
+            restore,
+            restore_mode,
+            durability,
+            group_commit_interval: Duration::from_millis(group_commit_interval),
+            compaction_interval: Duration::from_secs(compaction_interval),
+            compaction_threshold,
+            expiry_sweep_interval: Duration::from_secs(expiry_sweep_interval),
+            limits,
+            shutdown_timeout: Duration::from_secs(shutdown_timeout),
+            snapshot_on_shutdown,
+            auth,
+            tenants,
+            tls,
+        })
+    }
+
+    /// The data log the database is persisted to.
+    pub fn data_log_path(&self) -> PathBuf {
+        self.data_dir.join("data.log")
+    }
+
+    /// The file log entries that couldn't be restored are moved to in lenient restore mode.
+    pub fn quarantine_log_path(&self) -> PathBuf {
+        self.data_dir.join("quarantine.log")
+    }
+
+    /// The directory holding the files of memory-mapped collections.
+    pub fn collections_dir(&self) -> PathBuf {
+        self.data_dir.join("collections")
+    }
+
+    /// The log of earlier versions, which persisted the database in the diagnostic log.
+    pub fn legacy_log_path(&self) -> PathBuf {
+        self.data_dir.join("output.log")
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(env: impl Fn(&str) -> Option<String>, name: &'static str) -> Result<Option<T>, ConfigError> {
+    match env(name) {
+        Some(value) => value.parse().map(Some).map_err(|_| ConfigError::InvalidEnv { name, value }),
+        None => Ok(None),
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig, ConfigError> {
+    let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read { path: path.to_path_buf(), source })?;
+    toml::from_str(&content).map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn resolve(file: FileConfig, env: &[(&str, &str)], args: &ServeArgs) -> Result<Config, ConfigError> {
+        let env: HashMap<String, String> = env.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        Config::resolve(file, |name| env.get(name).cloned(), args)
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = resolve(FileConfig::default(), &[], &ServeArgs::default()).unwrap();
+        assert_eq!(config.addr, "0.0.0.0:8000".parse().unwrap());
+        assert_eq!(config.grpc_addr, "0.0.0.0:50051".parse().unwrap());
+        assert_eq!(config.data_log_path(), PathBuf::from("./data.log"));
+        assert_eq!(config.log_file, None);
+        assert_eq!(config.log_level, LevelFilter::Info);
+        assert_eq!(config.log_format, LogFormat::Text);
+        assert!(!config.restore);
+        assert_eq!(config.restore_mode, RestoreMode::Lenient);
+        assert_eq!(config.durability, Durability::Always);
+        assert_eq!(config.group_commit_interval, Duration::from_millis(10));
+        assert_eq!(config.compaction_interval, Duration::from_secs(30));
+        assert_eq!(config.compaction_threshold, 0.2);
+        assert_eq!(config.expiry_sweep_interval, Duration::from_secs(1));
+        assert_eq!(config.limits, Limits::default());
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(30));
+        assert!(!config.snapshot_on_shutdown);
+        assert!(!config.auth.is_enabled());
+        assert!(config.tenants.is_empty());
+        assert_eq!(config.tls, None);
+    }
+
+    #[test]
+    fn test_tenants() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "api_keys = \"operator=admin\"\n[tenants.team-a]\napi_keys = \"a-key=admin\"\nmax_collections = 5\nmax_vectors = 1000\n[tenants.team-b]").unwrap();
+        let config = resolve(read_file(file.path()).unwrap(), &[], &ServeArgs::default()).unwrap();
+        assert_eq!(config.tenants["team-a"], Quota { max_collections: Some(5), max_vectors: Some(1000), max_memory_bytes: None });
+        assert_eq!(config.tenants["team-b"], Quota::default());
+        assert!(config.auth.check_tenant(Some("team-b")).is_ok());
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "api_keys = \"shared=admin\"\n[tenants.team-a]\napi_keys = \"shared=read\"").unwrap();
+        let result = resolve(read_file(file.path()).unwrap(), &[], &ServeArgs::default());
+        assert!(matches!(result, Err(ConfigError::InvalidTenant { .. })));
+    }
+
+    #[test]
+    fn test_precedence() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "port = 9000\ngrpc_port = 9500\nhost = \"127.0.0.1\"\ndata_dir = \"/var/lib/memvectordb\"\nlog_level = \"debug\"\nlog_format = \"json\"\nrestore_mode = \"lenient\"\ndurability = \"buffered\"\nmax_vectors = 1000\neviction_policy = \"fifo\"\n[tls]\ncert_path = \"cert.pem\"\nkey_path = \"key.pem\"").unwrap();
+        let file = read_file(file.path()).unwrap();
+
+        let args = ServeArgs { port: Some(9100), restore: true, restore_mode: Some("strict".to_string()), ..ServeArgs::default() };
+        let env = [("MEMVECTORDB_PORT", "9001"), ("MEMVECTORDB_GRPC_PORT", "9501"), ("MEMVECTORDB_LOG_LEVEL", "warn"), ("MEMVECTORDB_SNAPSHOT_ON_SHUTDOWN", "true"), ("MEMVECTORDB_DURABILITY", "group_commit"), ("MEMVECTORDB_EVICTION_POLICY", "lru"), ("MEMVECTORDB_MAX_MEMORY_BYTES", "1048576")];
+        let config = resolve(file, &env, &args).unwrap();
+        assert_eq!(config.addr, "127.0.0.1:9100".parse().unwrap());
+        assert_eq!(config.grpc_addr, "127.0.0.1:9501".parse().unwrap());
+        assert_eq!(config.data_log_path(), PathBuf::from("/var/lib/memvectordb/data.log"));
+        assert_eq!(config.log_level, LevelFilter::Warn);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert!(config.restore);
+        assert_eq!(config.restore_mode, RestoreMode::Strict);
+        assert_eq!(config.durability, Durability::GroupCommit);
+        assert!(config.snapshot_on_shutdown);
+        assert_eq!(config.limits, Limits { max_vectors: Some(1000), max_memory_bytes: Some(1048576), eviction_policy: Some(EvictionPolicy::Lru) });
+        assert_eq!(config.tls.map(|tls| tls.cert_path), Some(PathBuf::from("cert.pem")));
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let args = ServeArgs::default();
+        let result = resolve(FileConfig::default(), &[("MEMVECTORDB_PORT", "http")], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidEnv { name: "MEMVECTORDB_PORT", .. })));
+        let result = resolve(FileConfig { port: Some(0), ..FileConfig::default() }, &[], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidPort)));
+        let result = resolve(FileConfig { grpc_port: Some(8000), ..FileConfig::default() }, &[], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidGrpcPort)));
+        let result = resolve(FileConfig::default(), &[("MEMVECTORDB_LOG_LEVEL", "loud")], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidLogLevel(_))));
+        let result = resolve(FileConfig::default(), &[("MEMVECTORDB_LOG_FORMAT", "xml")], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidLogFormat(_))));
+        let result = resolve(FileConfig::default(), &[("MEMVECTORDB_RESTORE_MODE", "careful")], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidRestoreMode(_))));
+        let result = resolve(FileConfig::default(), &[("MEMVECTORDB_DURABILITY", "eventually")], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidDurability(_))));
+        let result = resolve(FileConfig { group_commit_interval_ms: Some(0), ..FileConfig::default() }, &[], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidGroupCommitInterval)));
+        let result = resolve(FileConfig { compaction_interval_secs: Some(0), ..FileConfig::default() }, &[], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidCompactionInterval)));
+        let result = resolve(FileConfig::default(), &[("MEMVECTORDB_COMPACTION_THRESHOLD", "1.5")], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidCompactionThreshold)));
+        let result = resolve(FileConfig::default(), &[("MEMVECTORDB_EXPIRY_SWEEP_INTERVAL_SECS", "0")], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidExpirySweepInterval)));
+        let result = resolve(FileConfig::default(), &[("MEMVECTORDB_EVICTION_POLICY", "random")], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidEvictionPolicy(_))));
+        let result = resolve(FileConfig::default(), &[("TLS_CERT_PATH", "cert.pem")], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidTls(_))));
+        let result = resolve(FileConfig::default(), &[("API_KEYS", "key=root")], &args);
+        assert!(matches!(result, Err(ConfigError::InvalidApiKeys(_))));
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "prot = 9000").unwrap();
+        assert!(matches!(read_file(file.path()), Err(ConfigError::Parse { .. })));
+    }
+
+    #[test]
+    fn test_cli() {
+        let args = Cli::parse_from(["memvectordb", "serve", "--port", "9000", "--restore"]).serve_args();
+        assert_eq!(args.port, Some(9000));
+        assert!(args.restore);
+
+        let args = Cli::parse_from(["memvectordb", "--restore-db"]).serve_args();
+        assert!(args.restore);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::metrics::{DATA_LOG_BYTES, LAST_SNAPSHOT};
+use crate::model::{AliasAction, CacheDB, CollectionChanges, CollectionOptions, Distance, Embedding};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A change to the database, as recorded in the data log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum LogRecord {
+    CreateCollection {
+        name: String,
+        dimension: usize,
+        distance: Distance,
+        #[serde(default)]
+        options: CollectionOptions,
+    },
+    DeleteCollection {
+        name: String,
+    },
+    CreateTextIndex {
+        collection_name: String,
+        field: String,
+    },
+    Insert {
+        collection_name: String,
+        embeddings: Vec<Embedding>,
+        /// Whether the vectors are stored as they are in memory, already normalized for cosine distance.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        normalized: bool,
+    },
+    DeleteEmbeddings {
+        collection_name: String,
+        ids: Vec<HashMap<String, String>>,
+    },
+    UpdateAliases {
+        actions: Vec<AliasAction>,
+    },
+    RenameCollection {
+        name: String,
+        new_name: String,
+    },
+    CloneCollection {
+        source: String,
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filter: Option<HashMap<String, String>>,
+    },
+    AlterCollection {
+        name: String,
+        changes: CollectionChanges,
+    },
+}
+
+/// When changes appended to the data log are flushed to disk.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    /// Sync every change before it is acknowledged.
+    #[default]
+    Always,
+    /// Sync the changes of each interval together, losing at most the last interval on power loss.
+    GroupCommit,
+    /// Leave flushing to the operating system, losing its buffered changes on power loss.
+    Buffered,
+}
+
+impl std::str::FromStr for Durability {
+    type Err = String;
+
+    fn from_str(durability: &str) -> Result<Self, Self::Err> {
+        match durability {
+            "always" => Ok(Durability::Always),
+            "group_commit" => Ok(Durability::GroupCommit),
+            "buffered" => Ok(Durability::Buffered),
+            _ => Err(format!("Unknown durability '{}'", durability)),
+        }
+    }
+}
+
+/// The append-only file the database is restored from, holding one JSON `LogRecord` per line.
+///
+/// It is written by the storage layer only, independently of the diagnostic log and its level.
+#[derive(Debug)]
+pub struct DataLog {
+    path: PathBuf,
+    file: File,
+    durability: Durability,
+    /// Whether records were appended since the last sync.
+    unsynced: bool,
+}
+
+impl DataLog {
+    /// Open the data log at `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { path: path.to_path_buf(), file, durability: Durability::default(), unsynced: false })
+    }
+
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append records with a single write, so concurrent readers never see half a line and records
+    /// logged together are written together.
+    ///
+    /// With `Durability::Always` the records are on disk when this returns.
+    pub fn append(&mut self, records: &[LogRecord]) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut lines, record)?;
+            lines.push(b'\n');
+        }
+        self.file.write_all(&lines)?;
+        DATA_LOG_BYTES.inc_by(lines.len() as u64);
+        if self.durability == Durability::Always {
+            self.file.sync_data()?;
+        } else {
+            self.unsynced = true;
+        }
+        Ok(())
+    }
+
+    /// Flush the appended records to disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+
+    /// A handle to sync the records appended since the last call with, if there are any, so that
+    /// the sync can run without holding the database lock.
+    fn take_unsynced(&mut self) -> Option<io::Result<File>> {
+        if !std::mem::take(&mut self.unsynced) {
+            return None;
+        }
+        Some(self.file.try_clone())
+    }
+
+    /// Create the data log at `path` holding `records`, replacing any existing file.
+    pub fn create(path: &Path, records: &[LogRecord]) -> io::Result<Self> {
+        write_snapshot(path, records)?;
+        Self::open(path)
+    }
+
+    /// Replace the whole log with `records`, a snapshot of the current state.
+    pub fn rewrite(&mut self, records: &[LogRecord]) -> io::Result<()> {
+        write_snapshot(&self.path, records)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.unsynced = false;
+        Ok(())
+    }
+}
+
+/// Sync the data log of `db` every `interval` on a background thread, for `Durability::GroupCommit`.
+pub fn spawn_group_commit(db: Arc<Mutex<CacheDB>>, interval: Duration) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let Ok(mut db) = db.lock() else {
+            return;
+        };
+        let Some(data_log) = &mut db.data_log else {
+            continue;
+        };
+        let path = data_log.path().to_path_buf();
+        let unsynced = data_log.take_unsynced();
+        drop(db);
+
+        if let Some(Err(err)) = unsynced.map(|file| file.and_then(|file| file.sync_data())) {
+            error!("Failed to sync data log '{}': {}", path.display(), err);
+        }
+    });
+}
+
+/// Make the data log at `path` end with a complete record after a crash in the middle of an append.
+///
+/// A last line without its newline is cut off, unless it's a complete record missing only the newline.
+///
+/// # Returns
+///
+/// The number of bytes cut off.
+pub fn recover_torn_tail(path: &Path) -> io::Result<u64> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(0);
+    }
+    let mut last_byte = [0];
+    file.seek(SeekFrom::Start(len - 1))?;
+    file.read_exact(&mut last_byte)?;
+    if last_byte[0] == b'\n' {
+        return Ok(0);
+    }
+
+    // Look for the end of the last complete line, reading backwards.
+    let mut tail_start = 0;
+    let mut end = len;
+    let mut chunk = Vec::new();
+    while end > 0 {
+        let start = end.saturating_sub(64 * 1024);
+        chunk.resize((end - start) as usize, 0);
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        if let Some(newline) = chunk.iter().rposition(|byte| *byte == b'\n') {
+            tail_start = start + newline as u64 + 1;
+            break;
+        }
+        end = start;
+    }
+
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(tail_start))?;
+    file.read_to_end(&mut tail)?;
+    if serde_json::from_slice::<LogRecord>(&tail).is_ok() {
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+        return Ok(0);
+    }
+    file.set_len(tail_start)?;
+    file.sync_all()?;
+    Ok(len - tail_start)
+}
+
+/// Write `records` as the whole content of the log at `path`.
+///
+/// The snapshot is written to a temporary file next to the log and renamed over it once synced,
+/// so a crash leaves either the old log or the complete snapshot.
+fn write_snapshot(path: &Path, records: &[LogRecord]) -> io::Result<()> {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    let temp_path = path.with_file_name(file_name);
+
+    let mut temp_file = File::create(&temp_path)?;
+    let mut written = 0;
+    for record in records {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        temp_file.write_all(&line)?;
+        written += line.len();
+    }
+    temp_file.sync_all()?;
+    std::fs::rename(&temp_path, path)?;
+    sync_parent_dir(path)?;
+
+    DATA_LOG_BYTES.inc_by(written as u64);
+    LAST_SNAPSHOT.set(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |time| time.as_secs_f64()));
+    Ok(())
+}
+
+/// Sync the directory holding `path`, so that a rename into it survives a crash.
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// A line of the data log that couldn't be parsed as a record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnreadableLine {
+    pub text: String,
+    pub error: String,
+}
+
+/// A record read from the data log with its line number, or the unreadable line in its place.
+pub type ReadRecord = (usize, Result<LogRecord, UnreadableLine>);
+
+/// Read the records of the data log at `path` one at a time, so that replaying a log larger than
+/// memory only holds one record at once.
+///
+/// # Returns
+///
+/// The records in the order they were written with their line numbers, and the unreadable lines
+/// in place of the records they should have been.
+pub fn read_records(path: &Path) -> io::Result<impl Iterator<Item = io::Result<ReadRecord>>> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(reader.lines().enumerate().filter_map(|(number, line)| {
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => return None,
+            Ok(line) => line,
+            Err(err) => return Some(Err(err)),
+        };
+        let record = serde_json::from_str(&line).map_err(|err| UnreadableLine { error: err.to_string(), text: line });
+        Some(Ok((number + 1, record)))
+    }))
+}
+
+/// The number of records `read_records` reads from the data log at `path`, counted without parsing them.
+pub fn count_records(path: &Path) -> io::Result<usize> {
+    let mut count = 0;
+    for line in BufReader::new(File::open(path)?).lines() {
+        if !line?.trim().is_empty() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_append_and_read_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.log");
+
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), "0".to_string());
+        let records = vec![
+            LogRecord::CreateCollection { name: "test_collection".to_string(), dimension: 3, distance: Distance::Cosine, options: CollectionOptions::default() },
+            LogRecord::Insert {
+                collection_name: "test_collection".to_string(),
+                embeddings: vec![Embedding { id, vector: vec![1.0, 0.0, 0.0], ..Default::default() }],
+                normalized: false,
+            },
+            LogRecord::DeleteCollection { name: "test_collection".to_string() },
+        ];
+
+        let mut data_log = DataLog::open(&path).unwrap();
+        for record in &records {
+            data_log.append(std::slice::from_ref(record)).unwrap();
+        }
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"op\":\"insert\"\n").unwrap();
+
+        assert_eq!(count_records(&path).unwrap(), 4);
+        let read = read_records(&path).unwrap().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(read.len(), 4);
+        for (record, (_, read)) in records.iter().zip(&read) {
+            assert_eq!(read.as_ref().ok(), Some(record));
+        }
+        assert!(matches!(&read[3], (4, Err(line)) if line.text == "{\"op\":\"insert\""));
+
+        data_log.rewrite(&records[..1]).unwrap();
+        data_log.append(&records[2..3]).unwrap();
+        data_log.sync().unwrap();
+        let read: Vec<LogRecord> = read_records(&path).unwrap().map(|read| read.unwrap().1.unwrap()).collect();
+        assert_eq!(read, vec![records[0].clone(), records[2].clone()]);
+        assert!(!dir.path().join("data.log.tmp").exists());
+    }
+
+    #[test]
+    fn test_recover_torn_tail_at_every_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.log");
+
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), "0".to_string());
+        let records = vec![
+            LogRecord::CreateCollection { name: "test_collection".to_string(), dimension: 2, distance: Distance::DotProduct, options: CollectionOptions::default() },
+            LogRecord::Insert {
+                collection_name: "test_collection".to_string(),
+                embeddings: vec![Embedding { id, vector: vec![0.25, 12.5], ..Default::default() }],
+                normalized: false,
+            },
+            LogRecord::CreateTextIndex { collection_name: "test_collection".to_string(), field: "text".to_string() },
+        ];
+        let mut content = Vec::new();
+        let mut record_ends = Vec::new();
+        for record in &records {
+            content.extend(serde_json::to_vec(record).unwrap());
+            record_ends.push(content.len());
+            content.push(b'\n');
+        }
+
+        let appended = LogRecord::DeleteCollection { name: "test_collection".to_string() };
+        for offset in 0..=content.len() {
+            std::fs::write(&path, &content[..offset]).unwrap();
+            recover_torn_tail(&path).unwrap();
+
+            // Every record written in full survives, and appends start on a line of their own.
+            let complete = record_ends.iter().filter(|end| **end <= offset).count();
+            DataLog::open(&path).unwrap().with_durability(Durability::Buffered).append(std::slice::from_ref(&appended)).unwrap();
+            let read: Vec<LogRecord> = read_records(&path).unwrap().map(|read| read.unwrap().1.unwrap()).collect();
+            let mut expected = records[..complete].to_vec();
+            expected.push(appended.clone());
+            assert_eq!(read, expected, "truncated at byte {}", offset);
+        }
+    }
+}
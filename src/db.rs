@@ -1,101 +1,798 @@
+use rayon::iter::Either;
 use rayon::prelude::*;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 use std::collections::hash_map::DefaultHasher;
-use crate::similarity::{get_cache_attr, get_distance_fn, normalize, ScoreIndex};
-use crate::model::{CacheDB, SimilarityResult, Collection, Embedding, Distance, Error};
+use crate::similarity::{fuse, get_cache_attr, get_distance_fn, is_distance, max_sim, normalize, top_k, ScoreIndex};
+use crate::sparse_index::SparseIndex;
+use crate::partition_index::{self, PartitionIndex};
+use crate::text_index::TextIndex;
+use crate::data_log::{DataLog, LogRecord};
+use crate::mmap_store::{collection_dir_name, MmapStore};
+use crate::segment::{Segment, SEGMENT_CAPACITY};
+use crate::id_index::IdIndex;
+use crate::expiry::{unix_now, Expiries};
+use crate::eviction::{evict, eviction_records, plan_evictions, Usage};
+use crate::semantic_cache::CacheStats;
+use crate::tenant::tenant_of;
+use crate::model::{AliasAction, CacheDB, SimilarityResult, Collection, CollectionChanges, CollectionOptions, Embedding, Distance, Error, EvictionPolicy, Limits, Quota, Fusion, SearchParams, SparseVector, Storage, VectorParams, VectorType};
+use std::path::PathBuf;
+use std::sync::Arc;
 use log::{debug, error, info};
-use std::sync::Once;
-
-static INIT: Once = Once::new();
-
-fn setup_logger() -> Result<(), fern::InitError> {
-    INIT.call_once(|| {
-        let _ = fern::Dispatch::new()
-            .format(|out, message, record| {
-                out.finish(format_args!(
-                    "{} [{}] {}",
-                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                    record.level(),
-                    message
-                ))
-            })
-            .level(log::LevelFilter::Info)
-            .chain(std::io::stdout())
-            .chain(fern::log_file("output.log").unwrap())
-            .apply();
-    });
-    Ok(())
-}
 
+/// Delete the files of a collection's store, which are removed on the next start if this fails.
+fn remove_store(name: &str, store: MmapStore) {
+    let dir = store.dir().to_path_buf();
+    if let Err(err) = store.remove() {
+        error!("Failed to remove the files of collection '{}' in '{}': {}", name, dir.display(), err);
+    }
+}
 
 // Define a function to hash a HashMap<String, String>.
-// A custom hash function, you ensure that the hash value is based solely on the content of the HashMap
+// A custom hash function, you ensure that the hash value is based solely on the content of the HashMap.
+// Entries are hashed in key order, since equal maps may iterate in different orders.
 pub fn hash_map_id(id: &HashMap<String, String>) -> u64 {
+    let mut entries: Vec<_> = id.iter().collect();
+    entries.sort_unstable();
     let mut hasher = DefaultHasher::new();
-    for (key, value) in id {
+    for (key, value) in entries {
         key.hash(&mut hasher);
         value.hash(&mut hasher);
     }
     hasher.finish()
 }
 
+impl Embedding {
+    /// The default vector, or the named vector if `name` is given. A missing named vector is empty.
+    pub fn dense_vector(&self, name: Option<&str>) -> &[f32] {
+        match name {
+            Some(name) => self.vectors.get(name).map_or(&[], Vec::as_slice),
+            None => &self.vector,
+        }
+    }
+
+    /// An estimate of the heap and inline memory the embedding takes, counting its vectors, ids and metadata.
+    pub fn estimated_memory_bytes(&self) -> usize {
+        let floats = self.vector.len()
+            + self.multivector.iter().map(Vec::len).sum::<usize>()
+            + self.vectors.values().map(Vec::len).sum::<usize>();
+        let sparse = self.sparse_vector.as_ref().map_or(0, |sparse_vector| sparse_vector.indices.len() + sparse_vector.values.len());
+        let strings = |map: &HashMap<String, String>| map.iter().map(|(key, value)| key.len() + value.len()).sum::<usize>();
+        let names: usize = self.vectors.keys().map(String::len).sum();
+        std::mem::size_of::<Embedding>()
+            + (floats + sparse) * std::mem::size_of::<f32>()
+            + strings(&self.id)
+            + self.metadata.as_ref().map_or(0, strings)
+            + names
+    }
+}
+
 /// A collection that stores embeddings and handles similarity calculations.
 impl Collection {
+    /// Create an empty collection with the given dimension and distance metric.
+    pub fn new(dimension: usize, distance: Distance) -> Self {
+        Self {
+            dimension,
+            distance,
+            embeddings: Vec::new(),
+            sealed: Vec::new(),
+            tombstones: HashSet::new(),
+            expiries: Expiries::default(),
+            usage: Usage::default(),
+            live_bytes: 0,
+            cache_stats: CacheStats::default(),
+            text_index: None,
+            options: CollectionOptions::default(),
+            sparse_index: SparseIndex::default(),
+            partition_index: PartitionIndex::default(),
+            store: None,
+        }
+    }
+
+    /// Check that an embedding carries the kind and size of vector the collection expects, and the value of its
+    /// partition key if it has one.
+    fn check_vector(&self, collection_name: &str, embedding: &Embedding) -> Result<(), Error> {
+        match self.options.vector_type {
+            VectorType::Dense => {
+                if embedding.sparse_vector.is_some() || !embedding.multivector.is_empty() {
+                    error!("Sparse vector or multivector given for dense collection '{}'", collection_name);
+                    return Err(Error::VectorTypeMismatch);
+                }
+                if embedding.vector.len() != self.dimension {
+                    error!(
+                        "Dimension mismatch: embedding vector length is '{}' but collection '{}' expects dimension '{}'",
+                        embedding.vector.len(),
+                        collection_name,
+                        self.dimension
+                    );
+                    return Err(Error::DimensionMismatch);
+                }
+            }
+            VectorType::Sparse => {
+                let sparse_vector = match &embedding.sparse_vector {
+                    Some(sparse_vector) if embedding.vector.is_empty() && embedding.multivector.is_empty() => sparse_vector,
+                    _ => {
+                        error!("Sparse collection '{}' expects only a sparse vector", collection_name);
+                        return Err(Error::VectorTypeMismatch);
+                    }
+                };
+                let unique_indices: HashSet<u32> = sparse_vector.indices.iter().copied().collect();
+                if sparse_vector.indices.len() != sparse_vector.values.len() || unique_indices.len() != sparse_vector.indices.len() {
+                    error!("Invalid sparse vector for collection '{}'", collection_name);
+                    return Err(Error::InvalidSparseVector);
+                }
+                if sparse_vector.indices.iter().any(|&index| index as usize >= self.dimension) {
+                    error!(
+                        "Dimension mismatch: sparse vector index out of range for collection '{}' with dimension '{}'",
+                        collection_name,
+                        self.dimension
+                    );
+                    return Err(Error::DimensionMismatch);
+                }
+            }
+            VectorType::Multi => {
+                if embedding.multivector.is_empty() || !embedding.vector.is_empty() || embedding.sparse_vector.is_some() {
+                    error!("Multivector collection '{}' expects only a non-empty multivector", collection_name);
+                    return Err(Error::VectorTypeMismatch);
+                }
+                if let Some(token) = embedding.multivector.iter().find(|token| token.len() != self.dimension) {
+                    error!(
+                        "Dimension mismatch: multivector token length is '{}' but collection '{}' expects dimension '{}'",
+                        token.len(),
+                        collection_name,
+                        self.dimension
+                    );
+                    return Err(Error::DimensionMismatch);
+                }
+            }
+        }
+
+        // Named vectors are dense whatever the vector type, and each one declared by the collection is required.
+        if let Some(name) = embedding.vectors.keys().find(|name| !self.options.vectors.contains_key(*name)) {
+            error!("Named vector '{}' is not declared by collection '{}'", name, collection_name);
+            return Err(Error::VectorNotFound);
+        }
+        for (name, vector_params) in &self.options.vectors {
+            let length = embedding.vectors.get(name).map_or(0, Vec::len);
+            if length != vector_params.dimension {
+                error!(
+                    "Dimension mismatch: named vector '{}' length is '{}' but collection '{}' expects dimension '{}'",
+                    name,
+                    length,
+                    collection_name,
+                    vector_params.dimension
+                );
+                return Err(Error::DimensionMismatch);
+            }
+        }
+
+        if let Some(key) = &self.options.partition_key {
+            if self.partition_value(embedding).is_none() {
+                error!("Embedding doesn't set the partition key '{}' of collection '{}'", key, collection_name);
+                return Err(Error::MissingPartitionKey);
+            }
+        }
+        Ok(())
+    }
+
+    /// The value of the partition key in the metadata of an embedding, if the collection has one.
+    fn partition_value<'a>(&self, embedding: &'a Embedding) -> Option<&'a str> {
+        let key = self.options.partition_key.as_ref()?;
+        embedding.metadata.as_ref()?.get(key).map(String::as_str)
+    }
+
+    /// The positions of the embeddings in the partition a search is restricted to, if any.
+    fn partition_positions(&self, partition: Option<&str>) -> Result<Option<&[usize]>, Error> {
+        match partition {
+            Some(_) if self.options.partition_key.is_none() => {
+                error!("Partition search requested on a collection without a partition key");
+                Err(Error::NotPartitioned)
+            }
+            Some(value) => Ok(Some(self.partition_index.positions(value))),
+            None => Ok(None),
+        }
+    }
+
+    /// Normalize the default vector, multivector tokens and named vectors whose distance is cosine.
+    fn normalize_vectors(&self, embedding: &mut Embedding) {
+        if self.distance == Distance::Cosine {
+            embedding.vector = normalize(&embedding.vector);
+            for token in embedding.multivector.iter_mut() {
+                *token = normalize(token);
+            }
+        }
+        for (name, vector) in embedding.vectors.iter_mut() {
+            if self.options.vectors.get(name).is_some_and(|vector_params| vector_params.distance == Distance::Cosine) {
+                *vector = normalize(vector);
+            }
+        }
+    }
+
+    /// Resolve the dimension and distance of the dense vector a search targets.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The named vector to search, or `None` for the default vector, which only dense collections have.
+    ///
+    /// # Returns
+    ///
+    /// A result containing the vector's parameters, or an error if the collection has no such vector.
+    pub fn dense_vector_params(&self, name: Option<&str>) -> Result<VectorParams, Error> {
+        match name {
+            None if self.options.vector_type != VectorType::Dense => {
+                error!("Dense search requested on a {:?} collection without naming a vector", self.options.vector_type);
+                Err(Error::VectorTypeMismatch)
+            }
+            None => Ok(VectorParams { dimension: self.dimension, distance: self.distance }),
+            Some(name) => self.options.vectors.get(name).copied().ok_or_else(|| {
+                error!("Named vector '{}' not found", name);
+                Error::VectorNotFound
+            }),
+        }
+    }
+
+    /// An estimate of the memory the collection's embeddings take, leaving out index overhead.
+    ///
+    /// The files of a memory-mapped collection aren't counted, since the OS pages them in and out.
+    pub fn estimated_memory_bytes(&self) -> usize {
+        let stored = self.store.as_ref().map_or(0, MmapStore::estimated_memory_bytes);
+        let sealed: usize = self.sealed.iter().map(|segment| segment.estimated_memory_bytes()).sum();
+        stored + sealed + self.embeddings.iter().map(Embedding::estimated_memory_bytes).sum::<usize>()
+    }
+
+    /// The number of positions in the collection, counting deleted embeddings until compaction drops them.
+    pub fn position_count(&self) -> usize {
+        match &self.store {
+            Some(store) => store.len(),
+            None => self.sealed.iter().map(|segment| segment.len()).sum::<usize>() + self.embeddings.len(),
+        }
+    }
+
+    /// The number of embeddings in the collection, whether in memory or memory-mapped.
+    pub fn embedding_count(&self) -> usize {
+        self.position_count() - self.tombstones.len() - self.expiries.expired(unix_now()).len()
+    }
+
+    /// The number of embeddings that aren't deleted, counting expired ones until they're swept, as limits do.
+    pub fn stored_count(&self) -> usize {
+        self.position_count() - self.tombstones.len()
+    }
+
+    /// Whether the embedding at `index` hasn't been deleted or expired.
+    pub fn is_live(&self, index: usize) -> bool {
+        !self.is_deleted(index) && !self.expiries.is_expired(index)
+    }
+
+    /// Whether the embedding at `index` has been deleted. Expired embeddings count as deleted only
+    /// once the sweeper deletes them, so that deletes logged before they expired replay the same.
+    fn is_deleted(&self, index: usize) -> bool {
+        self.tombstones.contains(&index)
+    }
+
+    /// Mark the embeddings at `positions` as deleted.
+    pub fn delete_positions(&mut self, positions: impl IntoIterator<Item = usize>) -> Result<(), Error> {
+        for position in positions {
+            self.live_bytes -= self.embedding_at(position)?.estimated_memory_bytes();
+            self.expiries.remove(position);
+            self.usage.remove(position);
+            self.tombstones.insert(position);
+        }
+        Ok(())
+    }
+
+    /// Track the usage of every embedding that isn't deleted for `policy`, as if they were just inserted.
+    fn track_usage(&mut self, policy: EvictionPolicy) {
+        let mut usage = Usage::new(policy);
+        for position in (0..self.position_count()).filter(|&position| !self.is_deleted(position)) {
+            usage.insert(position);
+        }
+        self.usage = usage;
+    }
+
+    /// Set when an embedding expires from its own TTL or the collection's, unless it is set already.
+    fn set_expiry(&self, embedding: &mut Embedding, now: u64) {
+        if embedding.expires_at.is_none() {
+            embedding.expires_at = embedding.ttl_secs.or(self.options.ttl_secs).map(|ttl_secs| now.saturating_add(ttl_secs));
+        }
+        embedding.ttl_secs = None;
+    }
+
+    /// The in-memory segments with the position of their first embedding, oldest first and the mutable one last.
+    pub fn segments(&self) -> Vec<(usize, &[Embedding])> {
+        let mut start = 0;
+        let mut segments = Vec::with_capacity(self.sealed.len() + 1);
+        for embeddings in self.sealed.iter().map(|segment| segment.embeddings()).chain([self.embeddings.as_slice()]) {
+            segments.push((start, embeddings));
+            start += embeddings.len();
+        }
+        segments
+    }
+
+    /// Seal the mutable segment, even if it isn't full yet.
+    pub fn seal(&mut self) {
+        if !self.embeddings.is_empty() {
+            self.sealed.push(Arc::new(Segment::new(std::mem::take(&mut self.embeddings))));
+        }
+    }
+
+    /// Append embeddings to the collection, keeping the text, sparse and partition indexes up to date.
+    fn push_embeddings(&mut self, embeddings: Vec<Embedding>) -> Result<(), Error> {
+        let first_index = self.position_count();
+        if let Some(store) = self.store.as_mut() {
+            store.append(&embeddings).map_err(|e| {
+                error!("Failed to append embeddings to '{}': {}", store.dir().display(), e);
+                Error::Storage
+            })?;
+        }
+        for (index, embedding) in (first_index..).zip(&embeddings) {
+            if let Some(text_index) = self.text_index.as_mut() {
+                text_index.add(index, embedding);
+            }
+            if let Some(sparse_vector) = &embedding.sparse_vector {
+                self.sparse_index.add(index, sparse_vector);
+            }
+            if let Some(value) = self.partition_value(embedding) {
+                self.partition_index.add(index, value);
+            }
+            if let Some(expires_at) = embedding.expires_at {
+                self.expiries.insert(index, expires_at);
+            }
+            self.usage.insert(index);
+            self.live_bytes += embedding.estimated_memory_bytes();
+        }
+        if self.store.is_none() {
+            self.embeddings.extend(embeddings);
+            while self.embeddings.len() >= SEGMENT_CAPACITY {
+                let rest = self.embeddings.split_off(SEGMENT_CAPACITY);
+                let full = std::mem::replace(&mut self.embeddings, rest);
+                self.sealed.push(Arc::new(Segment::new(full)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The positions of the embeddings in the mutable segment that aren't deleted, by id.
+    fn active_ids(&self) -> IdIndex {
+        let start = self.position_count() - self.embeddings.len();
+        let mut active_ids = IdIndex::default();
+        for (row, embedding) in self.embeddings.iter().enumerate().filter(|(row, _)| !self.is_deleted(start + row)) {
+            active_ids.insert(&embedding.id, start + row);
+        }
+        active_ids
+    }
+
+    /// The position in `active_ids` of the embedding with the given id.
+    fn active_position(&self, active_ids: &IdIndex, id: &HashMap<String, String>) -> Option<usize> {
+        active_ids.position(id, |index| self.memory_embedding(index).id == *id)
+    }
+
+    /// The position of the embedding that isn't deleted with the given id in a sealed segment or the
+    /// memory-mapped store, whose ids are indexed unlike those of the mutable segment.
+    fn sealed_position(&self, id: &HashMap<String, String>) -> Option<usize> {
+        if let Some(store) = &self.store {
+            return store.position(id).filter(|&index| !self.is_deleted(index));
+        }
+        let mut start = 0;
+        for segment in &self.sealed {
+            if let Some(row) = segment.position(id).filter(|row| !self.is_deleted(start + row)) {
+                return Some(start + row);
+            }
+            start += segment.len();
+        }
+        None
+    }
+
+    /// The position of the embedding with the given id that isn't deleted, though it may have expired.
+    fn position(&self, id: &HashMap<String, String>) -> Option<usize> {
+        self.sealed_position(id).or_else(|| {
+            let start = self.position_count() - self.embeddings.len();
+            self.embeddings.iter()
+                .enumerate()
+                .find(|(row, embedding)| !self.is_deleted(start + row) && embedding.id == *id)
+                .map(|(row, _)| start + row)
+        })
+    }
+
+    /// The in-memory embedding at `index`.
+    fn memory_embedding(&self, mut index: usize) -> &Embedding {
+        for segment in &self.sealed {
+            match segment.embeddings().get(index) {
+                Some(embedding) => return embedding,
+                None => index -= segment.len(),
+            }
+        }
+        &self.embeddings[index]
+    }
+
+    /// The default or named vector of the embedding at `index`. Memory-mapped collections only have the default one.
+    fn dense_vector_at(&self, index: usize, using: Option<&str>) -> &[f32] {
+        match &self.store {
+            Some(store) => store.vector(index),
+            None => self.memory_embedding(index).dense_vector(using),
+        }
+    }
+
+    /// A copy of the embedding at `index`.
+    pub fn embedding_at(&self, index: usize) -> Result<Embedding, Error> {
+        match &self.store {
+            Some(store) => store.embedding(index).map_err(|e| {
+                error!("Failed to read embedding from '{}': {}", store.dir().display(), e);
+                Error::Storage
+            }),
+            None => Ok(self.memory_embedding(index).clone()),
+        }
+    }
+
+    /// Copies of every live embedding of the collection, in insertion order.
+    pub fn all_embeddings(&self) -> Result<Vec<Embedding>, Error> {
+        self.embeddings_where(|index| self.is_live(index))
+    }
+
+    /// Copies of every embedding that isn't deleted, in insertion order, including those that expired
+    /// but aren't swept yet.
+    ///
+    /// Collections rebuilt from these keep the embeddings the sweeper deletes later, so that its
+    /// deletes replay the same whenever the rebuild is replayed.
+    pub fn stored_embeddings(&self) -> Result<Vec<Embedding>, Error> {
+        self.embeddings_where(|index| !self.is_deleted(index))
+    }
+
+    fn embeddings_where(&self, keep: impl Fn(usize) -> bool) -> Result<Vec<Embedding>, Error> {
+        match &self.store {
+            Some(store) => (0..store.len())
+                .filter(|&index| keep(index))
+                .map(|index| self.embedding_at(index))
+                .collect(),
+            None => Ok(self.segments()
+                .into_iter()
+                .flat_map(|(start, embeddings)| (start..).zip(embeddings))
+                .filter(|(index, _)| keep(*index))
+                .map(|(_, embedding)| embedding.clone())
+                .collect()),
+        }
+    }
+
+    /// A copy of the collection with every live embedding in its mutable segment, as it is shown to clients.
+    pub fn flattened(&self) -> Result<Collection, Error> {
+        Ok(Collection {
+            embeddings: self.all_embeddings()?,
+            text_index: self.text_index.clone(),
+            options: self.options.clone(),
+            ..Collection::new(self.dimension, self.distance)
+        })
+    }
+
+    /// Map the live in-memory embeddings with their positions in parallel, keeping the results `f` returns.
+    ///
+    /// Given the positions of a partition, only those are visited, segment by segment, so that each
+    /// is read straight from its segment.
+    fn scan<T, F>(&self, partition: Option<&[usize]>, f: F) -> Vec<T>
+    where
+        T: Send,
+        F: Fn(usize, &Embedding) -> Option<T> + Sync,
+    {
+        self.segments()
+            .into_par_iter()
+            .flat_map(|(start, embeddings)| {
+                let indexes = match partition {
+                    Some(positions) => {
+                        let rows = positions.partition_point(|&index| index < start)..positions.partition_point(|&index| index < start + embeddings.len());
+                        Either::Left(positions[rows].par_iter().copied())
+                    }
+                    None => Either::Right((start..start + embeddings.len()).into_par_iter()),
+                };
+                indexes.map(move |index| (index, &embeddings[index - start]))
+            })
+            .filter(|(index, _)| self.is_live(*index))
+            .filter_map(|(index, embedding)| f(index, embedding))
+            .collect()
+    }
+
     /// Calculate similarity results for a given query and number of results (k).
     ///
     /// # Arguments
     ///
     /// * `query`: The query vector for which to calculate similarity.
     /// * `k`: The number of top similar results to return.
+    /// * `params`: The offset, score threshold and field selection to apply to the results.
+    ///
+    /// # Returns
+    ///
+    /// A result containing the similarity results, best first, or an error if the searched vector doesn't exist or
+    /// its dimension doesn't match the query. Results are ranked by descending similarity for cosine and dot
+    /// product, and by ascending distance for euclidean.
+    pub fn get_similarity(&self, query: &[f32], k: usize, params: &SearchParams) -> Result<Vec<SimilarityResult>, Error> {
+        self.get_similarity_where(query, k, params, |_| true)
+    }
+
+    /// Calculate dot product similarity results for a sparse query vector.
+    ///
+    /// # Arguments
+    ///
+    /// * `query`: The sparse query vector for which to calculate similarity.
+    /// * `k`: The number of top similar results to return.
+    /// * `params`: The offset, score threshold and field selection to apply to the results.
     ///
     /// # Returns
     ///
-    /// A vector of similarity results, sorted by their similarity scores.
-    pub fn get_similarity(&self, query: &[f32], k: usize) -> Vec<SimilarityResult> {
+    /// A result containing the similarity results sharing at least one dimension with the query, best first,
+    /// or an error if the collection isn't sparse.
+    pub fn get_sparse_similarity(&self, query: &SparseVector, k: usize, params: &SearchParams) -> Result<Vec<SimilarityResult>, Error> {
+        if self.options.vector_type != VectorType::Sparse {
+            error!("Sparse search requested on a collection without sparse vectors");
+            return Err(Error::VectorTypeMismatch);
+        }
+
+        let partition = self.partition_positions(params.partition.as_deref())?;
+        let include = |index| self.is_live(index) && partition_index::contains(partition, index);
+        let result: Vec<SimilarityResult> = self.sparse_index.search(query, params.offset + k, params.score_threshold, include)
+            .into_iter()
+            .skip(params.offset)
+            .map(|ScoreIndex { score, index }| self.similarity_result(score, index, params))
+            .collect::<Result<_, _>>()?;
+        debug!("Sparse similarity computed {} results", result.len());
+        Ok(result)
+    }
+
+    /// Calculate MaxSim similarity results for a multivector query, as used for late interaction retrieval.
+    ///
+    /// # Arguments
+    ///
+    /// * `query`: The query token vectors for which to calculate similarity.
+    /// * `k`: The number of top similar results to return.
+    /// * `params`: The offset, score threshold and field selection to apply to the results.
+    ///
+    /// # Returns
+    ///
+    /// A result containing the similarity results, best first, or an error if the collection doesn't hold
+    /// multivectors or a query token doesn't match its dimension.
+    pub fn get_multi_similarity(&self, query: &[Vec<f32>], k: usize, params: &SearchParams) -> Result<Vec<SimilarityResult>, Error> {
+        if self.options.vector_type != VectorType::Multi {
+            error!("Multivector search requested on a collection without multivectors");
+            return Err(Error::VectorTypeMismatch);
+        }
+        if query.is_empty() || query.iter().any(|token| token.len() != self.dimension) {
+            error!("Dimension mismatch: multivector query tokens must have dimension '{}'", self.dimension);
+            return Err(Error::DimensionMismatch);
+        }
+
+        // Document tokens are normalized for cosine, so query tokens must be too.
+        let query: Vec<Vec<f32>> = if self.distance == Distance::Cosine {
+            query.iter().map(|token| normalize(token)).collect()
+        } else {
+            query.to_vec()
+        };
+
+        let partition = self.partition_positions(params.partition.as_deref())?;
+        let scores = self.scan(partition, |index, embedding| {
+            Some(ScoreIndex { score: max_sim(&query, &embedding.multivector), index })
+                .filter(|score_index| params.score_threshold.is_none_or(|threshold| score_index.score >= threshold))
+        });
+
+        let result: Vec<SimilarityResult> = top_k(scores, params.offset + k)
+            .into_iter()
+            .skip(params.offset)
+            .map(|ScoreIndex { score, index }| self.similarity_result(score, index, params))
+            .collect::<Result<_, _>>()?;
+        debug!("Multivector similarity computed {} results", result.len());
+        Ok(result)
+    }
+
+    /// Calculate similarity results for a query over the embeddings whose positions `include` accepts.
+    fn get_similarity_where<F>(&self, query: &[f32], k: usize, params: &SearchParams, include: F) -> Result<Vec<SimilarityResult>, Error>
+    where
+        F: Fn(usize) -> bool + Sync,
+    {
+        let using = params.using.as_deref();
+
+        // Distances are negated in the ranking so that a higher score is always better.
+        let sign = if is_distance(self.dense_vector_params(using)?.distance) { -1.0 } else { 1.0 };
+        let partition = self.partition_positions(params.partition.as_deref())?;
+
+        let result: Vec<SimilarityResult> = self.rank_where(query, using, partition, params.offset + k, params.score_threshold, include)?
+            .into_iter()
+            .skip(params.offset)
+            .map(|ScoreIndex { score, index }| self.similarity_result(sign * score, index, params))
+            .collect::<Result<_, _>>()?;
+        debug!("Similarity computed {} results", result.len());
+        Ok(result)
+    }
 
-        debug!("Starting similarity computation with query vector of length {} and top k = {}", query.len(), k);
+    /// Rank the embeddings whose positions `include` accepts against a query for the default or a named vector.
+    ///
+    /// Given the positions of a partition, only those are scored rather than the whole collection.
+    ///
+    /// # Returns
+    ///
+    /// A result containing up to `limit` embeddings, best first, with distances negated so that higher scores are
+    /// always better, or an error if the vector doesn't exist or its dimension doesn't match the query.
+    fn rank_where<F>(
+        &self,
+        query: &[f32],
+        using: Option<&str>,
+        partition: Option<&[usize]>,
+        limit: usize,
+        score_threshold: Option<f32>,
+        include: F,
+    ) -> Result<Vec<ScoreIndex>, Error>
+    where
+        F: Fn(usize) -> bool + Sync,
+    {
+        let VectorParams { dimension, distance } = self.dense_vector_params(using)?;
+        if query.len() != dimension {
+            error!("Dimension mismatch: query vector length is '{}' but the searched vector has dimension '{}'", query.len(), dimension);
+            return Err(Error::DimensionMismatch);
+        }
+
+        debug!("Starting similarity computation with query vector of length {} and top k = {}", query.len(), limit);
+
+        // Stored vectors are normalized for cosine, so the query must be too for scores to be true cosine similarities.
+        let query = if distance == Distance::Cosine { normalize(query) } else { query.to_vec() };
 
-        // Prepare cache attributes and distance function based on collection's distance metric.
-        let memo_attr = get_cache_attr(self.distance, query);
-        let distance_fn = get_distance_fn(self.distance);
+        // Prepare cache attributes and distance function based on the searched vector's distance metric.
+        let memo_attr = get_cache_attr(distance, &query);
+        let distance_fn = get_distance_fn(distance);
 
-        debug!("Using distance function: {:?}", self.distance);
+        debug!("Using distance function: {:?}", distance);
         debug!("Memo attributes for distance function: {:?}", memo_attr);
 
-        // Calculate similarity scores for each embedding in parallel.
-        let scores = self.embeddings.par_iter()
-            .enumerate()
-            .map(|(index, embedding)| {
-                let score = distance_fn(&embedding.vector, query, memo_attr);
-                ScoreIndex { score, index }
-            })
-            .collect::<Vec<_>>();
+        let sign = if is_distance(distance) { -1.0 } else { 1.0 };
+        let threshold = score_threshold.map(|threshold| sign * threshold);
+
+        // Calculate similarity scores for each live embedding in parallel, dropping those below the threshold.
+        let score = |index: usize, vector: &[f32]| {
+            Some(ScoreIndex { score: sign * distance_fn(&query, vector, memo_attr), index })
+                .filter(|score_index| threshold.is_none_or(|threshold| score_index.score >= threshold))
+        };
+        let scores = match (partition, &self.store) {
+            (Some(positions), Some(store)) => positions.par_iter()
+                .copied()
+                .filter(|&index| self.is_live(index) && include(index))
+                .filter_map(|index| score(index, store.vector(index)))
+                .collect::<Vec<_>>(),
+            (None, Some(store)) => (0..store.len()).into_par_iter()
+                .filter(|&index| self.is_live(index) && include(index))
+                .filter_map(|index| score(index, store.vector(index)))
+                .collect::<Vec<_>>(),
+            (partition, None) => self.scan(partition, |index, embedding| include(index).then(|| score(index, embedding.dense_vector(using))).flatten()),
+        };
         debug!("Calculated {} similarity scores", scores.len());
-        // Use a binary heap to efficiently find the top k similarity results.
-        let mut heap = BinaryHeap::new();
-        for score_index in scores {
-            // Only keep top k results in the heap.
-            if heap.len() < k || score_index < *heap.peek().unwrap() {
-                heap.push(score_index);
-                if heap.len() > k {
-                    heap.pop();
-                }
-            }
+
+        let top = top_k(scores, limit);
+        debug!("Top k heap size: {}", top.len());
+
+        Ok(top)
+    }
+
+    /// Build the result for the embedding at `index`, keeping only the fields selected in `params`.
+    fn similarity_result(&self, score: f32, index: usize, params: &SearchParams) -> Result<SimilarityResult, Error> {
+        self.usage.touch(index);
+        let mut embedding = self.embedding_at(index)?;
+        if !params.with_vector {
+            embedding.vector.clear();
+            embedding.sparse_vector = None;
+            embedding.vectors.clear();
+            embedding.multivector.clear();
+        }
+        if !params.with_metadata {
+            embedding.metadata = None;
         }
-        debug!("Top k heap size: {}", heap.len());
+        Ok(SimilarityResult { score, embedding })
+    }
 
-        // Convert the heap into a sorted vector and map each score to a SimilarityResult.
-        let result: Vec<SimilarityResult> = heap.into_sorted_vec()
+    /// Search the collection with a query vector, a BM25 text query, or both fused into one ranking.
+    ///
+    /// Each ranking contributes its top `offset + k` candidates to the fusion. The score threshold in
+    /// `params` applies to the fused score.
+    ///
+    /// # Arguments
+    ///
+    /// * `query_vector`: The query vector for the similarity ranking, if any.
+    /// * `query_text`: The free-text query for the BM25 ranking, if any.
+    /// * `k`: The number of top results to return.
+    /// * `fusion`: How to combine the two rankings.
+    /// * `params`: The offset, score threshold and field selection to apply to the results.
+    ///
+    /// # Returns
+    ///
+    /// A result containing the fused results, best first, or an error if neither query was given or the
+    /// collection has no text index for a text query.
+    pub fn hybrid_search(
+        &self,
+        query_vector: Option<&[f32]>,
+        query_text: Option<&str>,
+        k: usize,
+        fusion: Fusion,
+        params: &SearchParams,
+    ) -> Result<Vec<SimilarityResult>, Error> {
+        if query_vector.is_none() && query_text.is_none() {
+            error!("Hybrid search requested without a query vector or query text");
+            return Err(Error::EmptyQuery);
+        }
+
+        let limit = params.offset + k;
+        let partition = self.partition_positions(params.partition.as_deref())?;
+        let vector_ranking = query_vector
+            .map(|query| self.rank_where(query, params.using.as_deref(), partition, limit, None, |_| true))
+            .transpose()?
+            .unwrap_or_default();
+        let text_ranking = match query_text {
+            Some(query) => {
+                let text_index = self.text_index.as_ref().ok_or(Error::TextIndexNotFound)?;
+                text_index.search(query, limit, |index| self.is_live(index) && partition_index::contains(partition, index))
+            }
+            None => Vec::new(),
+        };
+        debug!("Fusing {} vector and {} text candidates with {:?}", vector_ranking.len(), text_ranking.len(), fusion);
+
+        let result: Vec<SimilarityResult> = fuse(fusion, &vector_ranking, &text_ranking)
             .into_iter()
-            .map(|ScoreIndex { score, index }| SimilarityResult {
-                score,
-                embedding: self.embeddings[index].clone(),
-            })
-            .collect();
-        info!("Similarity computed successfully'{}' ", format!("{:?}", result));
-        result
+            .filter(|score_index| params.score_threshold.is_none_or(|threshold| score_index.score >= threshold))
+            .skip(params.offset)
+            .take(k)
+            .map(|ScoreIndex { score, index }| self.similarity_result(score, index, params))
+            .collect::<Result<_, _>>()?;
+        debug!("Hybrid search computed {} results", result.len());
+        Ok(result)
+    }
+
+    /// Find the neighbours of embeddings already stored in the collection.
+    ///
+    /// The query is built from the stored vectors as `2 * mean(positive) - mean(negative)`, which moves it
+    /// towards the positive examples and away from the negative ones. With no negative ids it is simply the
+    /// mean of the positive vectors. The input ids themselves are never returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `positive`: The ids of the embeddings to find similar items for.
+    /// * `negative`: The ids of the embeddings results should be dissimilar to.
+    /// * `k`: The number of top similar results to return.
+    /// * `params`: The offset, score threshold and field selection to apply to the results.
+    ///
+    /// # Returns
+    ///
+    /// A result containing the similarity results, or an error if no positive id was given or an id doesn't exist.
+    pub fn recommend(
+        &self,
+        positive: &[HashMap<String, String>],
+        negative: &[HashMap<String, String>],
+        k: usize,
+        params: &SearchParams,
+    ) -> Result<Vec<SimilarityResult>, Error> {
+        if positive.is_empty() {
+            error!("Recommendation requested without any positive embedding id");
+            return Err(Error::NoPositiveIds);
+        }
+        let using = params.using.as_deref();
+        let positive_mean = self.mean_vector(positive, using)?;
+        let query = if negative.is_empty() {
+            positive_mean
+        } else {
+            let negative_mean = self.mean_vector(negative, using)?;
+            positive_mean.iter()
+                .zip(&negative_mean)
+                .map(|(p, n)| 2.0f32.mul_add(*p, -n))
+                .collect()
+        };
+
+        let excluded: HashSet<usize> = positive.iter().chain(negative).filter_map(|id| self.position(id)).collect();
+        self.get_similarity_where(&query, k, params, |index| !excluded.contains(&index))
+    }
+
+    /// Average the stored default or named vectors of the embeddings with the given ids.
+    fn mean_vector(&self, ids: &[HashMap<String, String>], using: Option<&str>) -> Result<Vec<f32>, Error> {
+        let mut mean = vec![0.0; self.dense_vector_params(using)?.dimension];
+        for id in ids {
+            let index = self.position(id).filter(|&index| self.is_live(index)).ok_or_else(|| {
+                error!("Embedding with ID '{:?}' not found", id);
+                Error::EmbeddingNotFound
+            })?;
+            for (m, v) in mean.iter_mut().zip(self.dense_vector_at(index, using)) {
+                *m += v / ids.len() as f32;
+            }
+        }
+        Ok(mean)
     }
 }
 
@@ -105,8 +802,162 @@ impl CacheDB {
     pub fn new() -> Self {
         Self {
             collections: HashMap::new(),
+            aliases: HashMap::new(),
+            data_log: None,
+            collections_dir: None,
+            limits: Limits::default(),
+            quotas: HashMap::new(),
+        }
+    }
+
+    /// Set the quotas of each tenant, which apply to writes from now on.
+    pub fn set_quotas(&mut self, quotas: HashMap<String, Quota>) {
+        self.quotas = quotas;
+    }
+
+    /// Check that the tenant of a new collection named `name`, if any, has room for it.
+    fn check_collection_quota(&self, name: &str) -> Result<(), Error> {
+        let Some(tenant) = tenant_of(name) else {
+            return Ok(());
+        };
+        let Some(max_collections) = self.quotas.get(tenant).and_then(|quota| quota.max_collections) else {
+            return Ok(());
+        };
+        if self.collections.keys().filter(|name| tenant_of(name) == Some(tenant)).count() >= max_collections {
+            error!("Collection '{}' rejected for going over the collection quota of tenant '{}'", name, tenant);
+            return Err(Error::LimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Limit the embeddings of every collection together, as well as the policy of collections without one.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        let policies: Vec<(String, EvictionPolicy)> = self.collections
+            .iter()
+            .map(|(name, collection)| (name.clone(), self.eviction_policy(&collection.options)))
+            .collect();
+        for (name, policy) in policies {
+            if let Some(collection) = self.collections.get_mut(&name).filter(|collection| collection.usage.policy() != policy) {
+                collection.track_usage(policy);
+            }
+        }
+    }
+
+    /// The eviction policy of a collection with the given options, which follows the database's if it has none.
+    fn eviction_policy(&self, options: &CollectionOptions) -> EvictionPolicy {
+        options.limits.eviction_policy.or(self.limits.eviction_policy).unwrap_or_default()
+    }
+
+    /// Keep the files of memory-mapped collections under `dir`, removing those an earlier run left
+    /// there since they're rebuilt from the data log.
+    pub fn set_collections_dir(&mut self, dir: PathBuf) -> std::io::Result<()> {
+        match std::fs::remove_dir_all(&dir) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        std::fs::create_dir_all(&dir)?;
+        self.collections_dir = Some(dir);
+        Ok(())
+    }
+
+    /// Record every later change in `data_log`, which the database can be restored from.
+    pub fn set_data_log(&mut self, data_log: DataLog) {
+        self.data_log = Some(data_log);
+    }
+
+    /// Append a change to the data log, if there is one, before it is applied.
+    fn log_change(&mut self, record: &LogRecord) -> Result<(), Error> {
+        self.log_changes(std::slice::from_ref(record))
+    }
+
+    /// Append changes to the data log in a single write, so that they're logged together.
+    fn log_changes(&mut self, records: &[LogRecord]) -> Result<(), Error> {
+        let Some(data_log) = &mut self.data_log else {
+            return Ok(());
+        };
+        data_log.append(records).map_err(|e| {
+            error!("Failed to append to data log '{}': {}", data_log.path().display(), e);
+            Error::DataLog
+        })
+    }
+
+    /// Apply a change read back from the data log.
+    pub fn apply(&mut self, record: LogRecord) -> Result<(), Error> {
+        match record {
+            LogRecord::CreateCollection { name, dimension, distance, options } => {
+                self.add_collection(name, dimension, distance, options).map(|_| ())
+            }
+            LogRecord::DeleteCollection { name } => self.delete_collection(&name),
+            LogRecord::CreateTextIndex { collection_name, field } => self.create_text_index(&collection_name, field),
+            LogRecord::Insert { collection_name, embeddings, normalized } => self.add_embeddings(&collection_name, embeddings, normalized, false, false),
+            LogRecord::DeleteEmbeddings { collection_name, ids } => self.delete_embeddings(&collection_name, ids),
+            LogRecord::UpdateAliases { actions } => self.update_aliases(actions),
+            LogRecord::RenameCollection { name, new_name } => self.rename_collection(&name, new_name),
+            LogRecord::CloneCollection { source, name, filter } => self.copy_collection(&source, name, filter),
+            LogRecord::AlterCollection { name, changes } => self.alter_collection(&name, changes),
         }
     }
+
+    /// The records that recreate the current state of the database, in collection name order.
+    pub fn snapshot_records(&self) -> Result<Vec<LogRecord>, Error> {
+        let mut names: Vec<&String> = self.collections.keys().collect();
+        names.sort();
+
+        let mut records = Vec::new();
+        for name in names {
+            let collection = &self.collections[name];
+            records.push(LogRecord::CreateCollection {
+                name: name.clone(),
+                dimension: collection.dimension,
+                distance: collection.distance,
+                options: collection.options.clone(),
+            });
+            let embeddings = collection.stored_embeddings()?;
+            if !embeddings.is_empty() {
+                records.push(LogRecord::Insert { collection_name: name.clone(), embeddings, normalized: true });
+            }
+            if let Some(text_index) = &collection.text_index {
+                records.push(LogRecord::CreateTextIndex { collection_name: name.clone(), field: text_index.field.clone() });
+            }
+        }
+
+        let mut aliases: Vec<(&String, &String)> = self.aliases.iter().collect();
+        aliases.sort();
+        if !aliases.is_empty() {
+            let actions = aliases
+                .into_iter()
+                .map(|(alias, collection_name)| AliasAction::CreateAlias { alias: alias.clone(), collection_name: collection_name.clone() })
+                .collect();
+            records.push(LogRecord::UpdateAliases { actions });
+        }
+        Ok(records)
+    }
+
+    /// Replace the data log with a snapshot of the current state, dropping the history of deleted data.
+    pub fn snapshot(&mut self) -> Result<(), Error> {
+        let records = self.snapshot_records()?;
+        let Some(data_log) = &mut self.data_log else {
+            return Ok(());
+        };
+        data_log.rewrite(&records).map_err(|e| {
+            error!("Failed to write snapshot to data log '{}': {}", data_log.path().display(), e);
+            Error::DataLog
+        })?;
+        info!("Wrote snapshot of {} collections to the data log", self.collections.len());
+        Ok(())
+    }
+
+    /// Flush the data log to disk, as done before exiting.
+    pub fn sync_data_log(&self) -> Result<(), Error> {
+        let Some(data_log) = &self.data_log else {
+            return Ok(());
+        };
+        data_log.sync().map_err(|e| {
+            error!("Failed to sync data log '{}': {}", data_log.path().display(), e);
+            Error::DataLog
+        })
+    }
     /// Create a new collection in the database.
     ///
     /// # Arguments
@@ -114,33 +965,62 @@ impl CacheDB {
     /// * `name`: The name of the collection to create.
     /// * `dimension`: The dimension of the embeddings in the collection.
     /// * `distance`: The distance metric to use for similarity calculations.
+    /// * `options`: The optional settings of the collection, such as its vector type.
     ///
     /// # Returns
     ///
-    /// A result containing the new collection or an error if a collection with the same name already exists.
+    /// A result containing the new collection or an error if a collection with the same name already exists,
+    /// the distance isn't supported for the vector type, the storage isn't supported for the collection, or
+    /// its tenant has as many collections as its quota allows.
     pub fn create_collection(
         &mut self,
         name: String,
         dimension: usize,
         distance: Distance,
+        options: CollectionOptions,
+    ) -> Result<Collection, Error> {
+        self.check_collection_quota(&name)?;
+        self.add_collection(name, dimension, distance, options)
+    }
+
+    /// Create a collection without checking the quota of its tenant, as replayed collections aren't.
+    fn add_collection(
+        &mut self,
+        name: String,
+        dimension: usize,
+        distance: Distance,
+        options: CollectionOptions,
     ) -> Result<Collection, Error> {
 
-        if let Err(e) = setup_logger() {
-            error!("Logger setup failed: {:?}", e);
-            return Err(Error::LoggerInitializationError);
+        // Check if a collection or alias with the same name already exists.
+        self.check_new_name(&name)?;
+
+        // Sparse vectors are only scored by dot product, and MaxSim needs a similarity rather than a distance.
+        let supported = match options.vector_type {
+            VectorType::Dense => true,
+            VectorType::Sparse => distance == Distance::DotProduct,
+            VectorType::Multi => !is_distance(distance),
+        };
+        if !supported {
+            error!("{:?} collection: '{}', requested with unsupported distance '{:?}'", options.vector_type, name, distance);
+            return Err(Error::UnsupportedDistance);
         }
 
-        // Check if a collection with the same name already exists.
-        if self.collections.contains_key(&name) {
-            error!("Collection: '{}', already exists", name);
-            return Err(Error::UniqueViolation);
+        let store = self.create_store(&name, dimension, &options)?;
+
+        if let Err(err) = self.log_change(&LogRecord::CreateCollection { name: name.clone(), dimension, distance, options: options.clone() }) {
+            if let Some(store) = store {
+                remove_store(&name, store);
+            }
+            return Err(err);
         }
 
         // Create a new collection and add it to the database.
         let collection = Collection {
-            dimension,
-            distance,
-            embeddings: Vec::new(),
+            usage: Usage::new(self.eviction_policy(&options)),
+            options,
+            store,
+            ..Collection::new(dimension, distance)
         };
         self.collections.insert(name.clone(), collection.clone());
 
@@ -148,32 +1028,318 @@ impl CacheDB {
         Ok(collection)
     }
 
-    /// Delete a collection from the database.
+    /// Create the files of a collection if its options ask for memory-mapped storage.
+    fn create_store(&self, name: &str, dimension: usize, options: &CollectionOptions) -> Result<Option<MmapStore>, Error> {
+        // Memory-mapped segments hold a single dense vector per embedding.
+        match options.storage {
+            Storage::Memory => Ok(None),
+            Storage::Mmap => {
+                let supported = options.vector_type == VectorType::Dense && options.vectors.is_empty();
+                let Some(collections_dir) = self.collections_dir.as_ref().filter(|_| supported) else {
+                    error!("Memory-mapped storage requested for unsupported collection: '{}'", name);
+                    return Err(Error::UnsupportedStorage);
+                };
+                let dir = collections_dir.join(collection_dir_name(name));
+                let store = MmapStore::create(&dir, dimension).map_err(|e| {
+                    error!("Failed to create the files of collection '{}' in '{}': {}", name, dir.display(), e);
+                    Error::Storage
+                })?;
+                Ok(Some(store))
+            }
+        }
+    }
+
+    /// Build a collection holding `embeddings`, which are normalized already, with a text index over
+    /// `text_field` if one is given.
+    fn build_collection(
+        &self,
+        name: &str,
+        source: &Collection,
+        options: CollectionOptions,
+        text_field: Option<String>,
+        embeddings: Vec<Embedding>,
+    ) -> Result<Collection, Error> {
+        let store = self.create_store(name, source.dimension, &options)?;
+        let mut collection = Collection {
+            usage: Usage::new(self.eviction_policy(&options)),
+            options,
+            store,
+            text_index: text_field.map(TextIndex::new),
+            ..Collection::new(source.dimension, source.distance)
+        };
+        if let Err(err) = collection.push_embeddings(embeddings) {
+            if let Some(store) = collection.store {
+                remove_store(name, store);
+            }
+            return Err(err);
+        }
+        Ok(collection)
+    }
+
+    /// Check that `name` is free for a new collection.
+    fn check_new_name(&self, name: &str) -> Result<(), Error> {
+        if self.collections.contains_key(name) {
+            error!("Collection: '{}', already exists", name);
+            return Err(Error::UniqueViolation);
+        }
+        if self.aliases.contains_key(name) {
+            error!("Collection: '{}', has the name of an alias", name);
+            return Err(Error::AliasConflict);
+        }
+        Ok(())
+    }
+
+    /// Rename a collection, keeping its aliases pointed at it.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name or alias of the collection to rename.
+    /// * `new_name`: The new name of the collection.
+    ///
+    /// # Returns
+    ///
+    /// A result indicating success or an error if the collection was not found, the new name is taken by a
+    /// collection or an alias, or the files of the collection can't be moved.
+    pub fn rename_collection(&mut self, name: &str, new_name: String) -> Result<(), Error> {
+        let name = &self.resolve(name).to_string();
+        if !self.collections.contains_key(name) {
+            error!("Collection name: '{}', does not exist", name);
+            return Err(Error::NotFound);
+        }
+        self.check_new_name(&new_name)?;
+
+        // The files of a memory-mapped collection are named after it, so they move before the change is
+        // logged and move back if logging fails.
+        let old_dir = self.collections[name].store.as_ref().map(|store| store.dir().to_path_buf());
+        if let (Some(collections_dir), Some(store)) = (&self.collections_dir, self.collections.get_mut(name).and_then(|c| c.store.as_mut())) {
+            let dir = collections_dir.join(collection_dir_name(&new_name));
+            store.relocate(&dir).map_err(|e| {
+                error!("Failed to move the files of collection '{}' to '{}': {}", name, dir.display(), e);
+                Error::Storage
+            })?;
+        }
+        if let Err(err) = self.log_change(&LogRecord::RenameCollection { name: name.clone(), new_name: new_name.clone() }) {
+            if let (Some(old_dir), Some(store)) = (old_dir, self.collections.get_mut(name).and_then(|c| c.store.as_mut())) {
+                if let Err(e) = store.relocate(&old_dir) {
+                    error!("Failed to move the files of collection '{}' back to '{}': {}", name, old_dir.display(), e);
+                }
+            }
+            return Err(err);
+        }
+
+        let Some(collection) = self.collections.remove(name) else {
+            return Err(Error::NotFound);
+        };
+        self.collections.insert(new_name.clone(), collection);
+        for target in self.aliases.values_mut().filter(|target| *target == name) {
+            *target = new_name.clone();
+        }
+
+        info!("Renamed collection '{}' to '{}'", name, new_name);
+        Ok(())
+    }
+
+    /// Copy a collection, or the embeddings of it whose metadata matches a filter, into a new collection
+    /// with the same settings and text index.
+    ///
+    /// # Arguments
+    ///
+    /// * `source`: The name or alias of the collection to copy.
+    /// * `name`: The name of the new collection.
+    /// * `filter`: Metadata values every copied embedding must have, or `None` to copy every embedding.
+    ///
+    /// # Returns
+    ///
+    /// A result indicating success or an error if the source collection was not found, the name is taken by
+    /// a collection or an alias, the embeddings can't be stored, or the tenant of the new collection has as
+    /// many collections as its quota allows.
+    pub fn clone_collection(&mut self, source: &str, name: String, filter: Option<HashMap<String, String>>) -> Result<(), Error> {
+        self.check_collection_quota(&name)?;
+        self.copy_collection(source, name, filter)
+    }
+
+    /// Clone a collection without checking the quota of its tenant, as replayed clones aren't.
+    fn copy_collection(&mut self, source: &str, name: String, filter: Option<HashMap<String, String>>) -> Result<(), Error> {
+        let source = &self.resolve(source).to_string();
+        let collection = self.collections.get(source).ok_or_else(|| {
+            error!("Collection name: '{}', does not exist", source);
+            Error::NotFound
+        })?;
+        self.check_new_name(&name)?;
+
+        let mut embeddings = collection.stored_embeddings()?;
+        if let Some(filter) = &filter {
+            embeddings.retain(|embedding| {
+                let metadata = embedding.metadata.as_ref();
+                filter.iter().all(|(key, value)| metadata.and_then(|metadata| metadata.get(key)) == Some(value))
+            });
+        }
+        let count = embeddings.len();
+        let text_field = collection.text_index.as_ref().map(|text_index| text_index.field.clone());
+        let clone = self.build_collection(&name, collection, collection.options.clone(), text_field, embeddings)?;
+
+        if let Err(err) = self.log_change(&LogRecord::CloneCollection { source: source.clone(), name: name.clone(), filter }) {
+            if let Some(store) = clone.store {
+                remove_store(&name, store);
+            }
+            return Err(err);
+        }
+        self.collections.insert(name.clone(), clone);
+
+        info!("Cloned {} embeddings of collection '{}' into '{}'", count, source, name);
+        Ok(())
+    }
+
+    /// Change the settings of a collection, such as its storage or text index.
+    ///
+    /// Moving the embeddings to another storage rebuilds the collection without its deleted embeddings.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name or alias of the collection to change.
+    /// * `changes`: The settings to change, applied together.
+    ///
+    /// # Returns
+    ///
+    /// A result indicating success or an error if the collection was not found, the changes conflict, or the
+    /// storage isn't supported for the collection.
+    pub fn alter_collection(&mut self, name: &str, changes: CollectionChanges) -> Result<(), Error> {
+        let name = &self.resolve(name).to_string();
+        let collection = self.collections.get(name).ok_or_else(|| {
+            error!("Collection name: '{}', does not exist", name);
+            Error::NotFound
+        })?;
+        if changes.drop_text_index && changes.text_index_field.is_some() {
+            error!("Changes to collection '{}' both replace and drop its text index", name);
+            return Err(Error::ConflictingChanges);
+        }
+
+        let text_field = match (&changes.text_index_field, changes.drop_text_index) {
+            (Some(field), _) => Some(field.clone()),
+            (None, true) => None,
+            (None, false) => collection.text_index.as_ref().map(|text_index| text_index.field.clone()),
+        };
+
+        // A new storage needs a new collection, while the text index can be rebuilt in place.
+        let rebuilt = match changes.storage.filter(|&storage| storage != collection.options.storage) {
+            Some(storage) => {
+                let options = CollectionOptions { storage, ..collection.options.clone() };
+                let embeddings = collection.stored_embeddings()?;
+                Some(self.build_collection(name, collection, options, text_field.clone(), embeddings)?)
+            }
+            None => None,
+        };
+        let limits = changes.limits.map(|limits| {
+            let options = CollectionOptions { limits, ..collection.options.clone() };
+            (limits, self.eviction_policy(&options))
+        });
+        let text_index = match &rebuilt {
+            Some(_) => None,
+            None => text_field.map(|field| {
+                let mut text_index = TextIndex::new(field);
+                for index in 0..collection.position_count() {
+                    text_index.add(index, &collection.embedding_at(index)?);
+                }
+                Ok(text_index)
+            }).transpose()?,
+        };
+
+        if let Err(err) = self.log_change(&LogRecord::AlterCollection { name: name.clone(), changes }) {
+            if let Some(store) = rebuilt.and_then(|collection| collection.store) {
+                remove_store(name, store);
+            }
+            return Err(err);
+        }
+
+        let Some(collection) = self.collections.get_mut(name) else {
+            return Err(Error::NotFound);
+        };
+        match rebuilt {
+            Some(mut rebuilt) => {
+                rebuilt.cache_stats = collection.cache_stats.clone();
+                if let Some(store) = std::mem::replace(collection, rebuilt).store {
+                    remove_store(name, store);
+                }
+            }
+            None => collection.text_index = text_index,
+        }
+        // New limits apply from the next write, which evicts whatever goes over them.
+        if let Some((limits, policy)) = limits {
+            collection.options.limits = limits;
+            collection.track_usage(policy);
+        }
+
+        info!("Changed the settings of collection '{}'", name);
+        Ok(())
+    }
+
+    /// Delete a collection from the database.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the collection to delete.
+    ///
+    /// # Returns
+    ///
+    /// A result indicating success or an error if the collection was not found.
+    pub fn delete_collection(&mut self, name: &str) -> Result<(), Error> {
+        let name = &self.resolve(name).to_string();
+
+        // Check if the collection exists before attempting to delete it.
+        if !self.collections.contains_key(name) {
+            error!("Collection name: '{}', does not exist", name);
+            return Err(Error::NotFound);
+        }
+
+        self.log_change(&LogRecord::DeleteCollection { name: name.to_string() })?;
+
+        // Remove the collection from the database. Files left behind are removed on the next start.
+        if let Some(store) = self.collections.remove(name).and_then(|collection| collection.store) {
+            remove_store(name, store);
+        }
+        // Aliases of the collection go with it, so they can't refer to a later collection of the same name.
+        self.aliases.retain(|_, collection_name| collection_name != name);
+
+        info!("Deleted collection: '{}'", name);
+        Ok(())
+    }
+
+    /// Create a BM25 text index over a metadata field of a collection.
     ///
     /// # Arguments
     ///
-    /// * `name`: The name of the collection to delete.
+    /// * `collection_name`: The name of the collection to index.
+    /// * `field`: The metadata key holding the text to index.
     ///
     /// # Returns
     ///
-    /// A result indicating success or an error if the collection was not found.
-    pub fn delete_collection(&mut self, name: &str) -> Result<(), Error> {
+    /// A result indicating success or an error if the collection was not found or already has a text index.
+    pub fn create_text_index(&mut self, collection_name: &str, field: String) -> Result<(), Error> {
+        let collection_name = &self.resolve(collection_name).to_string();
+
+        let collection = self.collections
+            .get(collection_name)
+            .ok_or(Error::NotFound)?;
 
-        if let Err(e) = setup_logger() {
-            error!("Logger setup failed: {:?}", e);
-            return Err(Error::LoggerInitializationError);
+        if collection.text_index.is_some() {
+            error!("Collection '{}' already has a text index", collection_name);
+            return Err(Error::TextIndexUniqueViolation);
         }
 
-        // Check if the collection exists before attempting to delete it.
-        if !self.collections.contains_key(name) {
-            error!("Collection name: '{}', does not exist", name);
+        self.log_change(&LogRecord::CreateTextIndex { collection_name: collection_name.to_string(), field: field.clone() })?;
+        let Some(collection) = self.collections.get_mut(collection_name) else {
             return Err(Error::NotFound);
-        }
+        };
 
-        // Remove the collection from the database.
-        self.collections.remove(name);
+        // Index the embeddings already in the collection; later inserts are indexed as they arrive. Deleted
+        // embeddings are indexed too, since documents are numbered by position, and skipped by searches.
+        let mut text_index = TextIndex::new(field.clone());
+        for index in 0..collection.position_count() {
+            text_index.add(index, &collection.embedding_at(index)?);
+        }
+        collection.text_index = Some(text_index);
 
-        info!("Deleted collection: '{}'", name);
+        info!("Created text index on collection '{}' for metadata field '{}'", collection_name, field);
         Ok(())
     }
 
@@ -192,50 +1358,52 @@ impl CacheDB {
         collection_name: &str,
         mut embedding: Embedding,
     ) -> Result<(), Error> {
-
-        if let Err(e) = setup_logger() {
-            error!("Logger setup failed: {:?}", e);
-            return Err(Error::LoggerInitializationError);
-        }
+        let collection_name = &self.resolve(collection_name).to_string();
 
         // Get the collection to insert the embedding into.
         let collection = self.collections
-            .get_mut(collection_name)
+            .get(collection_name)
             .ok_or(Error::NotFound)?;
-        
 
-        // Create a HashSet to track unique hashed IDs.
-        let mut unique_ids: HashSet<u64> = collection.embeddings
-            .iter()
-            .map(|e| hash_map_id(&e.id))
-            .collect();
 
-        // Check for duplicate embeddings by hashed ID.
-        if !unique_ids.insert(hash_map_id(&embedding.id)) {
+        // Check for duplicate embeddings by ID. An expired embedding with the same ID is replaced.
+        let existing = collection.position(&embedding.id);
+        if existing.is_some_and(|index| !collection.expiries.is_expired(index)) {
             error!("Embedding with ID '{}' already exists in collection '{}'", format!("{:?}", embedding.id), collection_name);
             return Err(Error::EmbeddingUniqueViolation);
         }
 
-        // Check if the embedding's dimension matches the collection's dimension.
-        if embedding.vector.len() != collection.dimension {
-            error!(
-                "Dimension mismatch: embedding vector length is '{}' but collection '{}' expects dimension '{}'",
-                embedding.vector.len(),
-                collection_name,
-                collection.dimension
-            );
-            return Err(Error::DimensionMismatch);
-        }
+        // Check if the embedding's vector matches the collection's vector type and dimension.
+        collection.check_vector(collection_name, &embedding)?;
 
-        // Normalize the embedding vector if using cosine distance for more efficient calculations.
-        if collection.distance == Distance::Cosine {
-            embedding.vector = normalize(&embedding.vector);
-        }
+        // Expiry times are logged, so that a restore doesn't extend the life of the embedding.
+        collection.set_expiry(&mut embedding, unix_now());
+
+        // Embeddings are logged as given, since normalizing again on restore could change their values.
+        let record = LogRecord::Insert { collection_name: collection_name.to_string(), embeddings: vec![embedding.clone()], normalized: false };
+
+        // Normalize the embedding vectors using cosine distance for more efficient calculations.
+        collection.normalize_vectors(&mut embedding);
+
+        // Make room for the embedding if it would go over a limit.
+        let embeddings = vec![embedding];
+        let replaced: Vec<usize> = existing.into_iter().collect();
+        let plan = plan_evictions(self, collection_name, &embeddings, &replaced)?;
+
+        // The evictions are logged with the insert, so that they only happen if the insert does.
+        let mut records = eviction_records(self, &plan)?;
+        records.push(record);
+        self.log_changes(&records)?;
+        evict(self, plan)?;
 
         // Add the embedding to the collection.
-        collection.embeddings.push(embedding.clone());
+        let Some(collection) = self.collections.get_mut(collection_name) else {
+            return Err(Error::NotFound);
+        };
+        collection.delete_positions(replaced)?;
+        collection.push_embeddings(embeddings)?;
 
-        info!("Embedding: '{:?}', successfully inserted into collection '{}'", embedding, collection_name);
+        info!("Inserted embedding into collection '{}'", collection_name);
         Ok(())
     }
 
@@ -252,57 +1420,220 @@ impl CacheDB {
     pub fn update_collection(
         &mut self,
         collection_name: &str,
-        mut new_embeddings: Vec<Embedding>,
+        new_embeddings: Vec<Embedding>,
     ) -> Result<(), Error> {
+        self.add_embeddings(collection_name, new_embeddings, false, true, false)
+    }
 
-        if let Err(e) = setup_logger() {
-            error!("Logger setup failed: {:?}", e);
-            return Err(Error::LoggerInitializationError);
-        }
+    /// Insert embeddings into a collection, replacing those with the same ids.
+    ///
+    /// # Arguments
+    ///
+    /// * `collection_name`: The name of the collection to write to.
+    /// * `embeddings`: The embeddings to insert or replace.
+    ///
+    /// # Returns
+    ///
+    /// A result indicating success or an error if the collection was not found, an id is given twice, or an
+    /// embedding doesn't match the collection. A failed upsert changes nothing.
+    pub fn upsert_embeddings(&mut self, collection_name: &str, embeddings: Vec<Embedding>) -> Result<(), Error> {
+        self.add_embeddings(collection_name, embeddings, false, true, true)
+    }
 
+    /// Validate and add embeddings, normalizing their vectors unless they are `normalized` already.
+    ///
+    /// With `enforce_limits`, embeddings are evicted to make room for the new ones or the write is
+    /// rejected, as the limits of the collection and the database require. Replayed writes don't
+    /// enforce limits, since the evictions they caused are logged before them.
+    ///
+    /// With `upsert`, embeddings with the id of a stored one replace it instead of being rejected.
+    /// The replaced embeddings are logged as deleted before the insert, like evictions.
+    fn add_embeddings(
+        &mut self,
+        collection_name: &str,
+        mut new_embeddings: Vec<Embedding>,
+        normalized: bool,
+        enforce_limits: bool,
+        upsert: bool,
+    ) -> Result<(), Error> {
+        let collection_name = &self.resolve(collection_name).to_string();
         // Get the collection to update.
         let collection = self.collections
-            .get_mut(collection_name)
+            .get(collection_name)
             .ok_or(Error::NotFound)?;
 
-        // Iterate through each new embedding.
+        // Expiry times are logged, so that a restore doesn't extend the life of the embeddings.
+        let now = unix_now();
         for embedding in &mut new_embeddings {
-            // Create a HashSet to track unique hashed IDs.
-            let mut unique_ids: HashSet<u64> = collection.embeddings
-            .iter()
-            .map(|e| hash_map_id(&e.id))
-            .collect();
+            collection.set_expiry(embedding, now);
+        }
 
-            // Check for duplicate embeddings by hashed ID.
-            if !unique_ids.insert(hash_map_id(&embedding.id)) {
+        // Embeddings are logged as given, since normalizing again on restore could change their values.
+        let record = LogRecord::Insert { collection_name: collection_name.to_string(), embeddings: new_embeddings.clone(), normalized };
+
+        // Track the IDs of the mutable segment, besides those indexed by sealed segments, and of the new embeddings.
+        let active_ids = collection.active_ids();
+        let mut new_ids = IdIndex::default();
+        let mut replaced = Vec::new();
+        let mut upserted_ids = Vec::new();
+
+        // Validate every new embedding before adding any, so that a failed update changes nothing.
+        for new_index in 0..new_embeddings.len() {
+            // Check for duplicate embeddings by ID. Expired embeddings with the same ID are replaced.
+            let id = &new_embeddings[new_index].id;
+            let existing = collection.sealed_position(id).or_else(|| collection.active_position(&active_ids, id));
+            let stored = existing.is_some_and(|index| !collection.expiries.is_expired(index));
+            let repeated = new_ids.position(id, |earlier| new_embeddings[earlier].id == *id).is_some();
+            new_ids.insert(id, new_index);
+            let embedding = &mut new_embeddings[new_index];
+            if (stored && !upsert) || repeated {
                 error!("Embedding with ID '{}' already exists in collection '{}'", format!("{:?}", embedding.id), collection_name);
                 return Err(Error::UniqueViolation);
             }
-
-            // Check if the embedding's dimension matches the collection's dimension.
-            if embedding.vector.len() != collection.dimension {
-                error!(
-                    "Dimension mismatch: embedding vector length is '{}' but collection '{}' expects dimension '{}'",
-                    embedding.vector.len(),
-                    collection_name,
-                    collection.dimension
-                );
-                return Err(Error::DimensionMismatch);
+            if stored {
+                upserted_ids.push(embedding.id.clone());
             }
+            replaced.extend(existing);
 
-            // Normalize the vector if using cosine distance for efficient calculations.
-            if collection.distance == Distance::Cosine {
-                embedding.vector = normalize(&embedding.vector);
+            // Check if the embedding's vector matches the collection's vector type and dimension.
+            collection.check_vector(collection_name, embedding)?;
+
+            // Normalize the vectors using cosine distance for efficient calculations.
+            if !normalized {
+                collection.normalize_vectors(embedding);
             }
+        }
+
+        let plan = if enforce_limits { plan_evictions(self, collection_name, &new_embeddings, &replaced)? } else { Vec::new() };
+
+        // The evictions and replaced embeddings are logged with the insert, so that they only happen if the insert does.
+        let mut records = eviction_records(self, &plan)?;
+        if !upserted_ids.is_empty() {
+            records.push(LogRecord::DeleteEmbeddings { collection_name: collection_name.to_string(), ids: upserted_ids });
+        }
+        records.push(record);
+        self.log_changes(&records)?;
+        evict(self, plan)?;
+
+        // Add the embeddings to the collection.
+        let Some(collection) = self.collections.get_mut(collection_name) else {
+            return Err(Error::NotFound);
+        };
+        let count = new_embeddings.len();
+        collection.delete_positions(replaced)?;
+        collection.push_embeddings(new_embeddings)?;
+
+        info!("Inserted {} embeddings into collection '{}'", count, collection_name);
+        Ok(())
+    }
+
+    /// Delete embeddings from a collection.
+    ///
+    /// Deleted embeddings are marked with tombstones, which searches skip, and stay in memory until
+    /// compaction drops them. Their ids may be inserted again right away.
+    ///
+    /// # Arguments
+    ///
+    /// * `collection_name`: The name of the collection to delete from.
+    /// * `ids`: The ids of the embeddings to delete.
+    ///
+    /// # Returns
+    ///
+    /// A result indicating success or an error if the collection or one of the embeddings was not found.
+    pub fn delete_embeddings(&mut self, collection_name: &str, ids: Vec<HashMap<String, String>>) -> Result<(), Error> {
+        let collection_name = &self.resolve(collection_name).to_string();
+        let collection = self.collections
+            .get(collection_name)
+            .ok_or(Error::NotFound)?;
+
+        // Find every embedding before deleting any, so that a failed delete changes nothing.
+        let mut positions = HashSet::new();
+        for id in &ids {
+            let position = collection.position(id).ok_or_else(|| {
+                error!("Embedding with ID '{:?}' not found in collection '{}'", id, collection_name);
+                Error::EmbeddingNotFound
+            })?;
+            positions.insert(position);
+        }
+
+        self.log_change(&LogRecord::DeleteEmbeddings { collection_name: collection_name.to_string(), ids })?;
+
+        let Some(collection) = self.collections.get_mut(collection_name) else {
+            return Err(Error::NotFound);
+        };
+        let count = positions.len();
+        collection.delete_positions(positions)?;
+
+        info!("Deleted {} embeddings from collection '{}'", count, collection_name);
+        Ok(())
+    }
 
-            // Add the embedding to the collection.
-            collection.embeddings.push(embedding.clone());
+    /// The name of the collection `name` refers to, which is `name` itself unless it is an alias.
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map_or(name, String::as_str)
+    }
+
+    /// Create, repoint and delete aliases, applying every action or none of them.
+    ///
+    /// Actions are applied in order, so deleting an alias and creating it again for another
+    /// collection swaps it, and clients never see the alias missing in between.
+    ///
+    /// # Arguments
+    ///
+    /// * `actions`: The changes to make to the aliases.
+    ///
+    /// # Returns
+    ///
+    /// A result indicating success or an error if an alias to delete or a collection to point an
+    /// alias at doesn't exist, or an alias would have the name of a collection.
+    pub fn update_aliases(&mut self, actions: Vec<AliasAction>) -> Result<(), Error> {
+        // Apply the actions to a copy, so that a failed action leaves the aliases as they were.
+        let mut aliases = self.aliases.clone();
+        for action in &actions {
+            match action {
+                AliasAction::CreateAlias { alias, collection_name } => {
+                    if self.collections.contains_key(alias) {
+                        error!("Alias '{}' has the name of a collection", alias);
+                        return Err(Error::AliasConflict);
+                    }
+                    if !self.collections.contains_key(collection_name) {
+                        error!("Collection '{}' for alias '{}' not found", collection_name, alias);
+                        return Err(Error::NotFound);
+                    }
+                    aliases.insert(alias.clone(), collection_name.clone());
+                }
+                AliasAction::DeleteAlias { alias } => {
+                    if aliases.remove(alias).is_none() {
+                        error!("Alias '{}' not found", alias);
+                        return Err(Error::AliasNotFound);
+                    }
+                }
+            }
         }
 
-        info!("Embedding: '{:?}' successfully updated to collection '{}'", new_embeddings, collection_name);
+        self.log_change(&LogRecord::UpdateAliases { actions: actions.clone() })?;
+        self.aliases = aliases;
+
+        info!("Applied {} alias actions", actions.len());
         Ok(())
     }
 
+    /// The aliases referring to a collection, or to the collection an alias refers to, sorted by name.
+    pub fn aliases_of(&self, collection_name: &str) -> Result<Vec<String>, Error> {
+        let collection_name = self.resolve(collection_name);
+        if !self.collections.contains_key(collection_name) {
+            error!("Collection '{}' not found", collection_name);
+            return Err(Error::NotFound);
+        }
+        let mut aliases: Vec<String> = self.aliases
+            .iter()
+            .filter(|(_, target)| *target == collection_name)
+            .map(|(alias, _)| alias.clone())
+            .collect();
+        aliases.sort();
+        Ok(aliases)
+    }
+
     /// Retrieve a collection from the database.
     ///
     /// # Arguments
@@ -313,10 +1644,7 @@ impl CacheDB {
     ///
     /// An optional reference to the collection if found.
     pub fn get_collection(&self, collection_name: &str) -> Option<&Collection> {
-        if let Err(e) = setup_logger() {
-            error!("Logger setup failed: {:?}", e);
-        }
-    
+        let collection_name = self.resolve(collection_name);
         match self.collections.get(collection_name) {
             Some(collection) => {
                 info!("Collection '{}' found", collection_name);
@@ -337,20 +1665,19 @@ impl CacheDB {
     ///
     /// # Returns
     ///
-    /// An optional reference to the embeddings if found.
-    pub fn get_embeddings(&self, collection_name: &str) -> Option<Vec<Embedding>> {
-        if let Err(e) = setup_logger() {
-            error!("Logger setup failed: {:?}", e);
-        }
-    
+    /// A result containing copies of the embeddings, or an error if the collection was not found or its
+    /// files can't be read.
+    pub fn get_embeddings(&self, collection_name: &str) -> Result<Vec<Embedding>, Error> {
+        let collection_name = self.resolve(collection_name);
         match self.collections.get(collection_name) {
             Some(collection) => {
+                let embeddings = collection.all_embeddings()?;
                 info!("Successfully retrieved embeddings for collection '{}'", collection_name);
-                Some(collection.embeddings.clone())
+                Ok(embeddings)
             },
             None => {
                 error!("Collection '{}' not found", collection_name);
-                None
+                Err(Error::NotFound)
             }
         }
     }  
@@ -369,7 +1696,7 @@ mod tests {
     #[test]
     fn test_create_collection_success_eucledean() {
         let mut db = CacheDB::new();
-        let result = db.create_collection("test_collection".to_string(), 100, Distance::Euclidean);
+        let result = db.create_collection("test_collection".to_string(), 100, Distance::Euclidean, CollectionOptions::default());
 
         assert!(result.is_ok());
         let collection = result.unwrap();
@@ -381,7 +1708,7 @@ mod tests {
     #[test]
     fn test_create_collection_success_cosine() {
         let mut db = CacheDB::new();
-        let result = db.create_collection("test_collection".to_string(), 100, Distance::Cosine);
+        let result = db.create_collection("test_collection".to_string(), 100, Distance::Cosine, CollectionOptions::default());
 
         assert!(result.is_ok());
         let collection = result.unwrap();
@@ -393,7 +1720,7 @@ mod tests {
     #[test]
     fn test_create_collection_success_dot_product() {
         let mut db = CacheDB::new();
-        let result = db.create_collection("test_collection".to_string(), 100, Distance::DotProduct);
+        let result = db.create_collection("test_collection".to_string(), 100, Distance::DotProduct, CollectionOptions::default());
 
         assert!(result.is_ok());
         let collection = result.unwrap();
@@ -406,20 +1733,16 @@ mod tests {
     #[test]
     fn test_create_collection_already_exists() {
         let mut db = CacheDB::new();
-        db.create_collection("test_collection".to_string(), 100, Distance::Euclidean).unwrap();
+        db.create_collection("test_collection".to_string(), 100, Distance::Euclidean, CollectionOptions::default()).unwrap();
 
-        let result = db.create_collection("test_collection".to_string(), 200, Distance::Cosine);
+        let result = db.create_collection("test_collection".to_string(), 200, Distance::Cosine, CollectionOptions::default());
         assert!(result.is_err());
     }
 
     #[test]
     fn test_insert_into_collection_success() {
         let mut db = CacheDB::new();
-        let collection = Collection {
-            dimension: 3,
-            distance: Distance::Euclidean,
-            embeddings: Vec::new(),
-        };
+        let collection = Collection::new(3, Distance::Euclidean);
         db.collections.insert("test_collection".to_string(), collection);
         let mut metadata = HashMap::new();
         metadata.insert("page".to_string(), "1".to_string());
@@ -429,9 +1752,10 @@ mod tests {
         id.insert("unique_id".to_string(), "1".to_string());
         
         let embedding = Embedding {
-            id: id,
+            id,
             vector: vec![1.0, 2.0, 3.0],
-            metadata: Some(metadata)
+            metadata: Some(metadata),
+            ..Default::default()
         };
 
         let result = db.insert_into_collection("test_collection", embedding.clone());
@@ -456,13 +1780,13 @@ mod tests {
         id.insert("unique_id".to_string(), "0".to_string());
 
         let collection = Collection {
-            dimension: 3,
-            distance: Distance::Euclidean,
             embeddings: vec![Embedding {
-                id: id,
+                id,
                 vector: vec![1.0, 2.0, 3.0],
-                metadata: Some(metadata.clone())
+                metadata: Some(metadata.clone()),
+                ..Default::default()
             }],
+            ..Collection::new(3, Distance::Euclidean)
         };
 
         db.collections.insert("test_collection".to_string(), collection);
@@ -476,12 +1800,14 @@ mod tests {
             Embedding {
                 id: id_1, // Duplicate ID
                 vector: vec![4.0, 5.0, 6.0],
-                metadata: Some(metadata.clone())
+                metadata: Some(metadata.clone()),
+                ..Default::default()
             },
             Embedding {
                 id: id_2,
                 vector: vec![7.0, 8.0, 9.0],
-                metadata: Some(metadata.clone())
+                metadata: Some(metadata.clone()),
+                ..Default::default()
             },
         ];
 
@@ -505,13 +1831,13 @@ mod tests {
         id.insert("unique_id".to_string(), "0".to_string());
 
         let collection = Collection {
-            dimension: 3,
-            distance: Distance::Euclidean,
             embeddings: vec![Embedding {
                 id: id.clone(),
                 vector: vec![1.0, 2.0, 3.0],
-                metadata: Some(metadata.clone())
+                metadata: Some(metadata.clone()),
+                ..Default::default()
             }],
+            ..Collection::new(3, Distance::Euclidean)
         };
         db.collections.insert("test_collection".to_string(), collection);
 
@@ -522,14 +1848,16 @@ mod tests {
 
         let new_embeddings = vec![
             Embedding {
-                id: id, // Duplicate ID
+                id, // Duplicate ID
                 vector: vec![4.0, 5.0, 6.0],
-                metadata: Some(metadata.clone())
+                metadata: Some(metadata.clone()),
+                ..Default::default()
             },
             Embedding {
                 id: id_2,
                 vector: vec![7.0, 8.0, 9.0],
-                metadata: Some(metadata.clone())
+                metadata: Some(metadata.clone()),
+                ..Default::default()
             },
         ];
 
@@ -541,11 +1869,7 @@ mod tests {
     #[test]
     fn test_update_collection_dimension_mismatch() {
         let mut db = CacheDB::new();
-        let collection = Collection {
-            dimension: 3,
-            distance: Distance::Euclidean,
-            embeddings: Vec::new(),
-        };
+        let collection = Collection::new(3, Distance::Euclidean);
         db.collections.insert("test_collection".to_string(), collection);
 
         let mut metadata = HashMap::new();
@@ -557,9 +1881,10 @@ mod tests {
 
         let new_embeddings = vec![
             Embedding {
-                id: id,
+                id,
                 vector: vec![1.0, 2.0], 
-                metadata: Some(metadata)// Dimension mismatch
+                metadata: Some(metadata), // Dimension mismatch
+                ..Default::default()
             },
         ];
 
@@ -571,11 +1896,7 @@ mod tests {
     #[test]
     fn test_delete_collection_success() {
         let mut db = CacheDB::new();
-        db.collections.insert("test_collection".to_string(), Collection {
-            dimension: 3,
-            distance: Distance::Euclidean,
-            embeddings: Vec::new(),
-        });
+        db.collections.insert("test_collection".to_string(), Collection::new(3, Distance::Euclidean));
 
         let result = db.delete_collection("test_collection");
         assert!(result.is_ok());
@@ -596,11 +1917,7 @@ mod tests {
     #[test]
     fn test_get_collection_success() {
         let mut db = CacheDB::new();
-        let collection = Collection {
-            dimension: 3,
-            distance: Distance::Euclidean,
-            embeddings: Vec::new(),
-        };
+        let collection = Collection::new(3, Distance::Euclidean);
         db.collections.insert("test_collection".to_string(), collection.clone());
 
         let result = db.get_collection("test_collection");
@@ -633,18 +1950,17 @@ mod tests {
         id_2.insert("unique_id".to_string(), "2".to_string());
 
         let collection = Collection {
-            dimension: 3,
-            distance: Distance::Euclidean,
             embeddings: vec![
-                Embedding { id: id, vector: vec![1.0, 1.0, 1.0], metadata: None },
-                Embedding { id: id_1, vector: vec![2.0, 2.0, 2.0], metadata: None },
-                Embedding { id: id_2, vector: vec![3.0, 3.0, 3.0], metadata: None },
+                Embedding { id, vector: vec![1.0, 1.0, 1.0], metadata: None, ..Default::default() },
+                Embedding { id: id_1, vector: vec![2.0, 2.0, 2.0], metadata: None, ..Default::default() },
+                Embedding { id: id_2, vector: vec![3.0, 3.0, 3.0], metadata: None, ..Default::default() },
             ],
+            ..Collection::new(3, Distance::Euclidean)
         };
         db.collections.insert("test_collection".to_string(), collection.clone());
         let result = db.get_embeddings("test_collection");
-        assert!(result.is_some());
-        assert_eq!(result, Some(collection.embeddings));
+        assert!(result.is_ok());
+        assert_eq!(result, Ok(collection.embeddings));
     }
 
     #[test]
@@ -652,7 +1968,7 @@ mod tests {
         let db = CacheDB::new();
 
         let result = db.get_embeddings("non_existent_collection");
-        assert!(result.is_none());
+        assert_eq!(result, Err(Error::NotFound));
     }
 
 
@@ -669,30 +1985,662 @@ mod tests {
         id_2.insert("unique_id".to_string(), "2".to_string());
 
         let collection = Collection {
-            dimension: 3,
-            distance: Distance::Euclidean,
             embeddings: vec![
-                Embedding { id: id.clone(), vector: vec![1.0, 1.0, 1.0], metadata: None },
-                Embedding { id: id_1.clone(), vector: vec![2.0, 2.0, 2.0], metadata: None },
-                Embedding { id: id_2.clone(), vector: vec![3.0, 3.0, 3.0], metadata: None },
+                Embedding { id: id.clone(), vector: vec![1.0, 1.0, 1.0], metadata: None, ..Default::default() },
+                Embedding { id: id_1.clone(), vector: vec![2.0, 2.0, 2.0], metadata: None, ..Default::default() },
+                Embedding { id: id_2.clone(), vector: vec![3.0, 3.0, 3.0], metadata: None, ..Default::default() },
             ],
+            ..Collection::new(3, Distance::Euclidean)
         };
 
         // Define a query vector
         let query = vec![0.0, 0.0, 0.0];
 
-        // Define the expected similarity results
+        // Define the expected similarity results, nearest first
         let expected_results = vec![
-            SimilarityResult { score: 0.0, embedding: Embedding { id: id_1, vector: vec![2.0, 2.0, 2.0], metadata: None } },
-            SimilarityResult { score: 0.0, embedding: Embedding { id: id_2, vector: vec![3.0, 3.0, 3.0], metadata: None } },
-            SimilarityResult { score: 0.0, embedding: Embedding { id: id, vector: vec![1.0, 1.0, 1.0], metadata: None } },
+            SimilarityResult { score: 3.0f32.sqrt(), embedding: Embedding { id, vector: vec![1.0, 1.0, 1.0], metadata: None, ..Default::default() } },
+            SimilarityResult { score: 12.0f32.sqrt(), embedding: Embedding { id: id_1, vector: vec![2.0, 2.0, 2.0], metadata: None, ..Default::default() } },
+            SimilarityResult { score: 27.0f32.sqrt(), embedding: Embedding { id: id_2, vector: vec![3.0, 3.0, 3.0], metadata: None, ..Default::default() } },
         ];
 
         // Call the get_similarity method
-        let results = collection.get_similarity(&query, 3);
+        let results = collection.get_similarity(&query, 3, &SearchParams::default()).unwrap();
 
         // Assert that the results are as expected
         assert_eq!(results, expected_results);
     }
 
+
+    #[test]
+    fn test_search_offset_and_threshold() {
+        let embeddings = (0..5)
+            .map(|i| {
+                let mut id = HashMap::new();
+                id.insert("unique_id".to_string(), i.to_string());
+                Embedding { id, vector: vec![i as f32, 0.0], metadata: None, ..Default::default() }
+            })
+            .collect();
+        let collection = Collection {
+            embeddings,
+            ..Collection::new(2, Distance::DotProduct)
+        };
+
+        let params = SearchParams { offset: 1, ..SearchParams::default() };
+        let scores: Vec<f32> = collection.get_similarity(&[1.0, 0.0], 2, &params).unwrap().iter().map(|r| r.score).collect();
+        assert_eq!(scores, vec![3.0, 2.0]);
+
+        let params = SearchParams { score_threshold: Some(3.0), ..SearchParams::default() };
+        let scores: Vec<f32> = collection.get_similarity(&[1.0, 0.0], 5, &params).unwrap().iter().map(|r| r.score).collect();
+        assert_eq!(scores, vec![4.0, 3.0]);
+    }
+
+    #[test]
+    fn test_search_euclidean_threshold_is_max_distance() {
+        let collection = Collection {
+            embeddings: (0..4)
+                .map(|i| {
+                    let mut id = HashMap::new();
+                    id.insert("unique_id".to_string(), i.to_string());
+                    Embedding { id, vector: vec![i as f32], metadata: None, ..Default::default() }
+                })
+                .collect(),
+            ..Collection::new(1, Distance::Euclidean)
+        };
+
+        let params = SearchParams { score_threshold: Some(1.5), ..SearchParams::default() };
+        let scores: Vec<f32> = collection.get_similarity(&[0.0], 10, &params).unwrap().iter().map(|r| r.score).collect();
+        assert_eq!(scores, vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_search_field_selection() {
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), "0".to_string());
+        let mut metadata = HashMap::new();
+        metadata.insert("text".to_string(), "This is a test metadata text".to_string());
+        let collection = Collection {
+            embeddings: vec![Embedding { id: id.clone(), vector: vec![1.0, 0.0, 0.0], metadata: Some(metadata), ..Default::default() }],
+            ..Collection::new(3, Distance::Cosine)
+        };
+
+        let params = SearchParams { with_vector: false, with_metadata: false, ..SearchParams::default() };
+        let results = collection.get_similarity(&[2.0, 0.0, 0.0], 1, &params).unwrap();
+        assert_eq!(results, vec![SimilarityResult { score: 1.0, embedding: Embedding { id, vector: Vec::new(), metadata: None, ..Default::default() } }]);
+
+        let json = serde_json::to_value(&results[0]).unwrap();
+        assert!(json["embedding"].get("vector").is_none());
+    }
+
+
+    #[test]
+    fn test_sparse_collection_validation() {
+        let mut db = CacheDB::new();
+        let options = CollectionOptions { vector_type: VectorType::Sparse, ..CollectionOptions::default() };
+
+        let result = db.create_collection("test_collection".to_string(), 10, Distance::Cosine, options.clone());
+        assert_eq!(result.err(), Some(Error::UnsupportedDistance));
+        db.create_collection("test_collection".to_string(), 10, Distance::DotProduct, options).unwrap();
+
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), "0".to_string());
+        let invalid_vectors = [
+            (SparseVector { indices: vec![1, 2], values: vec![1.0] }, Error::InvalidSparseVector),
+            (SparseVector { indices: vec![1, 1], values: vec![1.0, 2.0] }, Error::InvalidSparseVector),
+            (SparseVector { indices: vec![10], values: vec![1.0] }, Error::DimensionMismatch),
+        ];
+        for (sparse_vector, expected) in invalid_vectors {
+            let embedding = Embedding { id: id.clone(), sparse_vector: Some(sparse_vector), ..Default::default() };
+            assert_eq!(db.insert_into_collection("test_collection", embedding).err(), Some(expected));
+        }
+
+        let embedding = Embedding { id, sparse_vector: Some(SparseVector { indices: vec![9], values: vec![1.0] }), ..Default::default() };
+        assert!(db.insert_into_collection("test_collection", embedding).is_ok());
+    }
+
+
+    #[test]
+    fn test_named_vector_validation() {
+        let mut db = CacheDB::new();
+        let mut vectors = HashMap::new();
+        vectors.insert("title".to_string(), VectorParams { dimension: 2, distance: Distance::Cosine });
+        let options = CollectionOptions { vectors, ..CollectionOptions::default() };
+        db.create_collection("test_collection".to_string(), 3, Distance::Euclidean, options).unwrap();
+
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), "0".to_string());
+
+        // Declared named vectors are required
+        let embedding = Embedding { id: id.clone(), vector: vec![1.0, 2.0, 3.0], ..Default::default() };
+        assert_eq!(db.insert_into_collection("test_collection", embedding.clone()).err(), Some(Error::DimensionMismatch));
+
+        let mut undeclared = embedding.clone();
+        undeclared.vectors.insert("title".to_string(), vec![3.0, 4.0]);
+        undeclared.vectors.insert("body".to_string(), vec![1.0]);
+        assert_eq!(db.insert_into_collection("test_collection", undeclared).err(), Some(Error::VectorNotFound));
+
+        // Named vectors with cosine distance are normalized on insertion
+        let mut valid = embedding;
+        valid.vectors.insert("title".to_string(), vec![3.0, 4.0]);
+        db.insert_into_collection("test_collection", valid).unwrap();
+        let collection = db.get_collection("test_collection").unwrap();
+        assert_eq!(collection.embeddings[0].vectors["title"], vec![0.6, 0.8]);
+        assert_eq!(collection.embeddings[0].vector, vec![1.0, 2.0, 3.0]);
+    }
+
+
+    #[test]
+    fn test_multivector_collection() {
+        let mut db = CacheDB::new();
+        let options = CollectionOptions { vector_type: VectorType::Multi, ..CollectionOptions::default() };
+        let result = db.create_collection("test_collection".to_string(), 2, Distance::Euclidean, options.clone());
+        assert_eq!(result.err(), Some(Error::UnsupportedDistance));
+        db.create_collection("test_collection".to_string(), 2, Distance::Cosine, options).unwrap();
+
+        let documents = [
+            vec![vec![1.0, 0.0], vec![1.0, 1.0]],
+            vec![vec![0.0, 2.0]],
+            vec![vec![1.0, 0.0], vec![0.0, 1.0]],
+        ];
+        for (i, multivector) in documents.into_iter().enumerate() {
+            let mut id = HashMap::new();
+            id.insert("unique_id".to_string(), i.to_string());
+            db.insert_into_collection("test_collection", Embedding { id, multivector, ..Default::default() }).unwrap();
+        }
+
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), "3".to_string());
+        let embedding = Embedding { id, multivector: vec![vec![1.0, 0.0, 0.0]], ..Default::default() };
+        assert_eq!(db.insert_into_collection("test_collection", embedding).err(), Some(Error::DimensionMismatch));
+
+        let collection = db.get_collection("test_collection").unwrap();
+        let query = vec![vec![2.0, 0.0], vec![0.0, 2.0]];
+        let results = collection.get_multi_similarity(&query, 3, &SearchParams::default()).unwrap();
+        let ids: Vec<&str> = results.iter().map(|r| r.embedding.id["unique_id"].as_str()).collect();
+        assert_eq!(ids, vec!["2", "0", "1"]);
+        assert_eq!(results[0].score, 2.0);
+
+        assert_eq!(collection.get_similarity(&[1.0, 0.0], 1, &SearchParams::default()).err(), Some(Error::VectorTypeMismatch));
+    }
+
+    #[test]
+    fn test_mmap_collection() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = CacheDB::new();
+        let mmap = CollectionOptions { storage: Storage::Mmap, ..CollectionOptions::default() };
+        assert_eq!(db.create_collection("mmap".to_string(), 2, Distance::Cosine, mmap.clone()).err(), Some(Error::UnsupportedStorage));
+
+        db.set_collections_dir(dir.path().join("collections")).unwrap();
+        let sparse = CollectionOptions { vector_type: VectorType::Sparse, ..mmap.clone() };
+        assert_eq!(db.create_collection("mmap".to_string(), 2, Distance::DotProduct, sparse).err(), Some(Error::UnsupportedStorage));
+        db.create_collection("mmap".to_string(), 2, Distance::Cosine, mmap).unwrap();
+        db.create_collection("memory".to_string(), 2, Distance::Cosine, CollectionOptions::default()).unwrap();
+        db.create_text_index("mmap", "text".to_string()).unwrap();
+        db.create_text_index("memory", "text".to_string()).unwrap();
+
+        let embeddings: Vec<Embedding> = (0..5)
+            .map(|i| {
+                let mut id = HashMap::new();
+                id.insert("unique_id".to_string(), i.to_string());
+                let mut metadata = HashMap::new();
+                metadata.insert("text".to_string(), format!("document {}", if i % 2 == 0 { "even" } else { "odd" }));
+                Embedding { id, vector: vec![1.0, i as f32], metadata: Some(metadata), ..Default::default() }
+            })
+            .collect();
+        for name in ["mmap", "memory"] {
+            db.insert_into_collection(name, embeddings[0].clone()).unwrap();
+            db.update_collection(name, embeddings[1..].to_vec()).unwrap();
+            assert_eq!(db.insert_into_collection(name, embeddings[2].clone()).err(), Some(Error::EmbeddingUniqueViolation));
+            assert_eq!(db.update_collection(name, embeddings[3..].to_vec()).err(), Some(Error::UniqueViolation));
+        }
+
+        // Searches give the same results whether the embeddings are memory-mapped or not.
+        let (mmap, memory) = (&db.collections["mmap"], &db.collections["memory"]);
+        assert!(mmap.embeddings.is_empty());
+        assert_eq!(mmap.embedding_count(), 5);
+        assert_eq!(mmap.all_embeddings(), memory.all_embeddings());
+        let params = SearchParams { offset: 1, ..SearchParams::default() };
+        assert_eq!(mmap.get_similarity(&[0.0, 1.0], 3, &params), memory.get_similarity(&[0.0, 1.0], 3, &params));
+        assert_eq!(
+            mmap.recommend(&[embeddings[4].id.clone()], &[embeddings[0].id.clone()], 2, &params),
+            memory.recommend(&[embeddings[4].id.clone()], &[embeddings[0].id.clone()], 2, &params)
+        );
+        let fusion = Fusion::Rrf { rank_constant: 60.0 };
+        assert_eq!(
+            mmap.hybrid_search(Some(&[1.0, 0.0]), Some("odd"), 3, fusion, &params),
+            memory.hybrid_search(Some(&[1.0, 0.0]), Some("odd"), 3, fusion, &params)
+        );
+
+        let collection_dir = dir.path().join("collections").join("mmap");
+        assert!(collection_dir.join("segment-00000.vec").exists());
+        db.delete_collection("mmap").unwrap();
+        assert!(!collection_dir.exists());
+    }
+
+    #[test]
+    fn test_delete_embeddings() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = CacheDB::new();
+        db.set_collections_dir(dir.path().join("collections")).unwrap();
+        let mmap = CollectionOptions { storage: Storage::Mmap, ..CollectionOptions::default() };
+        db.create_collection("mmap".to_string(), 2, Distance::Euclidean, mmap).unwrap();
+        db.create_collection("memory".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+
+        // Enough embeddings to seal a segment, so that deletes reach both sealed and mutable segments.
+        let embedding = |i: usize| {
+            let mut id = HashMap::new();
+            id.insert("unique_id".to_string(), i.to_string());
+            Embedding { id, vector: vec![i as f32, 0.0], ..Default::default() }
+        };
+        let embeddings: Vec<Embedding> = (0..SEGMENT_CAPACITY + 10).map(embedding).collect();
+        let deleted = [embedding(0).id, embedding(SEGMENT_CAPACITY + 5).id];
+        for name in ["mmap", "memory"] {
+            db.update_collection(name, embeddings.clone()).unwrap();
+
+            // A missing id fails the whole delete.
+            let missing = vec![embedding(0).id, embedding(SEGMENT_CAPACITY + 10).id];
+            assert_eq!(db.delete_embeddings(name, missing), Err(Error::EmbeddingNotFound));
+            assert_eq!(db.collections[name].embedding_count(), SEGMENT_CAPACITY + 10);
+
+            db.delete_embeddings(name, deleted.to_vec()).unwrap();
+            assert_eq!(db.delete_embeddings(name, vec![embedding(0).id]), Err(Error::EmbeddingNotFound));
+            assert_eq!(db.delete_embeddings("missing", vec![embedding(1).id]), Err(Error::NotFound));
+        }
+
+        let (mmap, memory) = (&db.collections["mmap"], &db.collections["memory"]);
+        assert_eq!(memory.sealed.len(), 1);
+        assert_eq!(memory.embeddings.len(), 10);
+        for collection in [mmap, memory] {
+            assert_eq!(collection.embedding_count(), SEGMENT_CAPACITY + 8);
+            assert_eq!(collection.position_count(), SEGMENT_CAPACITY + 10);
+            let live = collection.all_embeddings().unwrap();
+            assert_eq!(live.len(), SEGMENT_CAPACITY + 8);
+            assert!(!live.iter().any(|embedding| deleted.contains(&embedding.id)));
+
+            // Searches skip deleted embeddings.
+            let results = collection.get_similarity(&[0.0, 0.0], 1, &SearchParams::default()).unwrap();
+            assert_eq!(results[0].embedding.id, embedding(1).id);
+            let results = collection.get_similarity(&[(SEGMENT_CAPACITY + 5) as f32, 0.0], 1, &SearchParams::default()).unwrap();
+            assert_ne!(results[0].embedding.id, embedding(SEGMENT_CAPACITY + 5).id);
+        }
+
+        // Deleted ids may be inserted again, into the mutable segment.
+        for name in ["mmap", "memory"] {
+            db.update_collection(name, vec![embedding(0)]).unwrap();
+            assert_eq!(db.insert_into_collection(name, embedding(0)), Err(Error::EmbeddingUniqueViolation));
+            assert_eq!(db.insert_into_collection(name, embedding(1)), Err(Error::EmbeddingUniqueViolation));
+            let results = db.collections[name].get_similarity(&[0.0, 0.0], 1, &SearchParams::default()).unwrap();
+            assert_eq!(results[0].embedding.id, embedding(0).id);
+        }
+        assert_eq!(db.collections["memory"].flattened().unwrap().embeddings, db.collections["mmap"].all_embeddings().unwrap());
+    }
+
+    #[test]
+    fn test_multi_key_ids_past_one_segment() {
+        let mut db = CacheDB::new();
+        db.create_collection("test".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+
+        // Each id is built in a new map, with its keys in a different order, so that equal ids iterate differently.
+        let id = |i: usize, reversed: bool| {
+            let mut keys = vec!["tenant", "user", "document", "chunk", "page", "section", "version", "language"];
+            if reversed {
+                keys.reverse();
+            }
+            keys.into_iter().map(|key| (key.to_string(), format!("{}-{}", key, i))).collect::<HashMap<_, _>>()
+        };
+        let embedding = |i: usize, reversed: bool| Embedding { id: id(i, reversed), vector: vec![i as f32, 0.0], ..Default::default() };
+        db.update_collection("test", (0..SEGMENT_CAPACITY + 10).map(|i| embedding(i, false)).collect()).unwrap();
+        assert_eq!(db.collections["test"].sealed.len(), 1);
+
+        for i in [0, 7, SEGMENT_CAPACITY - 1, SEGMENT_CAPACITY + 3] {
+            assert_eq!(db.insert_into_collection("test", embedding(i, true)), Err(Error::EmbeddingUniqueViolation));
+            assert_eq!(db.update_collection("test", vec![embedding(i, true)]), Err(Error::UniqueViolation));
+            // Recommendations find the positive id, and leave it out of the results.
+            let results = db.collections["test"].recommend(&[id(i, true)], &[], 1, &SearchParams::default()).unwrap();
+            assert_ne!(results[0].embedding.id, id(i, false));
+        }
+
+        // Upserts replace the stored embedding rather than adding another.
+        db.upsert_embeddings("test", vec![Embedding { vector: vec![-1.0, 0.0], ..embedding(7, true) }]).unwrap();
+        assert_eq!(db.collections["test"].embedding_count(), SEGMENT_CAPACITY + 10);
+
+        db.delete_embeddings("test", vec![id(0, true), id(7, true), id(SEGMENT_CAPACITY + 3, true)]).unwrap();
+        assert_eq!(db.collections["test"].embedding_count(), SEGMENT_CAPACITY + 7);
+        assert_eq!(db.delete_embeddings("test", vec![id(7, false)]), Err(Error::EmbeddingNotFound));
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut db = CacheDB::new();
+        for name in ["books_v1", "books_v2"] {
+            db.create_collection(name.to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+        }
+        let embedding = |unique_id: &str| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![1.0, 2.0], ..Default::default() }
+        };
+        let create = |alias: &str, collection_name: &str| AliasAction::CreateAlias { alias: alias.to_string(), collection_name: collection_name.to_string() };
+        let delete = |alias: &str| AliasAction::DeleteAlias { alias: alias.to_string() };
+
+        db.update_aliases(vec![create("books", "books_v1"), create("library", "books_v1")]).unwrap();
+        db.insert_into_collection("books", embedding("1")).unwrap();
+        db.create_text_index("books", "text".to_string()).unwrap();
+        assert_eq!(db.collections["books_v1"].embedding_count(), 1);
+        assert!(db.collections["books_v1"].text_index.is_some());
+        assert_eq!(db.get_collection("books"), db.collections.get("books_v1"));
+        assert_eq!(db.aliases_of("books"), Ok(vec!["books".to_string(), "library".to_string()]));
+
+        // A failing action leaves every alias as it was.
+        assert_eq!(db.update_aliases(vec![delete("books"), create("books", "books_v3")]), Err(Error::NotFound));
+        assert_eq!(db.update_aliases(vec![delete("books"), delete("books")]), Err(Error::AliasNotFound));
+        assert_eq!(db.update_aliases(vec![create("books_v1", "books_v2")]), Err(Error::AliasConflict));
+        assert_eq!(db.resolve("books"), "books_v1");
+
+        // Swapping points the alias at the new collection at once.
+        db.update_aliases(vec![delete("books"), create("books", "books_v2")]).unwrap();
+        db.update_collection("books", vec![embedding("2")]).unwrap();
+        db.delete_embeddings("library", vec![embedding("1").id]).unwrap();
+        assert_eq!(db.get_embeddings("books"), Ok(vec![embedding("2")]));
+        assert_eq!(db.get_embeddings("library"), Ok(Vec::new()));
+
+        // Collections can't take the name of an alias, and deleting a collection deletes its aliases.
+        let result = db.create_collection("books".to_string(), 2, Distance::Euclidean, CollectionOptions::default());
+        assert_eq!(result.err(), Some(Error::AliasConflict));
+        db.delete_collection("library").unwrap();
+        assert!(!db.collections.contains_key("books_v1"));
+        assert_eq!(db.aliases, HashMap::from([("books".to_string(), "books_v2".to_string())]));
+    }
+
+    #[test]
+    fn test_embedding_expiry() {
+        let mut db = CacheDB::new();
+        let options = CollectionOptions { ttl_secs: Some(60), ..CollectionOptions::default() };
+        db.create_collection("cache".to_string(), 2, Distance::Euclidean, options).unwrap();
+        let embedding = |unique_id: &str, expires_at: Option<u64>| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![1.0, 0.0], expires_at, ..Default::default() }
+        };
+        let now = unix_now();
+
+        // The TTL of an embedding overrides the collection's, and either is turned into an expiry time.
+        db.insert_into_collection("cache", embedding("default", None)).unwrap();
+        db.insert_into_collection("cache", Embedding { ttl_secs: Some(3600), ..embedding("own", None) }).unwrap();
+        db.update_collection("cache", vec![embedding("expired", Some(now - 1)), embedding("stale", Some(now - 5))]).unwrap();
+        let expiries: Vec<Option<u64>> = db.collections["cache"].embeddings.iter().map(|embedding| embedding.expires_at).collect();
+        assert!((now + 60..now + 62).contains(&expiries[0].unwrap()));
+        assert!((now + 3600..now + 3602).contains(&expiries[1].unwrap()));
+        assert!(db.collections["cache"].embeddings.iter().all(|embedding| embedding.ttl_secs.is_none()));
+
+        // Expired embeddings are left out of searches and reads before they are swept.
+        let collection = &db.collections["cache"];
+        assert_eq!(collection.embedding_count(), 2);
+        let results = collection.get_similarity(&[1.0, 0.0], 10, &SearchParams::default()).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(db.get_embeddings("cache").unwrap().len(), 2);
+        let recommended = collection.recommend(&[embedding("expired", None).id], &[], 1, &SearchParams::default());
+        assert_eq!(recommended.err(), Some(Error::EmbeddingNotFound));
+
+        // Expired embeddings can be inserted again, replacing the old ones, while live ones can't.
+        db.insert_into_collection("cache", embedding("expired", None)).unwrap();
+        assert_eq!(db.update_collection("cache", vec![embedding("own", None)]), Err(Error::UniqueViolation));
+        assert_eq!(db.collections["cache"].tombstones, HashSet::from([2]));
+
+        crate::expiry::sweep(&mut db);
+        let collection = &db.collections["cache"];
+        assert_eq!(collection.tombstones, HashSet::from([2, 3]));
+        assert_eq!(collection.expiries.expired(now), Vec::<usize>::new());
+        assert_eq!(collection.embedding_count(), 3);
+    }
+
+    #[test]
+    fn test_limits_and_eviction() {
+        let mut db = CacheDB::new();
+        let embedding = |unique_id: &str, x: f32| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![x, 1.0], ..Default::default() }
+        };
+        let ids = |db: &CacheDB, name: &str| -> Vec<String> {
+            let mut ids: Vec<String> = db.get_embeddings(name).unwrap().into_iter().map(|embedding| embedding.id["unique_id"].clone()).collect();
+            ids.sort();
+            ids
+        };
+        let limited = |max_vectors, max_memory_bytes, eviction_policy| CollectionOptions {
+            limits: Limits { max_vectors, max_memory_bytes, eviction_policy },
+            ..CollectionOptions::default()
+        };
+
+        // Each policy evicts its own embedding to make room for a third one.
+        for (name, policy, evicted) in [("fifo", EvictionPolicy::Fifo, "a"), ("lru", EvictionPolicy::Lru, "b"), ("lfu", EvictionPolicy::Lfu, "a")] {
+            db.create_collection(name.to_string(), 2, Distance::Euclidean, limited(Some(2), None, Some(policy))).unwrap();
+            db.update_collection(name, vec![embedding("a", 0.0), embedding("b", 10.0)]).unwrap();
+            for x in [10.0, 10.0, 0.0] {
+                db.collections[name].get_similarity(&[x, 1.0], 1, &SearchParams::default()).unwrap();
+            }
+            db.insert_into_collection(name, embedding("c", 5.0)).unwrap();
+            assert!(!ids(&db, name).contains(&evicted.to_string()), "{}", name);
+            assert_eq!(db.collections[name].embedding_count(), 2);
+        }
+
+        // Writes over the limits are rejected when the policy says so, or when evicting can't make room.
+        let rejecting = limited(Some(1), None, Some(EvictionPolicy::RejectWrites));
+        db.create_collection("reject".to_string(), 2, Distance::Euclidean, rejecting).unwrap();
+        db.insert_into_collection("reject", embedding("a", 0.0)).unwrap();
+        assert_eq!(db.insert_into_collection("reject", embedding("b", 0.0)), Err(Error::LimitExceeded));
+        let batch = vec![embedding("d", 0.0), embedding("e", 0.0), embedding("f", 0.0)];
+        assert_eq!(db.update_collection("fifo", batch), Err(Error::LimitExceeded));
+        assert_eq!(ids(&db, "reject"), vec!["a"]);
+        assert_eq!(ids(&db, "fifo"), vec!["b", "c"]);
+
+        // Memory limits are checked against the estimated size of the embeddings.
+        let size = embedding("a", 0.0).estimated_memory_bytes();
+        db.create_collection("sized".to_string(), 2, Distance::Euclidean, limited(None, Some(2 * size), Some(EvictionPolicy::Fifo))).unwrap();
+        db.update_collection("sized", vec![embedding("a", 0.0), embedding("b", 0.0)]).unwrap();
+        db.insert_into_collection("sized", embedding("c", 0.0)).unwrap();
+        assert_eq!(ids(&db, "sized"), vec!["b", "c"]);
+
+        // The limits of the database evict whichever embedding its policy ranks first across collections.
+        db.set_limits(Limits { max_vectors: Some(9), max_memory_bytes: None, eviction_policy: Some(EvictionPolicy::Fifo) });
+        db.create_collection("open".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+        db.insert_into_collection("open", embedding("a", 0.0)).unwrap();
+        assert_eq!(ids(&db, "fifo"), vec!["c"]);
+        assert_eq!(ids(&db, "open"), vec!["a"]);
+
+        // Changed limits apply from the next write.
+        let changes = CollectionChanges { limits: Some(Limits { max_vectors: Some(1), max_memory_bytes: None, eviction_policy: Some(EvictionPolicy::Fifo) }), ..CollectionChanges::default() };
+        db.alter_collection("reject", changes).unwrap();
+        db.insert_into_collection("reject", embedding("b", 0.0)).unwrap();
+        assert_eq!(ids(&db, "reject"), vec!["b"]);
+    }
+
+    #[test]
+    fn test_tenant_quotas() {
+        let mut db = CacheDB::new();
+        let quota = Quota { max_collections: Some(2), max_vectors: Some(3), max_memory_bytes: None };
+        db.set_quotas(HashMap::from([("team-a".to_string(), quota)]));
+        let embedding = |unique_id: &str| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![1.0, 0.0], ..Default::default() }
+        };
+
+        // Collections of the same name live apart in each namespace, and only the tenant's count.
+        for name in ["team-a/books", "team-a/movies", "team-b/books", "books"] {
+            db.create_collection(name.to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+        }
+        assert_eq!(db.create_collection("team-a/songs".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).err(), Some(Error::LimitExceeded));
+        assert_eq!(db.clone_collection("team-a/books", "team-a/copy".to_string(), None), Err(Error::LimitExceeded));
+        db.clone_collection("team-b/books", "team-b/copy".to_string(), None).unwrap();
+
+        // Vectors count across the tenant's collections, and writes over the quota are rejected even
+        // when the collection would evict.
+        db.update_collection("team-a/books", vec![embedding("1"), embedding("2")]).unwrap();
+        db.insert_into_collection("team-a/movies", embedding("3")).unwrap();
+        db.alter_collection("team-a/movies", CollectionChanges {
+            limits: Some(Limits { max_vectors: Some(10), max_memory_bytes: None, eviction_policy: Some(EvictionPolicy::Fifo) }),
+            ..CollectionChanges::default()
+        }).unwrap();
+        assert_eq!(db.insert_into_collection("team-a/movies", embedding("4")), Err(Error::LimitExceeded));
+        db.update_collection("team-b/books", vec![embedding("1"), embedding("2"), embedding("3"), embedding("4")]).unwrap();
+        db.delete_embeddings("team-a/books", vec![embedding("1").id]).unwrap();
+        db.insert_into_collection("team-a/movies", embedding("4")).unwrap();
+    }
+
+    #[test]
+    fn test_global_eviction_spares_other_tenants() {
+        let mut db = CacheDB::new();
+        db.set_limits(Limits { max_vectors: Some(3), max_memory_bytes: None, eviction_policy: Some(EvictionPolicy::Fifo) });
+        let quota = Quota { max_collections: None, max_vectors: Some(5), max_memory_bytes: None };
+        db.set_quotas(HashMap::from([("team-a".to_string(), quota), ("team-b".to_string(), quota)]));
+        let embedding = |unique_id: &str| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![1.0, 0.0], ..Default::default() }
+        };
+        let ids = |db: &CacheDB, name: &str| -> Vec<String> {
+            let mut ids: Vec<String> = db.get_embeddings(name).unwrap().into_iter().map(|embedding| embedding.id["unique_id"].clone()).collect();
+            ids.sort();
+            ids
+        };
+        for name in ["team-a/docs", "team-b/docs", "docs"] {
+            db.create_collection(name.to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+        }
+
+        // The oldest embedding belongs to another tenant within its quota, so the writer's own is evicted.
+        db.insert_into_collection("team-b/docs", embedding("b1")).unwrap();
+        db.update_collection("team-a/docs", vec![embedding("a1"), embedding("a2")]).unwrap();
+        db.insert_into_collection("team-a/docs", embedding("a3")).unwrap();
+        assert_eq!(ids(&db, "team-a/docs"), vec!["a2", "a3"]);
+        assert_eq!(ids(&db, "team-b/docs"), vec!["b1"]);
+
+        // Collections without a tenant don't evict from tenants either.
+        assert_eq!(db.insert_into_collection("docs", embedding("d1")), Err(Error::LimitExceeded));
+
+        // A tenant over its own quota is evicted from like the writer's tenant.
+        let lowered = Quota { max_vectors: Some(0), ..quota };
+        db.set_quotas(HashMap::from([("team-a".to_string(), quota), ("team-b".to_string(), lowered)]));
+        db.insert_into_collection("team-a/docs", embedding("a4")).unwrap();
+        assert!(ids(&db, "team-b/docs").is_empty());
+        assert_eq!(ids(&db, "team-a/docs"), vec!["a2", "a3", "a4"]);
+    }
+
+    #[test]
+    fn test_partitioned_collection() {
+        let mut db = CacheDB::new();
+        let options = CollectionOptions { partition_key: Some("user".to_string()), ..CollectionOptions::default() };
+        db.create_collection("notes".to_string(), 2, Distance::Euclidean, options).unwrap();
+        db.create_collection("plain".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+        let embedding = |unique_id: usize, user: &str| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            let metadata = HashMap::from([("user".to_string(), user.to_string())]);
+            Embedding { id, vector: vec![unique_id as f32, 0.0], metadata: Some(metadata), ..Default::default() }
+        };
+
+        assert_eq!(db.insert_into_collection("notes", Embedding { vector: vec![0.0, 0.0], ..Default::default() }), Err(Error::MissingPartitionKey));
+        db.update_collection("notes", (0..6).map(|unique_id| embedding(unique_id, ["alice", "bob"][unique_id % 2])).collect()).unwrap();
+        db.delete_embeddings("notes", vec![embedding(0, "alice").id]).unwrap();
+
+        // Only the searched partition is scored, so the nearest neighbours of other users never show up.
+        let ids = |db: &CacheDB, partition: &str| {
+            let params = SearchParams { partition: Some(partition.to_string()), ..SearchParams::default() };
+            let results = db.collections["notes"].get_similarity(&[0.0, 0.0], 5, &params).unwrap();
+            results.into_iter().map(|result| result.embedding.id["unique_id"].clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&db, "alice"), ["2", "4"]);
+        assert_eq!(ids(&db, "bob"), ["1", "3", "5"]);
+        assert!(ids(&db, "carol").is_empty());
+        let params = SearchParams { partition: Some("alice".to_string()), ..SearchParams::default() };
+        assert_eq!(db.collections["plain"].get_similarity(&[0.0, 0.0], 5, &params), Err(Error::NotPartitioned));
+
+        // Compaction stores the embeddings of each partition together, once there are enough to seal
+        // the mutable segment, and rebuilds the partitions with them.
+        let filler_len = crate::segment::MIN_SEAL_LEN;
+        let filler = (6..6 + filler_len).map(|unique_id| embedding(unique_id, ["dave", "erin"][unique_id % 2])).collect();
+        db.update_collection("notes", filler).unwrap();
+        let db = std::sync::Mutex::new(db);
+        crate::compaction::compact(&db, "notes").unwrap();
+        let mut db = db.into_inner().unwrap();
+        let notes = &db.collections["notes"];
+        assert!(notes.tombstones.is_empty());
+        assert_eq!(notes.partition_index.positions("alice"), [0, 1]);
+        assert_eq!(notes.partition_index.positions("bob"), [2, 3, 4]);
+        assert_eq!(notes.partition_index.positions("dave"), (5..5 + filler_len / 2).collect::<Vec<_>>());
+        assert_eq!(notes.partition_index.positions("erin"), (5 + filler_len / 2..5 + filler_len).collect::<Vec<_>>());
+        assert_eq!(ids(&db, "alice"), ["2", "4"]);
+        assert_eq!(ids(&db, "bob"), ["1", "3", "5"]);
+
+        // Embeddings inserted after compaction are appended, and still found in their partition.
+        db.insert_into_collection("notes", embedding(10_000, "alice")).unwrap();
+        assert_eq!(ids(&db, "alice"), ["2", "4", "10000"]);
+    }
+
+    #[test]
+    fn test_rename_clone_alter_collection() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = CacheDB::new();
+        db.set_collections_dir(dir.path().join("collections")).unwrap();
+        let mmap = CollectionOptions { storage: Storage::Mmap, ..CollectionOptions::default() };
+        db.create_collection("books".to_string(), 2, Distance::Cosine, mmap).unwrap();
+        db.create_collection("movies".to_string(), 2, Distance::Cosine, CollectionOptions::default()).unwrap();
+        db.create_text_index("books", "genre".to_string()).unwrap();
+        let embeddings: Vec<Embedding> = (0..4)
+            .map(|i| {
+                let id = HashMap::from([("unique_id".to_string(), i.to_string())]);
+                let genre = if i % 2 == 0 { "poetry" } else { "novel" };
+                let metadata = HashMap::from([("genre".to_string(), genre.to_string()), ("title".to_string(), format!("book {}", i))]);
+                Embedding { id, vector: vec![1.0, i as f32], metadata: Some(metadata), ..Default::default() }
+            })
+            .collect();
+        db.update_collection("books", embeddings.clone()).unwrap();
+        db.delete_embeddings("books", vec![embeddings[0].id.clone()]).unwrap();
+        let books = db.get_embeddings("books").unwrap();
+
+        // Renaming moves the files and the aliases along with the collection.
+        let alias = AliasAction::CreateAlias { alias: "library".to_string(), collection_name: "books".to_string() };
+        db.update_aliases(vec![alias]).unwrap();
+        assert_eq!(db.rename_collection("books", "movies".to_string()), Err(Error::UniqueViolation));
+        assert_eq!(db.rename_collection("books", "library".to_string()), Err(Error::AliasConflict));
+        assert_eq!(db.rename_collection("missing", "shelf".to_string()), Err(Error::NotFound));
+        db.rename_collection("library", "shelf".to_string()).unwrap();
+        assert!(!db.collections.contains_key("books"));
+        assert_eq!(db.resolve("library"), "shelf");
+        assert!(!dir.path().join("collections").join("books").exists());
+        db.insert_into_collection("shelf", embeddings[0].clone()).unwrap();
+        db.delete_embeddings("shelf", vec![embeddings[0].id.clone()]).unwrap();
+        assert_eq!(db.get_embeddings("library"), Ok(books.clone()));
+
+        // Clones copy the live embeddings matching the filter, with the settings and text index of the source.
+        let poetry = HashMap::from([("genre".to_string(), "poetry".to_string())]);
+        db.clone_collection("library", "poetry".to_string(), Some(poetry)).unwrap();
+        db.clone_collection("shelf", "copy".to_string(), None).unwrap();
+        assert_eq!(db.clone_collection("shelf", "copy".to_string(), None), Err(Error::UniqueViolation));
+        let poetry = &db.collections["poetry"];
+        assert!(poetry.store.is_some());
+        assert_eq!(poetry.all_embeddings(), Ok(vec![books[1].clone()]));
+        let copy = &db.collections["copy"];
+        assert!(copy.tombstones.is_empty());
+        assert_eq!(copy.all_embeddings(), Ok(books.clone()));
+        let results = copy.text_index.as_ref().unwrap().search("novel", 5, |_| true);
+        assert_eq!(results.len(), 2);
+
+        // Changing the storage rebuilds the collection, and the text index can be replaced or dropped.
+        let changes = CollectionChanges { text_index_field: Some("title".to_string()), drop_text_index: true, ..CollectionChanges::default() };
+        assert_eq!(db.alter_collection("copy", changes), Err(Error::ConflictingChanges));
+        let changes = CollectionChanges { storage: Some(Storage::Mmap), ..CollectionChanges::default() };
+        let sparse = CollectionOptions { vector_type: VectorType::Sparse, ..CollectionOptions::default() };
+        db.create_collection("sparse".to_string(), 2, Distance::DotProduct, sparse).unwrap();
+        assert_eq!(db.alter_collection("sparse", changes), Err(Error::UnsupportedStorage));
+
+        let changes = CollectionChanges { storage: Some(Storage::Memory), text_index_field: Some("title".to_string()), ..CollectionChanges::default() };
+        db.alter_collection("shelf", changes).unwrap();
+        let shelf = &db.collections["shelf"];
+        assert!(shelf.store.is_none() && shelf.tombstones.is_empty());
+        assert_eq!(shelf.options.storage, Storage::Memory);
+        assert_eq!(shelf.all_embeddings(), Ok(books.clone()));
+        assert_eq!(shelf.text_index.as_ref().map(|index| index.field.as_str()), Some("title"));
+        assert!(!dir.path().join("collections").join("shelf").exists());
+
+        db.alter_collection("copy", CollectionChanges { drop_text_index: true, ..CollectionChanges::default() }).unwrap();
+        assert!(db.collections["copy"].text_index.is_none());
+        db.alter_collection("movies", CollectionChanges { text_index_field: Some("title".to_string()), ..CollectionChanges::default() }).unwrap();
+        db.update_collection("movies", embeddings[..1].to_vec()).unwrap();
+        assert_eq!(db.collections["movies"].text_index.as_ref().unwrap().search("book", 5, |_| true).len(), 1);
+    }
+
 }
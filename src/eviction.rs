@@ -0,0 +1,386 @@
+use crate::metrics::{EVICTIONS, LIMIT_REJECTIONS};
+use crate::data_log::LogRecord;
+use crate::model::{CacheDB, Collection, Embedding, Error, EvictionPolicy, Limits};
+use crate::tenant::tenant_of;
+use log::{error, info};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// A logical clock shared by every collection, so that usage can be compared across collections.
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+fn tick() -> u64 {
+    CLOCK.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// How an embedding has been used, in ticks of the shared clock.
+#[derive(Debug, Clone, Copy)]
+pub struct Access {
+    inserted: u64,
+    last_used: u64,
+    hits: u64,
+}
+
+impl Access {
+    /// The rank of the embedding under `policy`, the lowest evicted first.
+    fn rank(&self, policy: EvictionPolicy) -> (u64, u64) {
+        match policy {
+            EvictionPolicy::RejectWrites | EvictionPolicy::Fifo => (self.inserted, 0),
+            EvictionPolicy::Lru => (self.last_used, 0),
+            EvictionPolicy::Lfu => (self.hits, self.last_used),
+        }
+    }
+}
+
+/// The rank of an embedding followed by its position.
+type Entry = ((u64, u64), usize);
+
+#[derive(Debug, Clone, Default)]
+struct UsageState {
+    policy: EvictionPolicy,
+    accesses: HashMap<usize, Access>,
+    /// The positions in `accesses`, in the order the policy evicts them.
+    order: BTreeSet<Entry>,
+}
+
+impl UsageState {
+    fn set(&mut self, position: usize, access: Access) {
+        if let Some(previous) = self.accesses.insert(position, access) {
+            self.order.remove(&(previous.rank(self.policy), position));
+        }
+        self.order.insert((access.rank(self.policy), position));
+    }
+}
+
+/// How the embeddings of a collection that aren't deleted are used, in the order its eviction
+/// policy evicts them. Nothing is tracked for collections that reject writes over their limits.
+///
+/// Usage isn't persisted, so after a restore embeddings are ranked as if just inserted. Searches
+/// record hits through a shared reference, so the state is kept behind a lock.
+#[derive(Debug, Default)]
+pub struct Usage(Mutex<UsageState>);
+
+impl Clone for Usage {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.state().clone()))
+    }
+}
+
+/// Usages are equal when they track the same embeddings under the same policy, however they were
+/// used, since a restore doesn't reproduce the searches.
+impl PartialEq for Usage {
+    fn eq(&self, other: &Self) -> bool {
+        if std::ptr::eq(self, other) {
+            return true;
+        }
+        let (state, other) = (self.state(), other.state());
+        state.policy == other.policy && state.accesses.keys().collect::<HashSet<_>>() == other.accesses.keys().collect::<HashSet<_>>()
+    }
+}
+
+impl Usage {
+    pub fn new(policy: EvictionPolicy) -> Self {
+        Self(Mutex::new(UsageState { policy, ..UsageState::default() }))
+    }
+
+    fn state(&self) -> MutexGuard<'_, UsageState> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn policy(&self) -> EvictionPolicy {
+        self.state().policy
+    }
+
+    /// Start tracking a newly inserted embedding.
+    pub fn insert(&mut self, position: usize) {
+        let state = self.0.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if state.policy != EvictionPolicy::RejectWrites {
+            let now = tick();
+            state.set(position, Access { inserted: now, last_used: now, hits: 0 });
+        }
+    }
+
+    pub fn remove(&mut self, position: usize) {
+        let state = self.0.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(access) = state.accesses.remove(&position) {
+            state.order.remove(&(access.rank(state.policy), position));
+        }
+    }
+
+    /// Record that a search returned the embedding at `position`.
+    pub fn touch(&self, position: usize) {
+        let mut state = self.state();
+        if let Some(&access) = state.accesses.get(&position) {
+            state.set(position, Access { last_used: tick(), hits: access.hits + 1, ..access });
+        }
+    }
+
+    /// The usage with every position moved by `f`, leaving out those it maps to `None`.
+    pub fn remap(&self, f: impl Fn(usize) -> Option<usize>) -> Self {
+        let state = self.state();
+        let mut remapped = UsageState { policy: state.policy, ..UsageState::default() };
+        for (&position, &access) in &state.accesses {
+            if let Some(position) = f(position) {
+                remapped.set(position, access);
+            }
+        }
+        Self(Mutex::new(remapped))
+    }
+
+    /// The next embedding to evict after `after`, with how it was used.
+    fn next(&self, after: Option<Entry>) -> Option<(Entry, Access)> {
+        let state = self.state();
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let entry = *state.order.range((start, Bound::Unbounded)).next()?;
+        Some((entry, state.accesses[&entry.1]))
+    }
+}
+
+/// The embeddings of one collection that may be evicted, in the order its policy evicts them.
+struct Candidates<'a> {
+    name: &'a str,
+    collection: &'a Collection,
+    cursor: Option<Entry>,
+    /// Positions that can't be evicted, since they are evicted already or replaced by the write.
+    excluded: HashSet<usize>,
+    /// Whether the limits of the database may evict from the collection, see `plan_evictions`.
+    shared: bool,
+}
+
+impl Candidates<'_> {
+    fn peek(&self) -> Option<(Entry, Access)> {
+        let mut cursor = self.cursor;
+        loop {
+            let (entry, access) = self.collection.usage.next(cursor)?;
+            if !self.excluded.contains(&entry.1) {
+                return Some((entry, access));
+            }
+            cursor = Some(entry);
+        }
+    }
+
+    /// Evict the embedding `peek` returned, giving its estimated size.
+    fn take(&mut self, entry: Entry) -> Result<usize, Error> {
+        self.cursor = Some(entry);
+        self.excluded.insert(entry.1);
+        Ok(self.collection.embedding_at(entry.1)?.estimated_memory_bytes())
+    }
+}
+
+/// Whether a count and size of embeddings go over some limits.
+fn over(limits: &Limits, count: usize, bytes: usize) -> bool {
+    limits.max_vectors.is_some_and(|max| count > max) || limits.max_memory_bytes.is_some_and(|max| bytes > max)
+}
+
+/// The count and size of the embeddings stored in the collections of `tenant`.
+fn tenant_usage(db: &CacheDB, tenant: &str) -> (usize, usize) {
+    db.collections
+        .iter()
+        .filter(|(name, _)| tenant_of(name) == Some(tenant))
+        .fold((0, 0), |(count, bytes), (_, collection)| (count + collection.stored_count(), bytes + collection.live_bytes))
+}
+
+/// Whether the tenant has a quota and its collections already go over it.
+fn over_quota(db: &CacheDB, tenant: &str) -> bool {
+    let Some(quota) = db.quotas.get(tenant) else {
+        return false;
+    };
+    let limits = Limits { max_vectors: quota.max_vectors, max_memory_bytes: quota.max_memory_bytes, eviction_policy: None };
+    let (count, bytes) = tenant_usage(db, tenant);
+    over(&limits, count, bytes)
+}
+
+fn reject(collection_name: &str, scope: &str) -> Error {
+    error!("Write to collection '{}' rejected for going over the {} limits", collection_name, scope);
+    LIMIT_REJECTIONS.with_label_values(&[collection_name]).inc();
+    Error::LimitExceeded
+}
+
+/// Choose the embeddings to evict to make room for writing `added` into a collection, first under
+/// the limits of the collection and then under those of the database. In between, the write is
+/// rejected if it would go over the quota of the collection's tenant.
+///
+/// Global evictions take the embedding each collection would evict next, and evict whichever the
+/// policy of the database ranks first. Collections that reject writes over their limits are left alone,
+/// and so are those of other tenants, so that one tenant's writes can't push out another's embeddings.
+/// The exception is a tenant whose collections already go over its quota, for instance after the
+/// quota was lowered. Collections without a tenant are a namespace of their own.
+///
+/// # Arguments
+///
+/// * `db`: The database to write to.
+/// * `collection_name`: The name of the collection to write to.
+/// * `added`: The embeddings to write.
+/// * `replaced`: The positions of expired embeddings the write replaces, which free their room anyway.
+///
+/// # Returns
+///
+/// A result containing the positions to evict by collection, or an error if the write can't fit,
+/// either because the policy rejects it or because evicting everything allowed isn't enough.
+pub fn plan_evictions(db: &CacheDB, collection_name: &str, added: &[Embedding], replaced: &[usize]) -> Result<Vec<(String, Vec<usize>)>, Error> {
+    let Some(target) = db.collections.get(collection_name) else {
+        return Err(Error::NotFound);
+    };
+    let added_bytes: usize = added.iter().map(Embedding::estimated_memory_bytes).sum();
+    let mut replaced_bytes = 0;
+    for &position in replaced {
+        replaced_bytes += target.embedding_at(position)?.estimated_memory_bytes();
+    }
+
+    let mut candidates: Vec<Candidates> = db.collections
+        .iter()
+        .filter(|(_, collection)| collection.usage.policy() != EvictionPolicy::RejectWrites)
+        .map(|(name, collection)| {
+            let excluded = if name == collection_name { replaced.iter().copied().collect() } else { HashSet::new() };
+            let tenant = tenant_of(name);
+            let shared = tenant == tenant_of(collection_name) || tenant.is_some_and(|tenant| over_quota(db, tenant));
+            Candidates { name, collection, cursor: None, excluded, shared }
+        })
+        .collect();
+    candidates.sort_by_key(|candidates| candidates.name);
+
+    // Make room in the collection first.
+    let mut count = target.stored_count() + added.len() - replaced.len();
+    let mut bytes = target.live_bytes + added_bytes - replaced_bytes;
+    let (mut evicted_count, mut evicted_bytes) = (0, 0);
+    let limits = &target.options.limits;
+    if over(limits, count, bytes) {
+        let Some(own) = candidates.iter_mut().find(|candidates| candidates.name == collection_name) else {
+            return Err(reject(collection_name, "collection"));
+        };
+        while over(limits, count, bytes) {
+            let Some((entry, _)) = own.peek() else {
+                return Err(reject(collection_name, "collection"));
+            };
+            let size = own.take(entry)?;
+            count -= 1;
+            bytes -= size;
+            evicted_count += 1;
+            evicted_bytes += size;
+        }
+    }
+
+    // Then check the quota of the tenant, which is never made room for.
+    if let Some(quota) = tenant_of(collection_name).and_then(|tenant| db.quotas.get(tenant).map(|quota| (tenant, quota))) {
+        let (tenant, quota) = quota;
+        let (stored_count, stored_bytes) = tenant_usage(db, tenant);
+        let limits = Limits { max_vectors: quota.max_vectors, max_memory_bytes: quota.max_memory_bytes, eviction_policy: None };
+        let count = stored_count + added.len() - replaced.len() - evicted_count;
+        let bytes = stored_bytes + added_bytes - replaced_bytes - evicted_bytes;
+        if over(&limits, count, bytes) {
+            return Err(reject(collection_name, "tenant"));
+        }
+    }
+
+    // Then in the database, comparing the next embedding of each collection under the database's policy.
+    let mut total_count = db.collections.values().map(Collection::stored_count).sum::<usize>() + added.len() - replaced.len() - evicted_count;
+    let mut total_bytes = db.collections.values().map(|collection| collection.live_bytes).sum::<usize>() + added_bytes - replaced_bytes - evicted_bytes;
+    if over(&db.limits, total_count, total_bytes) {
+        let policy = db.limits.eviction_policy.unwrap_or_default();
+        if policy == EvictionPolicy::RejectWrites {
+            return Err(reject(collection_name, "database"));
+        }
+        while over(&db.limits, total_count, total_bytes) {
+            let next = candidates
+                .iter()
+                .enumerate()
+                .filter(|(_, candidates)| candidates.shared)
+                .filter_map(|(index, candidates)| candidates.peek().map(|(entry, access)| (access.rank(policy), index, entry)))
+                .min();
+            let Some((_, index, entry)) = next else {
+                return Err(reject(collection_name, "database"));
+            };
+            total_bytes -= candidates[index].take(entry)?;
+            total_count -= 1;
+        }
+    }
+
+    Ok(candidates
+        .into_iter()
+        .map(|candidates| {
+            let mut positions: Vec<usize> = candidates.excluded.into_iter().collect();
+            if candidates.name == collection_name {
+                positions.retain(|position| !replaced.contains(position));
+            }
+            positions.sort_unstable();
+            (candidates.name.to_string(), positions)
+        })
+        .filter(|(_, positions)| !positions.is_empty())
+        .collect())
+}
+
+/// The deletes of the embeddings chosen by `plan_evictions`, which are logged together with the
+/// write they make room for, so that a restore reproduces them and a failed write evicts nothing.
+pub fn eviction_records(db: &CacheDB, plan: &[(String, Vec<usize>)]) -> Result<Vec<LogRecord>, Error> {
+    let mut records = Vec::with_capacity(plan.len());
+    for (name, positions) in plan {
+        let Some(collection) = db.collections.get(name) else {
+            return Err(Error::NotFound);
+        };
+        let ids = positions
+            .iter()
+            .map(|&position| collection.embedding_at(position).map(|embedding| embedding.id))
+            .collect::<Result<Vec<_>, _>>()?;
+        records.push(LogRecord::DeleteEmbeddings { collection_name: name.clone(), ids });
+    }
+    Ok(records)
+}
+
+/// Delete the embeddings chosen by `plan_evictions`, once their `eviction_records` are logged.
+pub fn evict(db: &mut CacheDB, plan: Vec<(String, Vec<usize>)>) -> Result<(), Error> {
+    for (name, positions) in plan {
+        let Some(collection) = db.collections.get_mut(&name) else {
+            return Err(Error::NotFound);
+        };
+        let policy = collection.usage.policy();
+        let count = positions.len();
+        collection.delete_positions(positions)?;
+        EVICTIONS.with_label_values(&[name.as_str(), policy.as_str()]).inc_by(count as u64);
+        info!("Evicted {} embeddings from collection '{}' with policy {}", count, name, policy.as_str());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_order() {
+        for (policy, expected) in [
+            (EvictionPolicy::Fifo, vec![0, 1, 2]),
+            (EvictionPolicy::Lru, vec![2, 1, 0]),
+            (EvictionPolicy::Lfu, vec![2, 0, 1]),
+        ] {
+            let mut usage = Usage::new(policy);
+            for position in 0..3 {
+                usage.insert(position);
+            }
+            usage.touch(1);
+            usage.touch(1);
+            usage.touch(1);
+            usage.touch(0);
+            let mut order = Vec::new();
+            let mut cursor = None;
+            while let Some((entry, _)) = usage.next(cursor) {
+                order.push(entry.1);
+                cursor = Some(entry);
+            }
+            assert_eq!(order, expected, "{:?}", policy);
+        }
+
+        // Nothing is tracked when writes over the limits are rejected.
+        let mut usage = Usage::new(EvictionPolicy::RejectWrites);
+        usage.insert(0);
+        assert!(usage.next(None).is_none());
+
+        let mut usage = Usage::new(EvictionPolicy::Fifo);
+        usage.insert(3);
+        usage.insert(5);
+        usage.remove(3);
+        let remapped = usage.remap(|position| Some(position - 1));
+        assert_eq!(remapped.next(None).map(|(entry, _)| entry.1), Some(4));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::model::CacheDB;
+use crate::readiness::RestoreProgress;
+use log::{error, info};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The current time in seconds since the Unix epoch, which expiry times are given in.
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// When the embeddings of a collection expire, by position and in order of expiry.
+///
+/// Only embeddings that aren't deleted are tracked, so an expired one is dropped once the sweeper
+/// deletes it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Expiries {
+    by_position: HashMap<usize, u64>,
+    queue: BTreeSet<(u64, usize)>,
+}
+
+impl Expiries {
+    pub fn insert(&mut self, position: usize, expires_at: u64) {
+        if let Some(previous) = self.by_position.insert(position, expires_at) {
+            self.queue.remove(&(previous, position));
+        }
+        self.queue.insert((expires_at, position));
+    }
+
+    pub fn remove(&mut self, position: usize) {
+        if let Some(expires_at) = self.by_position.remove(&position) {
+            self.queue.remove(&(expires_at, position));
+        }
+    }
+
+    /// Whether the embedding at `position` has expired, which searches and reads treat as deleted.
+    pub fn is_expired(&self, position: usize) -> bool {
+        self.by_position.get(&position).is_some_and(|&expires_at| expires_at <= unix_now())
+    }
+
+    /// The positions of the embeddings expired at `now`, soonest expired first.
+    pub fn expired(&self, now: u64) -> Vec<usize> {
+        self.queue.range(..(now.saturating_add(1), 0)).map(|&(_, position)| position).collect()
+    }
+
+    /// The expiries with every position moved by `f`, leaving out those it maps to `None`.
+    pub fn remap(&self, f: impl Fn(usize) -> Option<usize>) -> Self {
+        let mut remapped = Self::default();
+        for (&position, &expires_at) in &self.by_position {
+            if let Some(position) = f(position) {
+                remapped.insert(position, expires_at);
+            }
+        }
+        remapped
+    }
+}
+
+/// Delete the expired embeddings of every collection every `interval` on a background thread,
+/// once the database is restored.
+pub fn spawn_expiry_sweeper(db: Arc<Mutex<CacheDB>>, interval: Duration, progress: Arc<RestoreProgress>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        // Sweeping during the restore could delete embeddings that records still to replay refer to.
+        if !progress.is_ready() {
+            continue;
+        }
+        let Ok(mut db) = db.lock() else {
+            return;
+        };
+        sweep(&mut db);
+    });
+}
+
+/// Delete the expired embeddings of every collection.
+///
+/// The deletes are logged like any other, so a restore removes the same embeddings at the same point.
+pub fn sweep(db: &mut CacheDB) {
+    let now = unix_now();
+    let mut expired = Vec::new();
+    for (name, collection) in &db.collections {
+        let ids = collection.expiries.expired(now).into_iter().map(|position| collection.embedding_at(position).map(|embedding| embedding.id));
+        match ids.collect::<Result<Vec<_>, _>>() {
+            Ok(ids) if ids.is_empty() => {}
+            Ok(ids) => expired.push((name.clone(), ids)),
+            Err(err) => error!("Failed to read the expired embeddings of collection '{}': {}", name, err),
+        }
+    }
+    for (name, ids) in expired {
+        let count = ids.len();
+        match db.delete_embeddings(&name, ids) {
+            Ok(()) => info!("Deleted {} expired embeddings from collection '{}'", count, name),
+            Err(err) => error!("Failed to delete the expired embeddings of collection '{}': {}", name, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiries() {
+        let now = unix_now();
+        let mut expiries = Expiries::default();
+        expiries.insert(0, now + 60);
+        expiries.insert(1, now - 10);
+        expiries.insert(2, now - 20);
+        expiries.insert(3, now);
+
+        assert!(!expiries.is_expired(0));
+        assert!(expiries.is_expired(1));
+        assert!(!expiries.is_expired(4));
+        assert_eq!(expiries.expired(now), vec![2, 1, 3]);
+
+        expiries.remove(2);
+        expiries.insert(3, now + 30);
+        assert_eq!(expiries.expired(now), vec![1]);
+        assert_eq!(expiries.expired(now + 60), vec![1, 3, 0]);
+
+        let remapped = expiries.remap(|position| position.checked_sub(1));
+        assert_eq!(remapped.expired(now + 60), vec![0, 2]);
+    }
+}
@@ -0,0 +1,470 @@
+// The service methods tonic generates fail with `Status`, so the helpers they call do too.
+#![allow(clippy::result_large_err)]
+
+use crate::auth::{api_key, authorize_body, impl_collection_scoped, AuthConfig, AuthError, CollectionScoped, Role};
+use crate::metrics;
+use crate::model::{CacheDB, CollectionOptions, Distance, Embedding, Error, SearchParams, SimilarityResult};
+use crate::readiness::RestoreProgress;
+use crate::shutdown::{requested, run_until_drained};
+use crate::tenant;
+use crate::tls::TlsConfig;
+use log::info;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tonic::metadata::MetadataMap;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic::{Code, Request, Response, Status, Streaming};
+
+pub mod proto {
+    tonic::include_proto!("memvectordb.v1");
+}
+
+use proto::vector_db_server::{VectorDb, VectorDbServer};
+
+/// How many embeddings each message of an export holds.
+const EXPORT_CHUNK: usize = 1000;
+
+impl_collection_scoped!(
+    proto::CreateCollectionRequest,
+    proto::DeleteCollectionRequest,
+    proto::GetCollectionRequest,
+    proto::InsertRequest,
+    proto::SearchRequest,
+    proto::ImportRequest,
+    proto::ExportRequest
+);
+
+impl CollectionScoped for proto::BatchSearchRequest {
+    /// The collection of every search, since a key must be allowed to search all of them.
+    fn collection_names(&self) -> Vec<&str> {
+        self.searches.iter().map(|search| search.collection_name.as_str()).collect()
+    }
+
+    fn collection_names_mut(&mut self) -> Vec<&mut String> {
+        self.searches.iter_mut().map(|search| &mut search.collection_name).collect()
+    }
+}
+
+/// The gRPC API, sharing the database, API keys and restore progress of the REST routes.
+pub struct VectorDbService {
+    db: Arc<Mutex<CacheDB>>,
+    auth: Arc<AuthConfig>,
+    progress: Arc<RestoreProgress>,
+}
+
+impl VectorDbService {
+    pub fn new(db: Arc<Mutex<CacheDB>>, auth: Arc<AuthConfig>, progress: Arc<RestoreProgress>) -> Self {
+        Self { db, auth, progress }
+    }
+
+    /// Check that the database is restored and that the API key in `metadata` grants `role` on every
+    /// collection `message` names, then move those names into the namespace of the selected tenant.
+    fn authorize<T: CollectionScoped>(&self, metadata: &MetadataMap, role: Role, message: &mut T) -> Result<(), Status> {
+        if !self.progress.is_ready() {
+            return Err(Status::unavailable("The database is being restored"));
+        }
+        let entry = |name| metadata.get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+        let key = api_key(entry("authorization"), entry("x-api-key"));
+        authorize_body(&self.auth, key.as_deref(), role, entry("x-tenant").as_deref(), message).map_err(auth_status)
+    }
+
+    fn lock_db(&self) -> Result<MutexGuard<'_, CacheDB>, Status> {
+        let start = Instant::now();
+        let db_lock = self.db.lock().map_err(|_| Status::internal("The database is unavailable"));
+        metrics::LOCK_WAIT.observe(start.elapsed().as_secs_f64());
+        db_lock
+    }
+}
+
+#[tonic::async_trait]
+impl VectorDb for VectorDbService {
+    async fn create_collection(&self, request: Request<proto::CreateCollectionRequest>) -> Result<Response<proto::CreateCollectionResponse>, Status> {
+        let (metadata, _, mut message) = request.into_parts();
+        self.authorize(&metadata, Role::Admin, &mut message)?;
+        let distance = distance(message.distance)?;
+        let options = CollectionOptions { ttl_secs: message.ttl_secs, partition_key: message.partition_key, ..CollectionOptions::default() };
+
+        let mut db_lock = self.lock_db()?;
+        db_lock.create_collection(message.collection_name, message.dimension as usize, distance, options).map_err(status)?;
+        Ok(Response::new(proto::CreateCollectionResponse {}))
+    }
+
+    async fn delete_collection(&self, request: Request<proto::DeleteCollectionRequest>) -> Result<Response<proto::DeleteCollectionResponse>, Status> {
+        let (metadata, _, mut message) = request.into_parts();
+        self.authorize(&metadata, Role::Admin, &mut message)?;
+
+        let mut db_lock = self.lock_db()?;
+        db_lock.delete_collection(&message.collection_name).map_err(status)?;
+        Ok(Response::new(proto::DeleteCollectionResponse {}))
+    }
+
+    async fn get_collection(&self, request: Request<proto::GetCollectionRequest>) -> Result<Response<proto::CollectionInfo>, Status> {
+        let (metadata, _, mut message) = request.into_parts();
+        self.authorize(&metadata, Role::Read, &mut message)?;
+
+        let db_lock = self.lock_db()?;
+        let collection = db_lock.get_collection(&message.collection_name).ok_or_else(|| status(Error::NotFound))?;
+        Ok(Response::new(proto::CollectionInfo {
+            collection_name: tenant::unqualify(&message.collection_name).to_string(),
+            dimension: collection.dimension as u32,
+            distance: proto::Distance::from(collection.distance).into(),
+            vector_count: collection.embedding_count() as u64,
+            ttl_secs: collection.options.ttl_secs,
+            partition_key: collection.options.partition_key.clone(),
+        }))
+    }
+
+    async fn insert(&self, request: Request<proto::InsertRequest>) -> Result<Response<proto::InsertResponse>, Status> {
+        let (metadata, _, mut message) = request.into_parts();
+        self.authorize(&metadata, Role::ReadWrite, &mut message)?;
+        let count = message.embeddings.len() as u64;
+
+        let mut db_lock = self.lock_db()?;
+        db_lock.update_collection(&message.collection_name, embeddings(message.embeddings)).map_err(status)?;
+        Ok(Response::new(proto::InsertResponse { count }))
+    }
+
+    async fn upsert(&self, request: Request<proto::InsertRequest>) -> Result<Response<proto::InsertResponse>, Status> {
+        let (metadata, _, mut message) = request.into_parts();
+        self.authorize(&metadata, Role::ReadWrite, &mut message)?;
+        let count = message.embeddings.len() as u64;
+
+        let mut db_lock = self.lock_db()?;
+        db_lock.upsert_embeddings(&message.collection_name, embeddings(message.embeddings)).map_err(status)?;
+        Ok(Response::new(proto::InsertResponse { count }))
+    }
+
+    async fn search(&self, request: Request<proto::SearchRequest>) -> Result<Response<proto::SearchResponse>, Status> {
+        let (metadata, _, mut message) = request.into_parts();
+        self.authorize(&metadata, Role::Read, &mut message)?;
+
+        let db_lock = self.lock_db()?;
+        Ok(Response::new(search(&db_lock, message)?))
+    }
+
+    async fn batch_search(&self, request: Request<proto::BatchSearchRequest>) -> Result<Response<proto::BatchSearchResponse>, Status> {
+        let (metadata, _, mut message) = request.into_parts();
+        self.authorize(&metadata, Role::Read, &mut message)?;
+
+        let db_lock = self.lock_db()?;
+        let responses = message.searches.into_iter().map(|search_request| search(&db_lock, search_request)).collect::<Result<_, _>>()?;
+        Ok(Response::new(proto::BatchSearchResponse { responses }))
+    }
+
+    async fn import(&self, request: Request<Streaming<proto::ImportRequest>>) -> Result<Response<proto::ImportResponse>, Status> {
+        let (metadata, _, mut stream) = request.into_parts();
+        let mut count = 0;
+        // Each message is inserted on its own, so the ones before a failure stay imported.
+        while let Some(mut message) = stream.message().await? {
+            let imported = self.authorize(&metadata, Role::ReadWrite, &mut message).and_then(|()| {
+                let mut db_lock = self.lock_db()?;
+                let size = message.embeddings.len() as u64;
+                db_lock.update_collection(&message.collection_name, embeddings(message.embeddings)).map_err(status)?;
+                Ok(size)
+            });
+            match imported {
+                Ok(size) => count += size,
+                Err(err) => return Err(Status::new(err.code(), format!("{} after importing {} embeddings", err.message(), count))),
+            }
+        }
+        Ok(Response::new(proto::ImportResponse { count }))
+    }
+
+    type ExportStream = tokio_stream::Iter<std::vec::IntoIter<Result<proto::ExportResponse, Status>>>;
+
+    async fn export(&self, request: Request<proto::ExportRequest>) -> Result<Response<Self::ExportStream>, Status> {
+        let (metadata, _, mut message) = request.into_parts();
+        self.authorize(&metadata, Role::Read, &mut message)?;
+
+        // The embeddings are copied under the lock, so the export is consistent however slowly it is read.
+        let embeddings = self.lock_db()?.get_embeddings(&message.collection_name).map_err(status)?;
+        let chunks: Vec<_> = embeddings
+            .chunks(EXPORT_CHUNK)
+            .map(|chunk| Ok(proto::ExportResponse { embeddings: chunk.iter().cloned().map(proto::Embedding::from).collect() }))
+            .collect();
+        Ok(Response::new(tokio_stream::iter(chunks)))
+    }
+}
+
+/// Run one search, with its collection name already in the namespace of the tenant.
+fn search(db: &CacheDB, request: proto::SearchRequest) -> Result<proto::SearchResponse, Status> {
+    let collection = db.get_collection(&request.collection_name).ok_or_else(|| status(Error::NotFound))?;
+    let params = SearchParams {
+        offset: request.offset as usize,
+        score_threshold: request.score_threshold,
+        with_vector: request.with_vector,
+        with_metadata: request.with_metadata,
+        using: request.using,
+        partition: request.partition,
+    };
+
+    let _timer = metrics::SEARCH_DURATION.with_label_values(&[db.resolve(&request.collection_name), "similarity"]).start_timer();
+    let results = collection.get_similarity(&request.vector, request.k as usize, &params).map_err(status)?;
+    let results = results
+        .into_iter()
+        .map(|SimilarityResult { score, embedding }| proto::ScoredEmbedding { score, embedding: Some(embedding.into()) })
+        .collect();
+    Ok(proto::SearchResponse { results })
+}
+
+fn distance(value: i32) -> Result<Distance, Status> {
+    match proto::Distance::try_from(value) {
+        Ok(proto::Distance::Cosine) => Ok(Distance::Cosine),
+        Ok(proto::Distance::Euclidean) => Ok(Distance::Euclidean),
+        Ok(proto::Distance::DotProduct) => Ok(Distance::DotProduct),
+        Ok(proto::Distance::Unspecified) | Err(_) => Err(Status::invalid_argument("A distance is required")),
+    }
+}
+
+impl From<Distance> for proto::Distance {
+    fn from(distance: Distance) -> Self {
+        match distance {
+            Distance::Cosine => proto::Distance::Cosine,
+            Distance::Euclidean => proto::Distance::Euclidean,
+            Distance::DotProduct => proto::Distance::DotProduct,
+        }
+    }
+}
+
+fn embeddings(embeddings: Vec<proto::Embedding>) -> Vec<Embedding> {
+    embeddings.into_iter().map(Embedding::from).collect()
+}
+
+/// An embedding with only a dense vector. Empty metadata is the same as none.
+impl From<proto::Embedding> for Embedding {
+    fn from(embedding: proto::Embedding) -> Self {
+        Embedding {
+            id: embedding.id,
+            vector: embedding.vector,
+            metadata: Some(embedding.metadata).filter(|metadata| !metadata.is_empty()),
+            ttl_secs: embedding.ttl_secs,
+            expires_at: embedding.expires_at,
+            ..Default::default()
+        }
+    }
+}
+
+/// The dense vector and fields of an embedding. Sparse, named and multivectors aren't part of the gRPC API.
+impl From<Embedding> for proto::Embedding {
+    fn from(embedding: Embedding) -> Self {
+        proto::Embedding {
+            id: embedding.id,
+            vector: embedding.vector,
+            metadata: embedding.metadata.unwrap_or_default(),
+            ttl_secs: embedding.ttl_secs,
+            expires_at: embedding.expires_at,
+        }
+    }
+}
+
+/// The gRPC status of a failed operation, with the codes matching the status codes of the REST routes.
+fn status(err: Error) -> Status {
+    let code = match err {
+        Error::NotFound | Error::EmbeddingNotFound | Error::TextIndexNotFound | Error::VectorNotFound | Error::AliasNotFound => Code::NotFound,
+        Error::UniqueViolation | Error::EmbeddingUniqueViolation | Error::TextIndexUniqueViolation | Error::AliasConflict => Code::AlreadyExists,
+        Error::LimitExceeded => Code::ResourceExhausted,
+        Error::DataLog | Error::Storage => Code::Internal,
+        _ => Code::InvalidArgument,
+    };
+    Status::new(code, format!("{:?}", err))
+}
+
+fn auth_status(err: AuthError) -> Status {
+    match err {
+        AuthError::MissingKey => Status::unauthenticated("Missing API key"),
+        AuthError::InvalidKey => Status::unauthenticated("Invalid API key"),
+        AuthError::Forbidden => Status::permission_denied("API key isn't allowed to perform this operation"),
+        AuthError::UnknownTenant => Status::not_found("Unknown tenant"),
+        AuthError::ConflictingTenants => Status::invalid_argument("The path and the x-tenant entry select different tenants"),
+        AuthError::InvalidName => Status::invalid_argument("Collection and alias names can't contain '/'"),
+    }
+}
+
+/// Serve the gRPC API on `addr`, over TLS when `tls` is configured, until `shutdown` is set and
+/// in-flight requests are drained or `drain_timeout` passes.
+///
+/// Unlike the HTTPS server, the gRPC server reads the certificates once at startup.
+pub async fn serve(service: VectorDbService, addr: SocketAddr, tls: Option<TlsConfig>, shutdown: watch::Receiver<bool>, drain_timeout: Duration) {
+    let mut builder = Server::builder();
+    if let Some(tls) = tls {
+        let material = tls.load().unwrap_or_else(|err| panic!("Failed to read TLS certificates: {}", err));
+        let mut config = ServerTlsConfig::new().identity(Identity::from_pem(material.cert, material.key));
+        if let Some(client_ca) = material.client_ca {
+            config = config.client_ca_root(Certificate::from_pem(client_ca));
+        }
+        builder = builder.tls_config(config).unwrap_or_else(|err| panic!("Failed to configure TLS for gRPC: {}", err));
+    }
+
+    info!("Serving gRPC on {}", addr);
+    let server = builder.add_service(VectorDbServer::new(service)).serve_with_shutdown(addr, requested(shutdown.clone()));
+    let server = async {
+        if let Err(err) = server.await {
+            panic!("Failed to serve gRPC on {}: {}", addr, err);
+        }
+    };
+    run_until_drained(server, shutdown, drain_timeout).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::vector_db_client::VectorDbClient;
+    use std::collections::HashMap;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Channel;
+
+    fn service(auth: AuthConfig, ready: bool) -> (VectorDbService, Arc<Mutex<CacheDB>>) {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let progress = Arc::new(RestoreProgress::default());
+        if ready {
+            progress.set_ready();
+        }
+        (VectorDbService::new(db.clone(), Arc::new(auth), progress), db)
+    }
+
+    async fn connect(service: VectorDbService) -> VectorDbClient<Channel> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::builder().add_service(VectorDbServer::new(service)).serve_with_incoming(TcpListenerStream::new(listener)));
+        VectorDbClient::connect(format!("http://{}", addr)).await.unwrap()
+    }
+
+    fn embedding(unique_id: usize, vector: Vec<f32>) -> proto::Embedding {
+        let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+        proto::Embedding { id, vector, ..Default::default() }
+    }
+
+    fn search_request(collection_name: &str, vector: Vec<f32>, k: u32) -> proto::SearchRequest {
+        proto::SearchRequest { collection_name: collection_name.to_string(), vector, k, with_vector: true, ..Default::default() }
+    }
+
+    fn ids(response: &proto::SearchResponse) -> Vec<String> {
+        response.results.iter().map(|result| result.embedding.as_ref().unwrap().id["unique_id"].clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_collections_and_search() {
+        let (service, _) = service(AuthConfig::default(), true);
+        let mut client = connect(service).await;
+
+        let create = proto::CreateCollectionRequest {
+            collection_name: "books".to_string(),
+            dimension: 2,
+            distance: proto::Distance::Euclidean.into(),
+            ..Default::default()
+        };
+        client.create_collection(create.clone()).await.unwrap();
+        assert_eq!(client.create_collection(create.clone()).await.unwrap_err().code(), Code::AlreadyExists);
+        let unspecified = proto::CreateCollectionRequest { collection_name: "movies".to_string(), distance: 0, ..create };
+        assert_eq!(client.create_collection(unspecified).await.unwrap_err().code(), Code::InvalidArgument);
+
+        let insert = |embeddings| proto::InsertRequest { collection_name: "books".to_string(), embeddings };
+        let inserted = client.insert(insert((0..3).map(|i| embedding(i, vec![i as f32, 0.0])).collect())).await.unwrap();
+        assert_eq!(inserted.into_inner().count, 3);
+        assert_eq!(client.insert(insert(vec![embedding(0, vec![0.0, 0.0])])).await.unwrap_err().code(), Code::AlreadyExists);
+        assert_eq!(client.insert(insert(vec![embedding(3, vec![0.0])])).await.unwrap_err().code(), Code::InvalidArgument);
+        client.upsert(insert(vec![embedding(0, vec![9.0, 0.0]), embedding(3, vec![3.0, 0.0])])).await.unwrap();
+
+        let info = client.get_collection(proto::GetCollectionRequest { collection_name: "books".to_string() }).await.unwrap().into_inner();
+        assert_eq!((info.dimension, info.distance(), info.vector_count), (2, proto::Distance::Euclidean, 4));
+
+        let response = client.search(search_request("books", vec![0.0, 0.0], 2)).await.unwrap().into_inner();
+        assert_eq!(ids(&response), ["1", "2"]);
+        assert_eq!(response.results[0].embedding.as_ref().unwrap().vector, [1.0, 0.0]);
+        let threshold = proto::SearchRequest { score_threshold: Some(1.5), with_vector: false, ..search_request("books", vec![0.0, 0.0], 5) };
+        let response = client.search(threshold).await.unwrap().into_inner();
+        assert_eq!(ids(&response), ["1"]);
+        assert!(response.results[0].embedding.as_ref().unwrap().vector.is_empty());
+
+        let batch = proto::BatchSearchRequest { searches: vec![search_request("books", vec![9.0, 0.0], 1), search_request("books", vec![3.0, 0.0], 1)] };
+        let responses = client.batch_search(batch).await.unwrap().into_inner().responses;
+        assert_eq!(responses.iter().map(ids).collect::<Vec<_>>(), [["0"], ["3"]]);
+        let missing = proto::BatchSearchRequest { searches: vec![search_request("books", vec![0.0, 0.0], 1), search_request("missing", vec![0.0, 0.0], 1)] };
+        assert_eq!(client.batch_search(missing).await.unwrap_err().code(), Code::NotFound);
+
+        client.delete_collection(proto::DeleteCollectionRequest { collection_name: "books".to_string() }).await.unwrap();
+        assert_eq!(client.search(search_request("books", vec![0.0, 0.0], 1)).await.unwrap_err().code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_import_and_export() {
+        let (service, db) = service(AuthConfig::default(), true);
+        db.lock().unwrap().create_collection("books".to_string(), 2, Distance::DotProduct, CollectionOptions::default()).unwrap();
+        let mut client = connect(service).await;
+
+        let count = EXPORT_CHUNK + 10;
+        let chunks: Vec<proto::ImportRequest> = (0..count)
+            .map(|i| embedding(i, vec![i as f32, 1.0]))
+            .collect::<Vec<_>>()
+            .chunks(300)
+            .map(|chunk| proto::ImportRequest { collection_name: "books".to_string(), embeddings: chunk.to_vec() })
+            .collect();
+        let imported = client.import(tokio_stream::iter(chunks.clone())).await.unwrap().into_inner();
+        assert_eq!(imported.count, count as u64);
+
+        // A failing message stops the import, keeping the messages before it.
+        let failing = vec![
+            proto::ImportRequest { collection_name: "books".to_string(), embeddings: vec![embedding(count, vec![0.0, 1.0])] },
+            chunks[0].clone(),
+        ];
+        let err = client.import(tokio_stream::iter(failing)).await.unwrap_err();
+        assert_eq!(err.code(), Code::AlreadyExists);
+        assert!(err.message().ends_with("after importing 1 embeddings"));
+
+        let mut stream = client.export(proto::ExportRequest { collection_name: "books".to_string() }).await.unwrap().into_inner();
+        let mut exported = Vec::new();
+        while let Some(message) = stream.message().await.unwrap() {
+            assert!(message.embeddings.len() <= EXPORT_CHUNK);
+            exported.extend(message.embeddings);
+        }
+        let expected: Vec<proto::Embedding> = (0..=count).map(|i| embedding(i, if i < count { vec![i as f32, 1.0] } else { vec![0.0, 1.0] })).collect();
+        assert_eq!(exported, expected);
+    }
+
+    #[tokio::test]
+    async fn test_authorization() {
+        let mut auth = AuthConfig::parse("reader=read").unwrap();
+        auth.add_tenant("team-a", "a-admin=admin").unwrap();
+        let (service, db) = service(auth, true);
+        let mut client = connect(service).await;
+
+        let request = |key: Option<&str>, tenant: Option<&str>| {
+            let create = proto::CreateCollectionRequest {
+                collection_name: "books".to_string(),
+                dimension: 2,
+                distance: proto::Distance::Cosine.into(),
+                ..Default::default()
+            };
+            let mut request = Request::new(create);
+            if let Some(key) = key {
+                request.metadata_mut().insert("x-api-key", key.parse().unwrap());
+            }
+            if let Some(tenant) = tenant {
+                request.metadata_mut().insert("x-tenant", tenant.parse().unwrap());
+            }
+            request
+        };
+        assert_eq!(client.create_collection(request(None, None)).await.unwrap_err().code(), Code::Unauthenticated);
+        assert_eq!(client.create_collection(request(Some("wrong"), None)).await.unwrap_err().code(), Code::Unauthenticated);
+        assert_eq!(client.create_collection(request(Some("reader"), None)).await.unwrap_err().code(), Code::PermissionDenied);
+        assert_eq!(client.create_collection(request(Some("a-admin"), None)).await.unwrap_err().code(), Code::PermissionDenied);
+        assert_eq!(client.create_collection(request(Some("a-admin"), Some("team-b"))).await.unwrap_err().code(), Code::NotFound);
+
+        // Collections of a tenant are created in its namespace.
+        client.create_collection(request(Some("a-admin"), Some("team-a"))).await.unwrap();
+        assert!(db.lock().unwrap().collections.contains_key("team-a/books"));
+        let mut info = Request::new(proto::GetCollectionRequest { collection_name: "books".to_string() });
+        info.metadata_mut().insert("authorization", "Bearer a-admin".parse().unwrap());
+        info.metadata_mut().insert("x-tenant", "team-a".parse().unwrap());
+        assert_eq!(client.get_collection(info).await.unwrap().into_inner().collection_name, "books");
+    }
+
+    #[tokio::test]
+    async fn test_not_ready() {
+        let (service, _) = service(AuthConfig::default(), false);
+        let err = service.search(Request::new(search_request("books", vec![0.0, 0.0], 1))).await.unwrap_err();
+        assert_eq!(err.code(), Code::Unavailable);
+    }
+}
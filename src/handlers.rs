@@ -1,10 +1,23 @@
 use warp::{Rejection, Reply, http::StatusCode, reply::json, reply::with_status, reply::WithStatus, reply::Json};
 use crate::{
-    model::{CacheDB, CreateCollectionStruct, InsertEmbeddingStruct, CollectionHandlerStruct, BatchInsertEmbeddingsStruct, GetSimilarityStruct},
-    response::{CreateCollectionResponse, GenericResponse},
+    model::{CacheDB, CreateCollectionStruct, InsertEmbeddingStruct, CollectionHandlerStruct, BatchInsertEmbeddingsStruct, DeleteEmbeddingsStruct, GetSimilarityStruct, RecommendStruct, CreateTextIndexStruct, HybridSearchStruct, UpdateAliasesStruct, RenameCollectionStruct, CloneCollectionStruct, AlterCollectionStruct, CacheLookupStruct, CachePutStruct, Error},
+    response::{CacheLookupResponse, CacheStatsResponse, CreateCollectionResponse, GenericResponse},
     WebResult
 };
-use std::sync::{Arc, Mutex};
+use crate::metrics;
+use crate::semantic_cache;
+use crate::tenant;
+use crate::readiness::RestoreProgress;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// Lock the database, recording how long the request waited for the lock.
+fn lock_db(db: &Mutex<CacheDB>) -> Result<MutexGuard<'_, CacheDB>, Rejection> {
+    let start = Instant::now();
+    let db_lock = db.lock().map_err(|_| warp::reject::reject());
+    metrics::LOCK_WAIT.observe(start.elapsed().as_secs_f64());
+    db_lock
+}
 
 
 pub async fn health_checker_handler() -> WebResult<impl Reply> {
@@ -17,6 +30,18 @@ pub async fn health_checker_handler() -> WebResult<impl Reply> {
     Ok(json(response_json))
 }
 
+pub async fn readiness_handler(progress: Arc<RestoreProgress>) -> WebResult<impl Reply> {
+    let status = progress.status();
+    let code = if status.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    Ok(with_status(json(&status), code))
+}
+
+pub async fn metrics_handler(db: Arc<Mutex<CacheDB>>) -> WebResult<impl Reply> {
+    let db_lock = lock_db(&db)?;
+    let body = metrics::render(&db_lock);
+    Ok(warp::reply::with_header(body, "Content-Type", "text/plain; version=0.0.4"))
+}
+
 pub async fn create_collection_handler(
     body: CreateCollectionStruct,
     db: Arc<Mutex<CacheDB>>,
@@ -24,13 +49,13 @@ pub async fn create_collection_handler(
     let collection_name = body.collection_name;
     let dimension = body.dimension;
     let distance = body.distance;
-    let mut db_lock = db.lock().map_err(|_| warp::reject::reject())?;
-    match db_lock.create_collection(collection_name.clone(), dimension, distance) {
+    let mut db_lock = lock_db(&db)?;
+    match db_lock.create_collection(collection_name.clone(), dimension, distance, body.options) {
         Ok(collection) => {
             println!("Successfully created collection: {:?}", collection);
             Ok(json(&CreateCollectionResponse {
                 result: "success".to_string(),
-                status: format!("Collection created: {:?}", collection_name),
+                status: format!("Collection created: {:?}", tenant::unqualify(&collection_name)),
             }))
         }
         Err(err) => {
@@ -47,18 +72,21 @@ pub async fn insert_embeddings_handler(
     body: InsertEmbeddingStruct,
     db: Arc<Mutex<CacheDB>>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut db_lock = db.lock().map_err(|_| warp::reject::reject())?;
+    let mut db_lock = lock_db(&db)?;
 
     let result = db_lock.insert_into_collection(&body.collection_name, body.embedding);
 
     match result {
         Ok(_) => {
             println!("Successfully inserted embedding into collection: {}", &body.collection_name);
-            Ok(warp::reply::json(&format!("Embedding inserted into collection: {}", &body.collection_name)))
+            Ok(with_status(json(&format!("Embedding inserted into collection: {}", tenant::unqualify(&body.collection_name))), StatusCode::OK))
         }
         Err(err) => {
             eprintln!("Failed to insert embedding into collection: {}. Error: {:?}", &body.collection_name, err);
-            Ok(warp::reply::json(&format!("Failed to insert embedding into collection: {}. Error: {:?}", &body.collection_name, err)))
+            // Like a batch insert, a write over the limits is refused with 507, while other failures keep the 200 clients expect.
+            let status = if err == Error::LimitExceeded { StatusCode::INSUFFICIENT_STORAGE } else { StatusCode::OK };
+            let error_message = format!("Failed to insert embedding into collection: {}. Error: {:?}", tenant::unqualify(&body.collection_name), err);
+            Ok(with_status(json(&error_message), status))
         }
     }
 }
@@ -68,16 +96,17 @@ pub async fn get_collection_handler(
     body: CollectionHandlerStruct,
     db: Arc<Mutex<CacheDB>>,
 ) -> Result<WithStatus<Json>, Rejection> {
-    let db_lock = db.lock().map_err(|_| warp::reject::reject())?;
+    let db_lock = lock_db(&db)?;
     
     let collection = db_lock.get_collection(&body.collection_name);
 
-    match collection {
-        Some(collection) => {
+    match collection.map(|collection| collection.flattened()) {
+        Some(Ok(collection)) => {
             Ok(with_status(json(&collection), StatusCode::OK))
         }
+        Some(Err(err)) => Ok(with_status(json(&err.to_string()), StatusCode::INTERNAL_SERVER_ERROR)),
         None => {
-            let error_message = format!("Collection '{}' not found", body.collection_name);
+            let error_message = format!("Collection '{}' not found", tenant::unqualify(&body.collection_name));
             Ok(with_status(json(&error_message), StatusCode::NOT_FOUND))
         }
     }
@@ -87,17 +116,17 @@ pub async fn delete_collection_handler(
     body: CollectionHandlerStruct,
     db: Arc<Mutex<CacheDB>>,
 ) -> Result<impl Reply, Rejection> {
-    let mut db_lock = db.lock().map_err(|_| warp::reject::reject())?;
+    let mut db_lock = lock_db(&db)?;
     
     let result = db_lock.delete_collection(&body.collection_name);
 
     match result {
         Ok(_) => {
-            let success_message = format!("Collection '{}' deleted successfully", body.collection_name);
+            let success_message = format!("Collection '{}' deleted successfully", tenant::unqualify(&body.collection_name));
             Ok(with_status(json(&success_message), StatusCode::OK))
         }
         Err(err) => {
-            let error_message = format!("Failed to delete collection '{}': {:?}", body.collection_name, err);
+            let error_message = format!("Failed to delete collection '{}': {:?}", tenant::unqualify(&body.collection_name), err);
             Ok(with_status(json(&error_message), StatusCode::NOT_FOUND))
         }
     }
@@ -107,55 +136,331 @@ pub async fn batch_insert_embeddings_handler(
     body: BatchInsertEmbeddingsStruct,
     db: Arc<Mutex<CacheDB>>,
 ) -> Result<impl Reply, Rejection> {
-    let mut db_lock = db.lock().map_err(|_| warp::reject::reject())?;
+    let mut db_lock = lock_db(&db)?;
     
     let result = db_lock.update_collection(&body.collection_name, body.embeddings);
     match result {
         Ok(_) => {
-            let success_message = format!("Collection '{}' updated successfully", body.collection_name);
+            let success_message = format!("Collection '{}' updated successfully", tenant::unqualify(&body.collection_name));
             Ok(with_status(json(&success_message), StatusCode::OK))
         }
         Err(err) => {
-            let error_message = format!("Failed to update collection '{}': {:?}", body.collection_name, err);
-            Ok(with_status(json(&error_message), StatusCode::NOT_FOUND))
+            let error_message = format!("Failed to update collection '{}': {:?}", tenant::unqualify(&body.collection_name), err);
+            let status = if err == Error::LimitExceeded { StatusCode::INSUFFICIENT_STORAGE } else { StatusCode::NOT_FOUND };
+            Ok(with_status(json(&error_message), status))
         }
     }
 }
 
+pub async fn delete_embeddings_handler(
+    body: DeleteEmbeddingsStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<impl Reply, Rejection> {
+    let mut db_lock = lock_db(&db)?;
+
+    let count = body.ids.len();
+    match db_lock.delete_embeddings(&body.collection_name, body.ids) {
+        Ok(_) => {
+            let success_message = format!("Deleted {} embeddings from collection '{}'", count, tenant::unqualify(&body.collection_name));
+            Ok(with_status(json(&success_message), StatusCode::OK))
+        }
+        Err(err) => {
+            let status = match err {
+                Error::NotFound | Error::EmbeddingNotFound => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            let error_message = format!("Failed to delete embeddings from collection '{}': {:?}", tenant::unqualify(&body.collection_name), err);
+            Ok(with_status(json(&error_message), status))
+        }
+    }
+}
 
 pub async fn get_similarity_handler(
     body: GetSimilarityStruct,
     db: Arc<Mutex<CacheDB>>,
 ) -> Result<impl Reply, Rejection> {
-    let db_lock = db.lock().map_err(|_| warp::reject::reject())?;
+    let db_lock = lock_db(&db)?;
 
     if let Some(collection) = db_lock.get_collection(&body.collection_name) {
-        let similarity_results = collection.get_similarity(&body.query_vector, body.k);
-        return Ok(json(&similarity_results));
+        let _timer = metrics::SEARCH_DURATION.with_label_values(&[db_lock.resolve(&body.collection_name), "similarity"]).start_timer();
+        let similarity_results = match (&body.sparse_query_vector, &body.multi_query_vector) {
+            (Some(query), None) => collection.get_sparse_similarity(query, body.k, &body.params),
+            (None, Some(query)) => collection.get_multi_similarity(query, body.k, &body.params),
+            (None, None) => collection.get_similarity(&body.query_vector, body.k, &body.params),
+            (Some(_), Some(_)) => Err(Error::VectorTypeMismatch),
+        };
+        return match similarity_results {
+            Ok(similarity_results) => Ok(json(&similarity_results)),
+            Err(err) => {
+                let error_message = format!("Failed to search collection '{}': {:?}", tenant::unqualify(&body.collection_name), err);
+                Ok(json(&error_message))
+            }
+        };
     }
 
     Ok(json(&"Collection not found"))
 }
 
+pub async fn recommend_handler(
+    body: RecommendStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<WithStatus<Json>, Rejection> {
+    let db_lock = lock_db(&db)?;
+
+    let Some(collection) = db_lock.get_collection(&body.collection_name) else {
+        let error_message = format!("Collection '{}' not found", tenant::unqualify(&body.collection_name));
+        return Ok(with_status(json(&error_message), StatusCode::NOT_FOUND));
+    };
+
+    let _timer = metrics::SEARCH_DURATION.with_label_values(&[db_lock.resolve(&body.collection_name), "recommend"]).start_timer();
+    match collection.recommend(&body.positive, &body.negative, body.k, &body.params) {
+        Ok(similarity_results) => Ok(with_status(json(&similarity_results), StatusCode::OK)),
+        Err(err) => {
+            let status = if err == Error::EmbeddingNotFound { StatusCode::NOT_FOUND } else { StatusCode::BAD_REQUEST };
+            let error_message = format!("Failed to recommend from collection '{}': {:?}", tenant::unqualify(&body.collection_name), err);
+            Ok(with_status(json(&error_message), status))
+        }
+    }
+}
+
+pub async fn create_text_index_handler(
+    body: CreateTextIndexStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<impl Reply, Rejection> {
+    let mut db_lock = lock_db(&db)?;
+
+    match db_lock.create_text_index(&body.collection_name, body.field.clone()) {
+        Ok(_) => {
+            let success_message = format!("Text index created on field '{}' of collection '{}'", body.field, tenant::unqualify(&body.collection_name));
+            Ok(with_status(json(&success_message), StatusCode::OK))
+        }
+        Err(err) => {
+            let status = if err == Error::NotFound { StatusCode::NOT_FOUND } else { StatusCode::CONFLICT };
+            let error_message = format!("Failed to create text index on collection '{}': {:?}", tenant::unqualify(&body.collection_name), err);
+            Ok(with_status(json(&error_message), status))
+        }
+    }
+}
+
+pub async fn hybrid_search_handler(
+    body: HybridSearchStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<WithStatus<Json>, Rejection> {
+    let db_lock = lock_db(&db)?;
+
+    let Some(collection) = db_lock.get_collection(&body.collection_name) else {
+        let error_message = format!("Collection '{}' not found", tenant::unqualify(&body.collection_name));
+        return Ok(with_status(json(&error_message), StatusCode::NOT_FOUND));
+    };
+
+    let _timer = metrics::SEARCH_DURATION.with_label_values(&[db_lock.resolve(&body.collection_name), "hybrid"]).start_timer();
+    let result = collection.hybrid_search(
+        body.query_vector.as_deref(),
+        body.query_text.as_deref(),
+        body.k,
+        body.fusion,
+        &body.params,
+    );
+    match result {
+        Ok(similarity_results) => Ok(with_status(json(&similarity_results), StatusCode::OK)),
+        Err(err) => {
+            let error_message = format!("Failed to search collection '{}': {:?}", tenant::unqualify(&body.collection_name), err);
+            Ok(with_status(json(&error_message), StatusCode::BAD_REQUEST))
+        }
+    }
+}
+
+pub async fn update_aliases_handler(
+    body: UpdateAliasesStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<impl Reply, Rejection> {
+    let mut db_lock = lock_db(&db)?;
+
+    match db_lock.update_aliases(body.actions) {
+        Ok(_) => Ok(with_status(json(&"Aliases updated successfully"), StatusCode::OK)),
+        Err(err) => {
+            let status = match err {
+                Error::NotFound | Error::AliasNotFound => StatusCode::NOT_FOUND,
+                Error::AliasConflict => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            let error_message = format!("Failed to update aliases: {:?}", err);
+            Ok(with_status(json(&error_message), status))
+        }
+    }
+}
+
+pub async fn rename_collection_handler(
+    body: RenameCollectionStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<impl Reply, Rejection> {
+    let mut db_lock = lock_db(&db)?;
+
+    match db_lock.rename_collection(&body.collection_name, body.new_name.clone()) {
+        Ok(_) => Ok(with_status(json(&"Collection renamed successfully"), StatusCode::OK)),
+        Err(err) => {
+            let status = match err {
+                Error::NotFound => StatusCode::NOT_FOUND,
+                Error::UniqueViolation | Error::AliasConflict => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            let error_message = format!("Failed to rename collection '{}' to '{}': {:?}", tenant::unqualify(&body.collection_name), tenant::unqualify(&body.new_name), err);
+            Ok(with_status(json(&error_message), status))
+        }
+    }
+}
+
+pub async fn clone_collection_handler(
+    body: CloneCollectionStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<impl Reply, Rejection> {
+    let mut db_lock = lock_db(&db)?;
+
+    match db_lock.clone_collection(&body.collection_name, body.new_name.clone(), body.filter) {
+        Ok(_) => Ok(with_status(json(&"Collection cloned successfully"), StatusCode::CREATED)),
+        Err(err) => {
+            let status = match err {
+                Error::NotFound => StatusCode::NOT_FOUND,
+                Error::UniqueViolation | Error::AliasConflict => StatusCode::CONFLICT,
+                Error::LimitExceeded => StatusCode::INSUFFICIENT_STORAGE,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            let error_message = format!("Failed to clone collection '{}' to '{}': {:?}", tenant::unqualify(&body.collection_name), tenant::unqualify(&body.new_name), err);
+            Ok(with_status(json(&error_message), status))
+        }
+    }
+}
+
+pub async fn alter_collection_handler(
+    body: AlterCollectionStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<impl Reply, Rejection> {
+    let mut db_lock = lock_db(&db)?;
+
+    match db_lock.alter_collection(&body.collection_name, body.changes) {
+        Ok(_) => Ok(with_status(json(&"Collection settings changed successfully"), StatusCode::OK)),
+        Err(err) => {
+            let status = match err {
+                Error::NotFound => StatusCode::NOT_FOUND,
+                Error::ConflictingChanges | Error::UnsupportedStorage => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            let error_message = format!("Failed to change the settings of collection '{}': {:?}", tenant::unqualify(&body.collection_name), err);
+            Ok(with_status(json(&error_message), status))
+        }
+    }
+}
+
+pub async fn list_aliases_handler(
+    body: CollectionHandlerStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<impl Reply, Rejection> {
+    let db_lock = lock_db(&db)?;
+
+    match db_lock.aliases_of(&body.collection_name) {
+        Ok(aliases) => {
+            let aliases: Vec<&str> = aliases.iter().map(|alias| tenant::unqualify(alias)).collect();
+            Ok(with_status(json(&aliases), StatusCode::OK))
+        }
+        Err(_) => {
+            let error_message = format!("Collection '{}' not found", tenant::unqualify(&body.collection_name));
+            Ok(with_status(json(&error_message), StatusCode::NOT_FOUND))
+        }
+    }
+}
+
 pub async fn get_embeddings_handler(
     body: CollectionHandlerStruct,
     db: Arc<Mutex<CacheDB>>, 
 ) -> Result<impl Reply, Rejection> {
-    let db_lock = db.lock().map_err(|_| warp::reject::reject())?;
+    let db_lock = lock_db(&db)?;
 
     let embeddings = db_lock.get_embeddings(&body.collection_name);
 
     match embeddings {
-        Some(embeddings) => {
+        Ok(embeddings) => {
             Ok(with_status(json(&embeddings), StatusCode::OK))
         }
-        None => {
-            let error_message = format!("Collection '{}' not found", body.collection_name);
+        Err(Error::NotFound) => {
+            let error_message = format!("Collection '{}' not found", tenant::unqualify(&body.collection_name));
             Ok(with_status(json(&error_message), StatusCode::NOT_FOUND))
         }
+        Err(err) => Ok(with_status(json(&err.to_string()), StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+pub async fn cache_lookup_handler(
+    body: CacheLookupStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<WithStatus<Json>, Rejection> {
+    let db_lock = lock_db(&db)?;
+
+    // Lookups are timed under the name of the collection, so only once it is known to exist.
+    if db_lock.get_collection(&body.collection_name).is_none() {
+        let error_message = format!("Failed to look up collection '{}': {:?}", tenant::unqualify(&body.collection_name), Error::NotFound);
+        return Ok(with_status(json(&error_message), StatusCode::NOT_FOUND));
+    }
+    let _timer = metrics::SEARCH_DURATION.with_label_values(&[db_lock.resolve(&body.collection_name), "cache"]).start_timer();
+    match semantic_cache::lookup(&db_lock, &body.collection_name, &body.vector, body.threshold) {
+        Ok(hit) => {
+            let response = CacheLookupResponse {
+                hit: hit.is_some(),
+                score: hit.as_ref().map(|hit| hit.score),
+                id: hit.as_ref().map(|hit| hit.embedding.id.clone()),
+                payload: hit.and_then(|hit| hit.embedding.metadata),
+            };
+            Ok(with_status(json(&response), StatusCode::OK))
+        }
+        Err(err) => {
+            let status = if err == Error::NotFound { StatusCode::NOT_FOUND } else { StatusCode::BAD_REQUEST };
+            let error_message = format!("Failed to look up collection '{}': {:?}", tenant::unqualify(&body.collection_name), err);
+            Ok(with_status(json(&error_message), status))
+        }
     }
 }
 
+pub async fn cache_put_handler(
+    body: CachePutStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<WithStatus<Json>, Rejection> {
+    let mut db_lock = lock_db(&db)?;
+
+    match semantic_cache::put(&mut db_lock, &body.collection_name, body.key, body.vector, body.payload, body.ttl_secs) {
+        Ok(_) => Ok(with_status(json(&"Entry cached successfully"), StatusCode::CREATED)),
+        Err(err) => {
+            let status = match err {
+                Error::NotFound => StatusCode::NOT_FOUND,
+                Error::EmbeddingUniqueViolation => StatusCode::CONFLICT,
+                Error::LimitExceeded => StatusCode::INSUFFICIENT_STORAGE,
+                Error::DataLog | Error::Storage => StatusCode::INTERNAL_SERVER_ERROR,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            let error_message = format!("Failed to cache entry in collection '{}': {:?}", tenant::unqualify(&body.collection_name), err);
+            Ok(with_status(json(&error_message), status))
+        }
+    }
+}
+
+pub async fn cache_stats_handler(
+    body: CollectionHandlerStruct,
+    db: Arc<Mutex<CacheDB>>,
+) -> Result<WithStatus<Json>, Rejection> {
+    let db_lock = lock_db(&db)?;
+
+    let Some(collection) = db_lock.get_collection(&body.collection_name) else {
+        let error_message = format!("Collection '{}' not found", tenant::unqualify(&body.collection_name));
+        return Ok(with_status(json(&error_message), StatusCode::NOT_FOUND));
+    };
+    let stats = &collection.cache_stats;
+    let response = CacheStatsResponse {
+        collection_name: tenant::unqualify(&body.collection_name).to_string(),
+        hits: stats.hits(),
+        misses: stats.misses(),
+        hit_rate: stats.hit_rate(),
+    };
+    Ok(with_status(json(&response), StatusCode::OK))
+}
+
 
 
 
@@ -167,7 +472,7 @@ mod tests {
     use warp::http::StatusCode;
     use warp::Buf;
     use serde_json::{Value, json};
-    use crate::model::{Distance, Embedding, SimilarityResult, CacheDB};
+    use crate::model::{Distance, Embedding, SimilarityResult, SearchParams, CollectionOptions, SparseVector, CacheDB, Limits};
     use std::collections::HashMap;
 
     #[tokio::test]
@@ -194,6 +499,7 @@ mod tests {
             collection_name: "test_collection".to_string(),
             dimension: 100,
             distance: Distance::Euclidean,
+            options: CollectionOptions::default(),
         };
     
         let db = Arc::new(Mutex::new(CacheDB::new()));
@@ -226,6 +532,7 @@ mod tests {
             collection_name: "test_collection".to_string(),
             dimension: 3,
             distance: Distance::Euclidean,
+            options: CollectionOptions::default(),
         };
         let reply = create_collection_handler(request_body.clone(), db.clone()).await.unwrap();
         let response = reply.into_response();
@@ -235,7 +542,7 @@ mod tests {
         id.insert("unique_id".to_string(), "1".to_string());
         let request_body = InsertEmbeddingStruct {
             collection_name: "test_collection".to_string(),
-            embedding: Embedding { id: id, vector: vec![1.0, 1.0, 1.0], metadata: None },
+            embedding: Embedding { id, vector: vec![1.0, 1.0, 1.0], metadata: None, ..Default::default() },
         };
         let reply = insert_embeddings_handler(request_body.clone(), db.clone()).await.unwrap();
         let response = reply.into_response();
@@ -273,6 +580,7 @@ mod tests {
             collection_name: collection_name.clone(),
             dimension: 3,
             distance: Distance::Euclidean,
+            options: CollectionOptions::default(),
         };
         let _ = create_collection_handler(request_body.clone(), db.clone()).await.unwrap();
 
@@ -308,6 +616,7 @@ mod tests {
             collection_name: collection_name.clone(),
             dimension: 3,
             distance: Distance::Euclidean,
+            options: CollectionOptions::default(),
         };
         let _ = create_collection_handler(request_body.clone(), db.clone()).await.unwrap();
 
@@ -346,6 +655,7 @@ mod tests {
             collection_name: collection_name.clone(),
             dimension: 3,
             distance: Distance::Euclidean,
+            options: CollectionOptions::default(),
         };
         let _ = create_collection_handler(request_body.clone(), db.clone()).await.unwrap();
 
@@ -357,8 +667,8 @@ mod tests {
 
         // Update the collection
         let embeddings = vec![
-            Embedding { id: id_1, vector: vec![2.0, 2.0, 2.0], metadata: None },
-            Embedding { id: id_2, vector: vec![3.0, 3.0, 3.0], metadata: None },
+            Embedding { id: id_1, vector: vec![2.0, 2.0, 2.0], metadata: None, ..Default::default() },
+            Embedding { id: id_2, vector: vec![3.0, 3.0, 3.0], metadata: None, ..Default::default() },
         ];
         let request_body = BatchInsertEmbeddingsStruct {
             collection_name: collection_name.clone(),
@@ -396,8 +706,8 @@ mod tests {
 
         // Try to update a non-existent collection
         let embeddings = vec![
-            Embedding { id: id_1, vector: vec![2.0, 2.0, 2.0], metadata: None },
-            Embedding { id: id_2, vector: vec![3.0, 3.0, 3.0], metadata: None },
+            Embedding { id: id_1, vector: vec![2.0, 2.0, 2.0], metadata: None, ..Default::default() },
+            Embedding { id: id_2, vector: vec![3.0, 3.0, 3.0], metadata: None, ..Default::default() },
         ];
         let request_body = BatchInsertEmbeddingsStruct {
             collection_name: collection_name.clone(),
@@ -409,6 +719,29 @@ mod tests {
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
+    #[tokio::test]
+    async fn test_insert_handlers_over_limit() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let embedding = |unique_id: &str| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![1.0, 2.0], ..Default::default() }
+        };
+        {
+            let mut db_lock = db.lock().unwrap();
+            db_lock.create_collection("full".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+            db_lock.set_limits(Limits { max_vectors: Some(1), ..Limits::default() });
+            db_lock.insert_into_collection("full", embedding("1")).unwrap();
+        }
+
+        // Both insert handlers refuse writes over the limits with the same status.
+        let request_body = InsertEmbeddingStruct { collection_name: "full".to_string(), embedding: embedding("2") };
+        let response = insert_embeddings_handler(request_body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+        let request_body = BatchInsertEmbeddingsStruct { collection_name: "full".to_string(), embeddings: vec![embedding("2")] };
+        let response = batch_insert_embeddings_handler(request_body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+    }
+
 
     #[tokio::test]
     async fn test_get_similarity_handler_success() {
@@ -420,6 +753,7 @@ mod tests {
             collection_name: collection_name.clone(),
             dimension: 3,
             distance: Distance::Euclidean,
+            options: CollectionOptions::default(),
         };
         let _ = create_collection_handler(request_body.clone(), db.clone()).await.unwrap();
 
@@ -431,7 +765,7 @@ mod tests {
         id.insert("unique_id".to_string(), "0".to_string());
 
         // Insert an embedding into the collection
-        let embedding = Embedding { id: id, vector: vec![1.0, 1.0, 1.0], metadata: Some(metadata.clone())};
+        let embedding = Embedding { id, vector: vec![1.0, 1.0, 1.0], metadata: Some(metadata.clone()), ..Default::default() };
         
         let insert_request_body = InsertEmbeddingStruct {
             collection_name: collection_name.clone(),
@@ -443,7 +777,10 @@ mod tests {
         let request_body = GetSimilarityStruct {
             collection_name: collection_name.clone(),
             query_vector: vec![1.0, 1.0, 1.0],
+            sparse_query_vector: None,
+            multi_query_vector: None,
             k: 1,
+            params: SearchParams::default(),
         };
         let reply = get_similarity_handler(request_body, db.clone()).await.unwrap();
         let response = reply.into_response();
@@ -458,6 +795,56 @@ mod tests {
         assert_eq!(similarity_results[0].embedding.metadata, Some(metadata.clone()));
     }
 
+    #[tokio::test]
+    async fn test_get_similarity_handler_search_options() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let collection_name = "test_collection".to_string();
+
+        let request_body = CreateCollectionStruct {
+            collection_name: collection_name.clone(),
+            dimension: 3,
+            distance: Distance::DotProduct,
+            options: CollectionOptions::default(),
+        };
+        let _ = create_collection_handler(request_body.clone(), db.clone()).await.unwrap();
+
+        let mut metadata = HashMap::new();
+        metadata.insert("text".to_string(), "This is a test metadata text".to_string());
+        let embeddings = (1..=3)
+            .map(|i| {
+                let mut id = HashMap::new();
+                id.insert("unique_id".to_string(), i.to_string());
+                Embedding { id, vector: vec![i as f32, 0.0, 0.0], metadata: Some(metadata.clone()), ..Default::default() }
+            })
+            .collect();
+        let batch_request_body = BatchInsertEmbeddingsStruct {
+            collection_name: collection_name.clone(),
+            embeddings,
+        };
+        let _ = batch_insert_embeddings_handler(batch_request_body, db.clone()).await.unwrap();
+
+        // Options are read from the same JSON body as the query
+        let request_body: GetSimilarityStruct = serde_json::from_value(json!({
+            "collection_name": collection_name,
+            "query_vector": [1.0, 0.0, 0.0],
+            "k": 5,
+            "offset": 1,
+            "score_threshold": 2.0,
+            "with_vector": false
+        })).unwrap();
+        let reply = get_similarity_handler(request_body, db.clone()).await.unwrap();
+        let response = reply.into_response();
+        let body = warp::hyper::body::aggregate(response.into_body()).await.unwrap();
+        let body_value: Value = serde_json::from_reader(body.reader()).unwrap();
+
+        assert_eq!(body_value, json!([
+            {
+                "score": 2.0,
+                "embedding": { "id": { "unique_id": "2" }, "metadata": { "text": "This is a test metadata text" } }
+            }
+        ]));
+    }
+
     #[tokio::test]
     async fn test_get_similarity_handler_not_found() {
         let db = Arc::new(Mutex::new(CacheDB::new()));
@@ -467,7 +854,10 @@ mod tests {
         let request_body = GetSimilarityStruct {
             collection_name: collection_name.clone(),
             query_vector: vec![1.0, 1.0, 1.0],
+            sparse_query_vector: None,
+            multi_query_vector: None,
             k: 1,
+            params: SearchParams::default(),
         };
         let reply = get_similarity_handler(request_body, db.clone()).await.unwrap();
         let response = reply.into_response();
@@ -477,6 +867,319 @@ mod tests {
         assert_eq!(body_value, "Collection not found");
     }
 
+    #[tokio::test]
+    async fn test_recommend_handler() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let collection_name = "test_collection".to_string();
+
+        let request_body = CreateCollectionStruct {
+            collection_name: collection_name.clone(),
+            dimension: 2,
+            distance: Distance::Euclidean,
+            options: CollectionOptions::default(),
+        };
+        let _ = create_collection_handler(request_body, db.clone()).await.unwrap();
+
+        let ids: Vec<HashMap<String, String>> = (0..4)
+            .map(|i| {
+                let mut id = HashMap::new();
+                id.insert("unique_id".to_string(), i.to_string());
+                id
+            })
+            .collect();
+        let embeddings = vec![
+            Embedding { id: ids[0].clone(), vector: vec![0.0, 0.0], metadata: None, ..Default::default() },
+            Embedding { id: ids[1].clone(), vector: vec![1.0, 0.0], metadata: None, ..Default::default() },
+            Embedding { id: ids[2].clone(), vector: vec![5.0, 5.0], metadata: None, ..Default::default() },
+            Embedding { id: ids[3].clone(), vector: vec![0.0, 1.5], metadata: None, ..Default::default() },
+        ];
+        let batch_request_body = BatchInsertEmbeddingsStruct {
+            collection_name: collection_name.clone(),
+            embeddings,
+        };
+        let _ = batch_insert_embeddings_handler(batch_request_body, db.clone()).await.unwrap();
+
+        let request_body = RecommendStruct {
+            collection_name: collection_name.clone(),
+            positive: vec![ids[0].clone()],
+            negative: Vec::new(),
+            k: 2,
+            params: SearchParams::default(),
+        };
+        let reply = recommend_handler(request_body, db.clone()).await.unwrap();
+        let response = reply.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = warp::hyper::body::aggregate(response.into_body()).await.unwrap();
+        let similarity_results: Vec<SimilarityResult> = serde_json::from_reader(body.reader()).unwrap();
+
+        // The positive example itself is excluded from the results
+        let result_ids: Vec<_> = similarity_results.iter().map(|r| r.embedding.id.clone()).collect();
+        assert_eq!(result_ids, vec![ids[1].clone(), ids[3].clone()]);
+
+        // A negative example pushes the query away from it: 2 * [0, 0] - [1, 0] = [-1, 0]
+        let request_body = RecommendStruct {
+            collection_name: collection_name.clone(),
+            positive: vec![ids[0].clone()],
+            negative: vec![ids[1].clone()],
+            k: 1,
+            params: SearchParams::default(),
+        };
+        let reply = recommend_handler(request_body, db.clone()).await.unwrap();
+        let response = reply.into_response();
+        let body = warp::hyper::body::aggregate(response.into_body()).await.unwrap();
+        let similarity_results: Vec<SimilarityResult> = serde_json::from_reader(body.reader()).unwrap();
+        assert_eq!(similarity_results[0].embedding.id, ids[3]);
+        assert_eq!(similarity_results[0].score, 3.25f32.sqrt());
+    }
+
+    #[tokio::test]
+    async fn test_recommend_handler_embedding_not_found() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let request_body = CreateCollectionStruct {
+            collection_name: "test_collection".to_string(),
+            dimension: 3,
+            distance: Distance::Euclidean,
+            options: CollectionOptions::default(),
+        };
+        let _ = create_collection_handler(request_body, db.clone()).await.unwrap();
+
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), "missing".to_string());
+        let request_body = RecommendStruct {
+            collection_name: "test_collection".to_string(),
+            positive: vec![id],
+            negative: Vec::new(),
+            k: 1,
+            params: SearchParams::default(),
+        };
+        let reply = recommend_handler(request_body, db.clone()).await.unwrap();
+        let response = reply.into_response();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_search_handler() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let collection_name = "test_collection".to_string();
+
+        let request_body = CreateCollectionStruct {
+            collection_name: collection_name.clone(),
+            dimension: 2,
+            distance: Distance::Cosine,
+            options: CollectionOptions::default(),
+        };
+        let _ = create_collection_handler(request_body, db.clone()).await.unwrap();
+
+        let texts = ["rust vector database", "python web framework", "in memory cache"];
+        let vectors = [vec![0.0, 1.0], vec![1.0, 0.0], vec![0.7, 0.7]];
+        let embeddings = texts.iter().zip(vectors).enumerate()
+            .map(|(i, (text, vector))| {
+                let mut id = HashMap::new();
+                id.insert("unique_id".to_string(), i.to_string());
+                let mut metadata = HashMap::new();
+                metadata.insert("text".to_string(), text.to_string());
+                Embedding { id, vector, metadata: Some(metadata), ..Default::default() }
+            })
+            .collect();
+        let batch_request_body = BatchInsertEmbeddingsStruct {
+            collection_name: collection_name.clone(),
+            embeddings,
+        };
+        let _ = batch_insert_embeddings_handler(batch_request_body, db.clone()).await.unwrap();
+
+        // Text search needs an index on the collection
+        let request_body: HybridSearchStruct = serde_json::from_value(json!({
+            "collection_name": collection_name,
+            "query_text": "vector database",
+            "k": 3
+        })).unwrap();
+        let reply = hybrid_search_handler(request_body.clone(), db.clone()).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let index_request_body = CreateTextIndexStruct {
+            collection_name: collection_name.clone(),
+            field: "text".to_string(),
+        };
+        let reply = create_text_index_handler(index_request_body.clone(), db.clone()).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::OK);
+        let reply = create_text_index_handler(index_request_body, db.clone()).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::CONFLICT);
+
+        // Keyword only
+        let reply = hybrid_search_handler(request_body, db.clone()).await.unwrap();
+        let response = reply.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = warp::hyper::body::aggregate(response.into_body()).await.unwrap();
+        let similarity_results: Vec<SimilarityResult> = serde_json::from_reader(body.reader()).unwrap();
+        assert_eq!(similarity_results.len(), 1);
+        assert_eq!(similarity_results[0].embedding.id["unique_id"], "0");
+
+        // The keyword match and the vector match both outrank the embedding matched by neither
+        let request_body: HybridSearchStruct = serde_json::from_value(json!({
+            "collection_name": collection_name,
+            "query_vector": [1.0, 0.0],
+            "query_text": "vector database",
+            "k": 3,
+            "fusion": { "method": "weighted", "vector_weight": 0.5 },
+            "with_vector": false
+        })).unwrap();
+        let reply = hybrid_search_handler(request_body, db.clone()).await.unwrap();
+        let response = reply.into_response();
+        let body = warp::hyper::body::aggregate(response.into_body()).await.unwrap();
+        let similarity_results: Vec<SimilarityResult> = serde_json::from_reader(body.reader()).unwrap();
+        let result_ids: Vec<&str> = similarity_results.iter().map(|r| r.embedding.id["unique_id"].as_str()).collect();
+        assert_eq!(result_ids, vec!["0", "1", "2"]);
+        assert!(similarity_results[0].embedding.vector.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sparse_collection_handlers() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let collection_name = "sparse_collection".to_string();
+
+        let request_body: CreateCollectionStruct = serde_json::from_value(json!({
+            "collection_name": collection_name,
+            "dimension": 30000,
+            "distance": "dot",
+            "vector_type": "sparse"
+        })).unwrap();
+        let _ = create_collection_handler(request_body, db.clone()).await.unwrap();
+
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), "0".to_string());
+        let sparse_vector = SparseVector { indices: vec![17, 2048], values: vec![0.25, 1.5] };
+        let embedding = Embedding { id: id.clone(), sparse_vector: Some(sparse_vector), ..Default::default() };
+        let insert_request_body = InsertEmbeddingStruct {
+            collection_name: collection_name.clone(),
+            embedding: embedding.clone(),
+        };
+        let reply = insert_embeddings_handler(insert_request_body, db.clone()).await.unwrap();
+        let body = warp::hyper::body::aggregate(reply.into_response().into_body()).await.unwrap();
+        let body_value: String = serde_json::from_reader(body.reader()).unwrap();
+        assert_eq!(body_value, format!("Embedding inserted into collection: {}", collection_name));
+
+        // Dense vectors are rejected by sparse collections
+        let mut id_1 = HashMap::new();
+        id_1.insert("unique_id".to_string(), "1".to_string());
+        let insert_request_body = InsertEmbeddingStruct {
+            collection_name: collection_name.clone(),
+            embedding: Embedding { id: id_1, vector: vec![1.0, 1.0, 1.0], metadata: None, ..Default::default() },
+        };
+        let reply = insert_embeddings_handler(insert_request_body, db.clone()).await.unwrap();
+        let body = warp::hyper::body::aggregate(reply.into_response().into_body()).await.unwrap();
+        let body_value: String = serde_json::from_reader(body.reader()).unwrap();
+        assert!(body_value.ends_with("VectorTypeMismatch"));
+
+        let request_body: GetSimilarityStruct = serde_json::from_value(json!({
+            "collection_name": collection_name,
+            "sparse_query_vector": { "indices": [2048, 5], "values": [2.0, 1.0] },
+            "k": 10
+        })).unwrap();
+        let reply = get_similarity_handler(request_body, db.clone()).await.unwrap();
+        let body = warp::hyper::body::aggregate(reply.into_response().into_body()).await.unwrap();
+        let similarity_results: Vec<SimilarityResult> = serde_json::from_reader(body.reader()).unwrap();
+
+        assert_eq!(similarity_results.len(), 1);
+        assert_eq!(similarity_results[0].score, 3.0);
+        assert_eq!(similarity_results[0].embedding, embedding);
+    }
+
+    #[tokio::test]
+    async fn test_named_vectors_handlers() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let collection_name = "named_collection".to_string();
+
+        // No default vector, only named ones
+        let request_body: CreateCollectionStruct = serde_json::from_value(json!({
+            "collection_name": collection_name,
+            "dimension": 0,
+            "distance": "dot",
+            "vectors": {
+                "title": { "dimension": 2, "distance": "cosine" },
+                "image": { "dimension": 3, "distance": "euclidean" }
+            }
+        })).unwrap();
+        let _ = create_collection_handler(request_body, db.clone()).await.unwrap();
+
+        let embeddings = [([1.0, 0.0], [0.0, 0.0, 0.0]), ([0.0, 1.0], [5.0, 5.0, 5.0])]
+            .iter()
+            .enumerate()
+            .map(|(i, (title, image))| {
+                let mut id = HashMap::new();
+                id.insert("unique_id".to_string(), i.to_string());
+                let mut vectors = HashMap::new();
+                vectors.insert("title".to_string(), title.to_vec());
+                vectors.insert("image".to_string(), image.to_vec());
+                Embedding { id, vectors, ..Default::default() }
+            })
+            .collect();
+        let batch_request_body = BatchInsertEmbeddingsStruct {
+            collection_name: collection_name.clone(),
+            embeddings,
+        };
+        let reply = batch_insert_embeddings_handler(batch_request_body, db.clone()).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::OK);
+
+        // Each named vector is ranked with its own distance
+        for (using, query, expected_id) in [("title", json!([0.0, 3.0]), "1"), ("image", json!([1.0, 1.0, 1.0]), "0")] {
+            let request_body: GetSimilarityStruct = serde_json::from_value(json!({
+                "collection_name": collection_name,
+                "query_vector": query,
+                "using": using,
+                "k": 1
+            })).unwrap();
+            let reply = get_similarity_handler(request_body, db.clone()).await.unwrap();
+            let body = warp::hyper::body::aggregate(reply.into_response().into_body()).await.unwrap();
+            let similarity_results: Vec<SimilarityResult> = serde_json::from_reader(body.reader()).unwrap();
+            assert_eq!(similarity_results[0].embedding.id["unique_id"], expected_id);
+        }
+
+        let request_body: GetSimilarityStruct = serde_json::from_value(json!({
+            "collection_name": collection_name,
+            "query_vector": [1.0, 0.0],
+            "using": "body",
+            "k": 1
+        })).unwrap();
+        let reply = get_similarity_handler(request_body, db.clone()).await.unwrap();
+        let body = warp::hyper::body::aggregate(reply.into_response().into_body()).await.unwrap();
+        let body_value: String = serde_json::from_reader(body.reader()).unwrap();
+        assert_eq!(body_value, "Failed to search collection 'named_collection': VectorNotFound");
+    }
+
+    #[tokio::test]
+    async fn test_multivector_similarity_handler() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let collection_name = "colbert_collection".to_string();
+
+        let request_body: CreateCollectionStruct = serde_json::from_value(json!({
+            "collection_name": collection_name,
+            "dimension": 2,
+            "distance": "dot",
+            "vector_type": "multi"
+        })).unwrap();
+        let _ = create_collection_handler(request_body, db.clone()).await.unwrap();
+
+        let insert_request_body: InsertEmbeddingStruct = serde_json::from_value(json!({
+            "collection_name": collection_name,
+            "embedding": { "id": { "unique_id": "0" }, "multivector": [[1.0, 0.0], [0.0, 3.0]], "metadata": null }
+        })).unwrap();
+        let _ = insert_embeddings_handler(insert_request_body, db.clone()).await.unwrap();
+
+        let request_body: GetSimilarityStruct = serde_json::from_value(json!({
+            "collection_name": collection_name,
+            "multi_query_vector": [[1.0, 1.0], [2.0, 0.0]],
+            "k": 1
+        })).unwrap();
+        let reply = get_similarity_handler(request_body, db.clone()).await.unwrap();
+        let body = warp::hyper::body::aggregate(reply.into_response().into_body()).await.unwrap();
+        let similarity_results: Vec<SimilarityResult> = serde_json::from_reader(body.reader()).unwrap();
+
+        // max(1, 3) + max(2, 0)
+        assert_eq!(similarity_results[0].score, 5.0);
+        assert_eq!(similarity_results[0].embedding.multivector, vec![vec![1.0, 0.0], vec![0.0, 3.0]]);
+    }
+
     #[tokio::test]
     async fn test_get_embeddings_handler_success() {
         let db = Arc::new(Mutex::new(CacheDB::new()));
@@ -486,6 +1189,7 @@ mod tests {
             collection_name: collection_name.clone(),
             dimension: 3,
             distance: Distance::Euclidean,
+            options: CollectionOptions::default(),
         };
         let _ = create_collection_handler(request_body.clone(), db.clone()).await.unwrap();
 
@@ -512,6 +1216,163 @@ mod tests {
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
+    #[tokio::test]
+    async fn test_delete_embeddings_handler() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let embedding = |unique_id: &str| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![1.0, 2.0], ..Default::default() }
+        };
+        {
+            let mut db_lock = db.lock().unwrap();
+            db_lock.create_collection("test_collection".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+            db_lock.update_collection("test_collection", vec![embedding("1"), embedding("2")]).unwrap();
+        }
+
+        let request_body = DeleteEmbeddingsStruct { collection_name: "test_collection".to_string(), ids: vec![embedding("1").id] };
+        let response = delete_embeddings_handler(request_body.clone(), db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(db.lock().unwrap().get_embeddings("test_collection"), Ok(vec![embedding("2")]));
+
+        // Deleting it again finds nothing, like deleting from a missing collection.
+        let response = delete_embeddings_handler(request_body.clone(), db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // Tenants see their collection names without the tenant.
+        let request_body = DeleteEmbeddingsStruct { collection_name: "team-a/missing".to_string(), ..request_body };
+        let response = delete_embeddings_handler(request_body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = warp::hyper::body::aggregate(response.into_body()).await.unwrap();
+        let body_value: String = serde_json::from_reader(body.reader()).unwrap();
+        assert_eq!(body_value, "Failed to delete embeddings from collection 'missing': NotFound");
+    }
+
+    #[tokio::test]
+    async fn test_alias_handlers() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        db.lock().unwrap().create_collection("books_v1".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+
+        let body: UpdateAliasesStruct = serde_json::from_value(json!({
+            "actions": [{ "create_alias": { "alias": "books", "collection_name": "books_v1" } }]
+        })).unwrap();
+        let response = update_aliases_handler(body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: UpdateAliasesStruct = serde_json::from_value(json!({
+            "actions": [{ "create_alias": { "alias": "books_v1", "collection_name": "books_v1" } }]
+        })).unwrap();
+        let response = update_aliases_handler(body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let request_body = CollectionHandlerStruct { collection_name: "books".to_string() };
+        let response = list_aliases_handler(request_body.clone(), db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = warp::hyper::body::aggregate(response.into_body()).await.unwrap();
+        let body_value: Value = serde_json::from_reader(body.reader()).unwrap();
+        assert_eq!(body_value, json!(["books"]));
+
+        // Endpoints taking a collection name accept the alias.
+        let response = get_collection_handler(request_body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let request_body = CollectionHandlerStruct { collection_name: "movies".to_string() };
+        let response = list_aliases_handler(request_body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_search_metrics_label_collection() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        db.lock().unwrap().create_collection("labelled_v1".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+        let body: UpdateAliasesStruct = serde_json::from_value(json!({
+            "actions": [{ "create_alias": { "alias": "labelled", "collection_name": "labelled_v1" } }]
+        })).unwrap();
+        update_aliases_handler(body, db.clone()).await.unwrap();
+
+        // Searches and cache lookups are labelled with the collection an alias points at, and unknown names aren't labelled at all.
+        for collection_name in ["labelled", "unlabelled"] {
+            let body: GetSimilarityStruct = serde_json::from_value(json!({ "collection_name": collection_name, "query_vector": [1.0, 0.0], "k": 1 })).unwrap();
+            get_similarity_handler(body, db.clone()).await.unwrap();
+            let body: CacheLookupStruct = serde_json::from_value(json!({ "collection_name": collection_name, "vector": [1.0, 0.0], "threshold": 0.9 })).unwrap();
+            cache_lookup_handler(body, db.clone()).await.unwrap();
+        }
+
+        let output = metrics::render(&db.lock().unwrap());
+        assert!(output.contains("memvectordb_search_duration_seconds_count{collection=\"labelled_v1\",kind=\"similarity\"} 1"));
+        assert!(output.contains("memvectordb_search_duration_seconds_count{collection=\"labelled_v1\",kind=\"cache\"} 1"));
+        assert!(!output.contains("memvectordb_search_duration_seconds_count{collection=\"labelled\""));
+        assert!(!output.contains("memvectordb_search_duration_seconds_count{collection=\"unlabelled\""));
+        assert!(output.contains("memvectordb_cache_lookups_total{collection=\"labelled_v1\",result=\"miss\"} 1"));
+        assert!(!output.contains("memvectordb_cache_lookups_total{collection=\"labelled\""));
+    }
+
+    #[tokio::test]
+    async fn test_rename_clone_alter_handlers() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        for name in ["books", "movies"] {
+            db.lock().unwrap().create_collection(name.to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+        }
+
+        let body: RenameCollectionStruct = serde_json::from_value(json!({ "collection_name": "books", "new_name": "shelf" })).unwrap();
+        let response = rename_collection_handler(body.clone(), db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = rename_collection_handler(body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body: CloneCollectionStruct = serde_json::from_value(json!({
+            "collection_name": "shelf", "new_name": "movies", "filter": { "genre": "poetry" }
+        })).unwrap();
+        let response = clone_collection_handler(body.clone(), db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = CloneCollectionStruct { new_name: "poetry".to_string(), ..body };
+        let response = clone_collection_handler(body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let body: AlterCollectionStruct = serde_json::from_value(json!({ "collection_name": "poetry", "text_index_field": "text" })).unwrap();
+        let response = alter_collection_handler(body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(db.lock().unwrap().collections["poetry"].text_index.is_some());
+        let body: AlterCollectionStruct = serde_json::from_value(json!({
+            "collection_name": "poetry", "text_index_field": "text", "drop_text_index": true
+        })).unwrap();
+        let response = alter_collection_handler(body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_cache_handlers() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        db.lock().unwrap().create_collection("prompts".to_string(), 2, Distance::Cosine, CollectionOptions::default()).unwrap();
+
+        let body: CachePutStruct = serde_json::from_value(json!({
+            "collection_name": "prompts", "key": "capital of France?", "vector": [1.0, 0.1], "payload": { "response": "Paris" }, "ttl_secs": 60
+        })).unwrap();
+        let response = cache_put_handler(body.clone(), db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = cache_put_handler(body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        for (vector, expected) in [
+            (json!([1.0, 0.0]), json!({ "hit": true, "id": { "key": "capital of France?" }, "payload": { "response": "Paris" } })),
+            (json!([0.0, 1.0]), json!({ "hit": false })),
+        ] {
+            let body: CacheLookupStruct = serde_json::from_value(json!({ "collection_name": "prompts", "vector": vector, "threshold": 0.9 })).unwrap();
+            let response = cache_lookup_handler(body, db.clone()).await.unwrap().into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = warp::hyper::body::aggregate(response.into_body()).await.unwrap();
+            let mut body: Value = serde_json::from_reader(body.reader()).unwrap();
+            body.as_object_mut().unwrap().remove("score");
+            assert_eq!(body, expected);
+        }
+        let body: CacheLookupStruct = serde_json::from_value(json!({ "collection_name": "missing", "vector": [1.0, 0.0], "threshold": 0.9 })).unwrap();
+        let response = cache_lookup_handler(body, db.clone()).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = CollectionHandlerStruct { collection_name: "prompts".to_string() };
+        let response = cache_stats_handler(body, db.clone()).await.unwrap().into_response();
+        let body = warp::hyper::body::aggregate(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_reader(body.reader()).unwrap();
+        assert_eq!(body, json!({ "collection_name": "prompts", "hits": 1, "misses": 1, "hit_rate": 0.5 }));
+    }
+
 }
 
 
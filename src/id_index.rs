@@ -0,0 +1,74 @@
+use crate::db::hash_map_id;
+use std::collections::HashMap;
+
+/// The positions of embeddings by id, for the embeddings stored where scanning them is too slow.
+///
+/// Ids are indexed by their hash, which keeps the index small, and lookups compare the id stored
+/// at each position with that hash, so that ids sharing a hash are still told apart.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IdIndex {
+    /// The latest position of each hashed id.
+    latest: HashMap<u64, usize>,
+    /// The earlier positions of hashes with more than one position, oldest first. Those are ids
+    /// sharing a hash, or ids inserted again after they were deleted.
+    earlier: HashMap<u64, Vec<usize>>,
+}
+
+impl IdIndex {
+    /// Index the embedding with `id` stored at `position`, after every position indexed so far.
+    pub fn insert(&mut self, id: &HashMap<String, String>, position: usize) {
+        self.insert_hashed(hash_map_id(id), position);
+    }
+
+    fn insert_hashed(&mut self, id_hash: u64, position: usize) {
+        if let Some(previous) = self.latest.insert(id_hash, position) {
+            self.earlier.entry(id_hash).or_default().push(previous);
+        }
+    }
+
+    /// The latest position holding `id`, as told by `holds`, which checks the id stored at a position.
+    pub fn position(&self, id: &HashMap<String, String>, holds: impl Fn(usize) -> bool) -> Option<usize> {
+        self.position_hashed(hash_map_id(id), holds)
+    }
+
+    fn position_hashed(&self, id_hash: u64, holds: impl Fn(usize) -> bool) -> Option<usize> {
+        let latest = self.latest.get(&id_hash).copied()?;
+        if holds(latest) {
+            return Some(latest);
+        }
+        self.earlier.get(&id_hash)?.iter().rev().copied().find(|&position| holds(position))
+    }
+
+    /// The number of positions indexed.
+    pub fn len(&self) -> usize {
+        self.latest.len() + self.earlier.values().map(Vec::len).sum::<usize>()
+    }
+
+    /// An estimate of the memory the index takes.
+    pub fn estimated_memory_bytes(&self) -> usize {
+        self.len() * std::mem::size_of::<(u64, usize)>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions_of_ids_sharing_a_hash() {
+        // Every id is indexed under the same hash, as if they all collided.
+        let stored = ["a", "b", "a", "c"];
+        let mut index = IdIndex::default();
+        for position in 0..stored.len() {
+            index.insert_hashed(7, position);
+        }
+
+        let holds = |wanted: &'static str| move |position: usize| stored[position] == wanted;
+        assert_eq!(index.position_hashed(7, holds("a")), Some(2));
+        assert_eq!(index.position_hashed(7, holds("b")), Some(1));
+        assert_eq!(index.position_hashed(7, holds("c")), Some(3));
+        assert_eq!(index.position_hashed(7, holds("d")), None);
+        assert_eq!(index.position_hashed(8, holds("a")), None);
+        assert_eq!(index.len(), 4);
+    }
+}
@@ -0,0 +1,56 @@
+use serde::Deserialize;
+use std::path::Path;
+
+/// How diagnostic log lines are written.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `2024-09-10 23:28:48 [INFO] message`
+    #[default]
+    Text,
+    /// One JSON object per line with `timestamp`, `level`, `target` and `message` keys.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format '{}'", format)),
+        }
+    }
+}
+
+/// Install the diagnostic logger, writing to stdout and, when given, to `log_file`.
+///
+/// This log is for operators only; the database is persisted by the data log.
+pub fn init_logger(level: log::LevelFilter, format: LogFormat, log_file: Option<&Path>) -> Result<(), fern::InitError> {
+    let mut dispatch = fern::Dispatch::new()
+        .format(move |out, message, record| match format {
+            LogFormat::Text => out.finish(format_args!(
+                "{} [{}] {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                message
+            )),
+            LogFormat::Json => out.finish(format_args!(
+                "{}",
+                serde_json::json!({
+                    "timestamp": chrono::Local::now().to_rfc3339(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": message.to_string(),
+                })
+            )),
+        })
+        .level(level)
+        .chain(std::io::stdout());
+    if let Some(log_file) = log_file {
+        dispatch = dispatch.chain(fern::log_file(log_file)?);
+    }
+    dispatch.apply()?;
+    Ok(())
+}
@@ -1,19 +1,53 @@
+mod auth;
+mod compaction;
+mod config;
+mod data_log;
 mod db;
+mod eviction;
+mod expiry;
+mod grpc;
+mod logging;
+mod metrics;
+mod mmap_store;
+mod partition_index;
+mod shutdown;
 mod similarity;
 mod handlers;
+mod id_index;
 mod model;
 mod response;
+mod readiness;
 mod replay_log;
+mod segment;
+mod semantic_cache;
+mod sparse_index;
+mod tenant;
+mod text_index;
+mod tls;
 
 use handlers::{
     health_checker_handler, 
+    metrics_handler,
+    readiness_handler,
     create_collection_handler, 
     insert_embeddings_handler, 
     get_collection_handler, 
     delete_collection_handler, 
     batch_insert_embeddings_handler, 
+    delete_embeddings_handler,
     get_similarity_handler,
-    get_embeddings_handler
+    get_embeddings_handler,
+    recommend_handler,
+    create_text_index_handler,
+    hybrid_search_handler,
+    update_aliases_handler,
+    list_aliases_handler,
+    rename_collection_handler,
+    clone_collection_handler,
+    alter_collection_handler,
+    cache_lookup_handler,
+    cache_put_handler,
+    cache_stats_handler
 };
 use warp::{Filter,Rejection};
 use crate::model::{
@@ -22,94 +56,281 @@ use crate::model::{
     InsertEmbeddingStruct, 
     CollectionHandlerStruct, 
     BatchInsertEmbeddingsStruct, 
-    GetSimilarityStruct
+    DeleteEmbeddingsStruct,
+    GetSimilarityStruct,
+    RecommendStruct,
+    CreateTextIndexStruct,
+    HybridSearchStruct,
+    UpdateAliasesStruct,
+    RenameCollectionStruct,
+    CloneCollectionStruct,
+    AlterCollectionStruct,
+    CacheLookupStruct,
+    CachePutStruct
 };
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, PoisonError};
 type WebResult<T> = std::result::Result<T, Rejection>;
-use crate::replay_log::restore_db_from_logs;
-use std::env;
+use crate::replay_log::load_db;
+use crate::data_log::{spawn_group_commit, Durability};
+use crate::compaction::spawn_compaction;
+use crate::expiry::spawn_expiry_sweeper;
+use crate::readiness::{handle_not_ready, require_ready, RestoreProgress};
+use crate::auth::{authorized_json, handle_auth_rejection, Role};
+use crate::config::{Cli, Config};
+use crate::logging::init_logger;
+use clap::Parser;
+use log::{error, info};
+use tokio::sync::watch;
 
 
 #[tokio::main]
 async fn main() {
+    let config = match Config::load(&Cli::parse().serve_args()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = init_logger(config.log_level, config.log_format, config.log_file.as_deref()) {
+        eprintln!("❌ Failed to set up logging: {}", err);
+        std::process::exit(1);
+    }
+
     // Create a shared CacheDB instance wrapped in Mutex and Arc
-    let db = Arc::new(Mutex::new(CacheDB::new()));
+    let mut cache_db = CacheDB::new();
+    cache_db.set_limits(config.limits);
+    cache_db.set_quotas(config.tenants.clone());
+    if let Err(err) = cache_db.set_collections_dir(config.collections_dir()) {
+        eprintln!("❌ Failed to prepare directory '{}': {}", config.collections_dir().display(), err);
+        std::process::exit(1);
+    }
+    let db = Arc::new(Mutex::new(cache_db));
+
+    // Restore in the background so the server can answer liveness and readiness probes meanwhile.
+    let progress = Arc::new(RestoreProgress::default());
+    let ready_progress = progress.clone();
+    {
+        let db = db.clone();
+        let progress = progress.clone();
+        let (restore, durability) = (config.restore.then_some(config.restore_mode), config.durability);
+        let (data_log_path, legacy_log_path, quarantine_path) = (config.data_log_path(), config.legacy_log_path(), config.quarantine_log_path());
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = load_db(&db, &progress, restore, &data_log_path, &legacy_log_path, &quarantine_path, durability) {
+                eprintln!("❌ {}", err);
+                std::process::exit(1);
+            }
+        });
+    }
+
+    if config.durability == Durability::GroupCommit {
+        spawn_group_commit(db.clone(), config.group_commit_interval);
+    }
+    spawn_compaction(db.clone(), config.compaction_interval, config.compaction_threshold, progress.clone());
+    spawn_expiry_sweeper(db.clone(), config.expiry_sweep_interval, progress.clone());
 
-    if env::var("RESTORE_DB").is_ok() {
-        let _restored_db = restore_db_from_logs(db.clone());
+    let auth = Arc::new(config.auth);
+    if !auth.is_enabled() {
+        println!("⚠️ No API keys configured, authentication is disabled");
     }
+    let grpc_service = grpc::VectorDbService::new(db.clone(), auth.clone(), progress.clone());
 
     let health_checker_route = warp::path!("healthchecker")
         .and(warp::get())
         .and_then(health_checker_handler);
 
     // Define the filter to inject the shared CacheDB instance into request handlers
+    let shutdown_db = db.clone();
     let with_db = warp::any().map(move || db.clone());
 
+    let readiness_route = warp::path!("readyz")
+        .and(warp::get())
+        .and(warp::any().map(move || progress.clone()))
+        .and_then(readiness_handler);
+
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(with_db.clone())
+        .and_then(metrics_handler);
+
     let create_collection_route = warp::path!("create_collection")
         .and(warp::post())
-        .and(warp::body::json::<CreateCollectionStruct>())
+        .and(authorized_json::<CreateCollectionStruct>(auth.clone(), Role::Admin))
         .and(with_db.clone())
         .and_then(create_collection_handler);
 
     let insert_embeddings_route = warp::path!("insert_embeddings")
         .and(warp::put())
-        .and(warp::body::json::<InsertEmbeddingStruct>())
+        .and(authorized_json::<InsertEmbeddingStruct>(auth.clone(), Role::ReadWrite))
         .and(with_db.clone())
         .and_then(insert_embeddings_handler);
 
     let get_collection_route = warp::path!("get_collection")
         .and(warp::get())
-        .and(warp::body::json::<CollectionHandlerStruct>())
+        .and(authorized_json::<CollectionHandlerStruct>(auth.clone(), Role::Read))
         .and(with_db.clone())
         .and_then(get_collection_handler);
 
     let delete_collection_route = warp::path!("delete_collection")
         .and(warp::delete())
-        .and(warp::body::json::<CollectionHandlerStruct>())
+        .and(authorized_json::<CollectionHandlerStruct>(auth.clone(), Role::Admin))
         .and(with_db.clone())
         .and_then(delete_collection_handler);
 
     let batch_insert_embeddings_route = warp::path!("batch_insert_embeddings")
         .and(warp::put())
-        .and(warp::body::json::<BatchInsertEmbeddingsStruct>())
+        .and(authorized_json::<BatchInsertEmbeddingsStruct>(auth.clone(), Role::ReadWrite))
         .and(with_db.clone())
         .and_then(batch_insert_embeddings_handler);
 
+    let delete_embeddings_route = warp::path!("delete_embeddings")
+        .and(warp::delete())
+        .and(authorized_json::<DeleteEmbeddingsStruct>(auth.clone(), Role::ReadWrite))
+        .and(with_db.clone())
+        .and_then(delete_embeddings_handler);
+
     let get_similarity_route = warp::path!("get_similarity")
         .and(warp::get())
-        .and(warp::body::json::<GetSimilarityStruct>())
+        .and(authorized_json::<GetSimilarityStruct>(auth.clone(), Role::Read))
         .and(with_db.clone())
         .and_then(get_similarity_handler);
 
+    let recommend_route = warp::path!("recommend")
+        .and(warp::get())
+        .and(authorized_json::<RecommendStruct>(auth.clone(), Role::Read))
+        .and(with_db.clone())
+        .and_then(recommend_handler);
+
+    let create_text_index_route = warp::path!("create_text_index")
+        .and(warp::post())
+        .and(authorized_json::<CreateTextIndexStruct>(auth.clone(), Role::Admin))
+        .and(with_db.clone())
+        .and_then(create_text_index_handler);
+
+    let hybrid_search_route = warp::path!("hybrid_search")
+        .and(warp::get())
+        .and(authorized_json::<HybridSearchStruct>(auth.clone(), Role::Read))
+        .and(with_db.clone())
+        .and_then(hybrid_search_handler);
+
     let get_embeddings_route = warp::path!("get_embeddings")
         .and(warp::get())
-        .and(warp::body::json::<CollectionHandlerStruct>())
+        .and(authorized_json::<CollectionHandlerStruct>(auth.clone(), Role::Read))
         .and(with_db.clone())
         .and_then(get_embeddings_handler);
 
+    let update_aliases_route = warp::path!("update_aliases")
+        .and(warp::post())
+        .and(authorized_json::<UpdateAliasesStruct>(auth.clone(), Role::Admin))
+        .and(with_db.clone())
+        .and_then(update_aliases_handler);
+
+    let list_aliases_route = warp::path!("list_aliases")
+        .and(warp::get())
+        .and(authorized_json::<CollectionHandlerStruct>(auth.clone(), Role::Read))
+        .and(with_db.clone())
+        .and_then(list_aliases_handler);
+
+    let rename_collection_route = warp::path!("rename_collection")
+        .and(warp::post())
+        .and(authorized_json::<RenameCollectionStruct>(auth.clone(), Role::Admin))
+        .and(with_db.clone())
+        .and_then(rename_collection_handler);
+
+    let clone_collection_route = warp::path!("clone_collection")
+        .and(warp::post())
+        .and(authorized_json::<CloneCollectionStruct>(auth.clone(), Role::Admin))
+        .and(with_db.clone())
+        .and_then(clone_collection_handler);
+
+    let alter_collection_route = warp::path!("alter_collection")
+        .and(warp::post())
+        .and(authorized_json::<AlterCollectionStruct>(auth.clone(), Role::Admin))
+        .and(with_db.clone())
+        .and_then(alter_collection_handler);
+
+    let cache_lookup_route = warp::path!("cache_lookup")
+        .and(warp::get())
+        .and(authorized_json::<CacheLookupStruct>(auth.clone(), Role::Read))
+        .and(with_db.clone())
+        .and_then(cache_lookup_handler);
+
+    let cache_put_route = warp::path!("cache_put")
+        .and(warp::put())
+        .and(authorized_json::<CachePutStruct>(auth.clone(), Role::ReadWrite))
+        .and(with_db.clone())
+        .and_then(cache_put_handler);
+
+    let cache_stats_route = warp::path!("cache_stats")
+        .and(warp::get())
+        .and(authorized_json::<CollectionHandlerStruct>(auth.clone(), Role::Read))
+        .and(with_db.clone())
+        .and_then(cache_stats_handler);
+
     // Define CORS
     let cors = warp::cors()
         .allow_any_origin() // define URL 
         .allow_methods(vec!["GET", "POST", "PUT", "DELETE"])
-        .allow_headers(vec!["Content-Type"]);
+        .allow_headers(vec!["Content-Type", "Authorization", "X-Api-Key", "X-Tenant"]);
 
     // Combine the routes
-    let routes = health_checker_route
-        .or(create_collection_route)
+    // Data routes answer 503 until the database is restored.
+    let data_routes = create_collection_route
         .or(insert_embeddings_route)
         .or(get_collection_route)
         .or(delete_collection_route)
         .or(batch_insert_embeddings_route)
+        .or(delete_embeddings_route)
         .or(get_similarity_route)
+        .or(recommend_route)
+        .or(create_text_index_route)
+        .or(hybrid_search_route)
         .or(get_embeddings_route)
-        .with(cors);
+        .or(update_aliases_route)
+        .or(list_aliases_route)
+        .or(rename_collection_route)
+        .or(clone_collection_route)
+        .or(alter_collection_route)
+        .or(cache_lookup_route)
+        .or(cache_put_route)
+        .or(cache_stats_route);
+
+    let routes = health_checker_route
+        .or(readiness_route)
+        .or(metrics_route)
+        .or(require_ready(ready_progress).and(tenant::skip_path_prefix()).and(data_routes))
+        .recover(handle_auth_rejection)
+        .recover(handle_not_ready)
+        .with(cors)
+        .with(warp::log::custom(metrics::record_request));
 
     // Start the server
+    let (stop, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        let _ = stop.send(true);
+    });
     println!("🚀 Server started successfully");
-    warp::serve(routes)
-        .run(([0, 0, 0, 0], 8000))
-        .await;
+    tokio::join!(
+        tls::serve(routes, config.addr, config.tls.clone(), shutdown.clone(), config.shutdown_timeout),
+        grpc::serve(grpc_service, config.grpc_addr, config.tls, shutdown, config.shutdown_timeout),
+    );
+
+    // Persist everything accepted before exiting.
+    let mut db_lock = shutdown_db.lock().unwrap_or_else(PoisonError::into_inner);
+    // A failed snapshot leaves the data log as it was, which is still synced before exiting.
+    let snapshot_result = if config.snapshot_on_shutdown { db_lock.snapshot() } else { Ok(()) };
+    if db_lock.sync_data_log().is_err() {
+        error!("Exiting without syncing the data log");
+        std::process::exit(1);
+    }
+    if let Err(err) = snapshot_result {
+        error!("Exiting without writing the snapshot: {}", err);
+        std::process::exit(1);
+    }
+    info!("Shutdown complete");
+    // Exit without waiting for a restore that may still be running in a blocking task.
+    std::process::exit(0);
 
 }
 
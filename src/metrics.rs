@@ -0,0 +1,144 @@
+use crate::model::CacheDB;
+use prometheus::{
+    register_counter_vec, register_gauge, register_gauge_vec, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, CounterVec, Encoder, Gauge, GaugeVec, Histogram, HistogramVec, IntCounter, IntCounterVec, TextEncoder,
+};
+use std::sync::LazyLock;
+
+/// The routes requests are labelled with; anything else is counted as `unmatched` to bound the label values.
+const ROUTES: [&str; 22] = [
+    "/healthchecker",
+    "/readyz",
+    "/metrics",
+    "/create_collection",
+    "/insert_embeddings",
+    "/get_collection",
+    "/delete_collection",
+    "/batch_insert_embeddings",
+    "/delete_embeddings",
+    "/get_similarity",
+    "/recommend",
+    "/create_text_index",
+    "/hybrid_search",
+    "/get_embeddings",
+    "/update_aliases",
+    "/list_aliases",
+    "/rename_collection",
+    "/clone_collection",
+    "/alter_collection",
+    "/cache_lookup",
+    "/cache_put",
+    "/cache_stats",
+];
+
+pub static HTTP_REQUESTS: LazyLock<CounterVec> = LazyLock::new(|| {
+    register_counter_vec!("memvectordb_http_requests_total", "HTTP requests by route, method and status", &["route", "method", "status"]).unwrap()
+});
+
+pub static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!("memvectordb_http_request_duration_seconds", "HTTP request latency by route", &["route"]).unwrap()
+});
+
+pub static SEARCH_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!("memvectordb_search_duration_seconds", "Search latency by collection and kind of search", &["collection", "kind"]).unwrap()
+});
+
+pub static LOCK_WAIT: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "memvectordb_lock_wait_seconds",
+        "Time requests waited for the database lock",
+        vec![0.00001, 0.0001, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0]
+    )
+    .unwrap()
+});
+
+pub static COLLECTION_VECTORS: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!("memvectordb_collection_vectors", "Embeddings stored per collection", &["collection"]).unwrap()
+});
+
+pub static COLLECTION_MEMORY: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!("memvectordb_collection_memory_bytes", "Estimated memory used per collection", &["collection"]).unwrap()
+});
+
+pub static DATA_LOG_BYTES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("memvectordb_data_log_bytes_written_total", "Bytes appended to the data log").unwrap()
+});
+
+pub static LAST_SNAPSHOT: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge!("memvectordb_last_snapshot_timestamp_seconds", "Unix time of the last snapshot of the data log, 0 if none was written").unwrap()
+});
+
+pub static RESTORE_DURATION: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge!("memvectordb_restore_duration_seconds", "Time taken to restore the database at startup").unwrap()
+});
+
+pub static EVICTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("memvectordb_evictions_total", "Embeddings evicted to stay within limits, by collection and policy", &["collection", "policy"]).unwrap()
+});
+
+pub static LIMIT_REJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("memvectordb_limit_rejections_total", "Writes rejected for going over limits, by collection", &["collection"]).unwrap()
+});
+
+pub static CACHE_LOOKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("memvectordb_cache_lookups_total", "Semantic cache lookups by collection and result", &["collection", "result"]).unwrap()
+});
+
+/// Record a finished HTTP request, as reported by `warp::log::custom`. Requests of every tenant
+/// share the label of their route.
+pub fn record_request(info: warp::log::Info) {
+    let path = crate::tenant::strip_path(info.path());
+    let route = ROUTES.iter().find(|route| **route == path).copied().unwrap_or("unmatched");
+    HTTP_REQUESTS.with_label_values(&[route, info.method().as_str(), info.status().as_str()]).inc();
+    HTTP_REQUEST_DURATION.with_label_values(&[route]).observe(info.elapsed().as_secs_f64());
+}
+
+/// Render every metric in the Prometheus text format, refreshing the per-collection gauges from `db`.
+pub fn render(db: &CacheDB) -> String {
+    COLLECTION_VECTORS.reset();
+    COLLECTION_MEMORY.reset();
+    for (name, collection) in &db.collections {
+        COLLECTION_VECTORS.with_label_values(&[name]).set(collection.embedding_count() as f64);
+        COLLECTION_MEMORY.with_label_values(&[name]).set(collection.estimated_memory_bytes() as f64);
+    }
+
+    // Touch the metrics that are only updated by rare events so they're exported from the start.
+    LazyLock::force(&LAST_SNAPSHOT);
+    LazyLock::force(&RESTORE_DURATION);
+    LazyLock::force(&DATA_LOG_BYTES);
+    LazyLock::force(&LOCK_WAIT);
+
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        log::error!("Failed to encode metrics: {}", err);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CollectionOptions, Distance, Embedding};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_render_metrics() {
+        let mut db = CacheDB::new();
+        db.create_collection("metrics_collection".to_string(), 3, Distance::DotProduct, CollectionOptions::default()).unwrap();
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), "0".to_string());
+        db.insert_into_collection("metrics_collection", Embedding { id, vector: vec![1.0, 2.0, 3.0], ..Default::default() }).unwrap();
+
+        SEARCH_DURATION.with_label_values(&["metrics_collection", "similarity"]).observe(0.01);
+
+        let output = render(&db);
+        assert!(output.contains("memvectordb_collection_vectors{collection=\"metrics_collection\"} 1"));
+        assert!(output.contains("memvectordb_collection_memory_bytes{collection=\"metrics_collection\"}"));
+        assert!(output.contains("memvectordb_search_duration_seconds_count{collection=\"metrics_collection\",kind=\"similarity\"} 1"));
+        // Other tests may write snapshots concurrently, so only check that the persistence metrics are exported.
+        assert!(output.contains("memvectordb_last_snapshot_timestamp_seconds "));
+        assert!(output.contains("memvectordb_data_log_bytes_written_total "));
+        assert!(output.contains("memvectordb_restore_duration_seconds 0"));
+    }
+}
@@ -0,0 +1,313 @@
+use crate::id_index::IdIndex;
+use crate::model::Embedding;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The number of embeddings in each segment, after which a new segment is started.
+const SEGMENT_ROWS: usize = 65_536;
+
+/// The embeddings of a collection kept in memory-mapped segment files instead of on the heap.
+///
+/// Each segment holds up to `SEGMENT_ROWS` embeddings in two files: `.vec` with the vectors as
+/// consecutive rows of native-endian `f32`s, and `.jsonl` with the ids, metadata and expiry times as JSON lines.
+/// Only the positions of the records and an index of the ids stay on the heap, and the OS page
+/// cache decides which parts of the files are in memory.
+///
+/// The files are derived from the data log and rebuilt from it on restore, so they're written
+/// without syncing. Clones share the files, which only the collection held by the database appends to.
+#[derive(Debug, Clone)]
+pub struct MmapStore {
+    dir: PathBuf,
+    dimension: usize,
+    segments: Vec<Segment>,
+    /// The offset and length of each embedding's record in the `.jsonl` file of its segment.
+    records: Vec<(u64, usize)>,
+    /// The positions of the embeddings by id.
+    positions: IdIndex,
+}
+
+#[derive(Debug, Clone)]
+struct Segment {
+    vectors: Arc<File>,
+    records: Arc<File>,
+    /// The mapped files, `None` while they're empty since empty files can't be mapped.
+    vector_map: Option<Arc<Mmap>>,
+    record_map: Option<Arc<Mmap>>,
+    records_len: u64,
+}
+
+/// The part of an embedding kept in the `.jsonl` files.
+#[derive(Serialize)]
+struct StoredRecord<'a> {
+    id: &'a HashMap<String, String>,
+    metadata: &'a Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+}
+
+/// The id of a record in the `.jsonl` files, read without its metadata.
+#[derive(Deserialize)]
+struct StoredId {
+    id: HashMap<String, String>,
+}
+
+impl PartialEq for MmapStore {
+    /// Stores are equal when they're backed by the same files and hold as many embeddings.
+    fn eq(&self, other: &Self) -> bool {
+        self.dir == other.dir && self.len() == other.len()
+    }
+}
+
+impl MmapStore {
+    /// Create an empty store for vectors of `dimension` values in `dir`, removing anything already there.
+    pub fn create(dir: &Path, dimension: usize) -> io::Result<Self> {
+        match std::fs::remove_dir_all(dir) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        std::fs::create_dir_all(dir)?;
+        Ok(Self { dir: dir.to_path_buf(), dimension, segments: Vec::new(), records: Vec::new(), positions: IdIndex::default() })
+    }
+
+    /// Move the files of the store to `dir`, replacing anything already there.
+    pub fn relocate(&mut self, dir: &Path) -> io::Result<()> {
+        match std::fs::remove_dir_all(dir) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        std::fs::rename(&self.dir, dir)?;
+        self.dir = dir.to_path_buf();
+        Ok(())
+    }
+
+    /// Delete the files of the store.
+    pub fn remove(self) -> io::Result<()> {
+        std::fs::remove_dir_all(&self.dir)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    /// The position of the latest embedding with the given id, the only one that may not be deleted.
+    pub fn position(&self, id: &HashMap<String, String>) -> Option<usize> {
+        self.positions.position(id, |index| {
+            let stored = self.record(index).ok().and_then(|record| serde_json::from_slice::<StoredId>(record).ok());
+            stored.is_some_and(|stored| stored.id == *id)
+        })
+    }
+
+    /// Whether `other` is a clone of this store, rather than one created later in the same directory.
+    pub fn shares_files(&self, other: &MmapStore) -> bool {
+        match (self.segments.first(), other.segments.first()) {
+            (Some(segment), Some(other)) => Arc::ptr_eq(&segment.vectors, &other.vectors),
+            (None, None) => self.dir == other.dir,
+            _ => false,
+        }
+    }
+
+    /// An estimate of the heap memory the store takes, since the files are paged in by the OS.
+    pub fn estimated_memory_bytes(&self) -> usize {
+        self.records.len() * std::mem::size_of::<(u64, usize)>() + self.positions.estimated_memory_bytes()
+    }
+
+    /// Append embeddings with dense vectors of the store's dimension, which the caller has checked.
+    ///
+    /// Nothing is added if writing fails.
+    pub fn append(&mut self, embeddings: &[Embedding]) -> io::Result<()> {
+        let mut records = Vec::with_capacity(embeddings.len());
+        let mut start = 0;
+        while start < embeddings.len() {
+            let row = self.len() + start;
+            let segment_index = row / SEGMENT_ROWS;
+            if segment_index == self.segments.len() {
+                self.segments.push(self.create_segment(segment_index)?);
+            }
+            let end = embeddings.len().min(start + SEGMENT_ROWS - row % SEGMENT_ROWS);
+
+            let mut vector_bytes = Vec::with_capacity((end - start) * self.row_bytes());
+            let mut record_bytes = Vec::new();
+            let records_len = self.segments[segment_index].records_len;
+            for embedding in &embeddings[start..end] {
+                vector_bytes.extend(embedding.vector.iter().flat_map(|value| value.to_ne_bytes()));
+                let offset = records_len + record_bytes.len() as u64;
+                serde_json::to_writer(&mut record_bytes, &StoredRecord { id: &embedding.id, metadata: &embedding.metadata, expires_at: embedding.expires_at })?;
+                records.push((offset, records_len as usize + record_bytes.len() - offset as usize));
+                record_bytes.push(b'\n');
+            }
+
+            let row_bytes = self.row_bytes() as u64;
+            let segment = &mut self.segments[segment_index];
+            write_at(&segment.vectors, (row % SEGMENT_ROWS) as u64 * row_bytes, &vector_bytes)?;
+            write_at(&segment.records, records_len, &record_bytes)?;
+            segment.vector_map = map(&segment.vectors)?;
+            segment.record_map = map(&segment.records)?;
+            segment.records_len += record_bytes.len() as u64;
+            start = end;
+        }
+
+        for (embedding, record) in embeddings.iter().zip(records) {
+            self.positions.insert(&embedding.id, self.records.len());
+            self.records.push(record);
+        }
+        Ok(())
+    }
+
+    /// The vector of the embedding at `index`, read from the mapped segment.
+    pub fn vector(&self, index: usize) -> &[f32] {
+        let Some(vector_map) = &self.segments[index / SEGMENT_ROWS].vector_map else {
+            return &[];
+        };
+        let start = (index % SEGMENT_ROWS) * self.row_bytes();
+        let bytes = &vector_map[start..start + self.row_bytes()];
+        // SAFETY: The map starts on a page boundary and rows are whole `f32`s, so `bytes` is
+        // aligned for `f32`. It holds `dimension` values written with `to_ne_bytes`.
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr().cast::<f32>(), self.dimension) }
+    }
+
+    /// The embedding at `index`, with its vector copied out of the mapped segment.
+    pub fn embedding(&self, index: usize) -> io::Result<Embedding> {
+        let mut embedding: Embedding = serde_json::from_slice(self.record(index)?)?;
+        embedding.vector = self.vector(index).to_vec();
+        Ok(embedding)
+    }
+
+    /// The JSON record of the embedding at `index`, read from the mapped segment.
+    fn record(&self, index: usize) -> io::Result<&[u8]> {
+        let (offset, len) = self.records[index];
+        let record_map = self.segments[index / SEGMENT_ROWS].record_map.as_ref().ok_or(io::ErrorKind::UnexpectedEof)?;
+        record_map.get(offset as usize..offset as usize + len).ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn row_bytes(&self) -> usize {
+        self.dimension * std::mem::size_of::<f32>()
+    }
+
+    fn create_segment(&self, index: usize) -> io::Result<Segment> {
+        let open = |extension: &str| {
+            OpenOptions::new().read(true).write(true).create(true).truncate(true).open(self.dir.join(format!("segment-{:05}.{}", index, extension)))
+        };
+        Ok(Segment {
+            vectors: Arc::new(open("vec")?),
+            records: Arc::new(open("jsonl")?),
+            vector_map: None,
+            record_map: None,
+            records_len: 0,
+        })
+    }
+}
+
+fn write_at(mut file: &File, offset: u64, bytes: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(bytes)
+}
+
+fn map(file: &File) -> io::Result<Option<Arc<Mmap>>> {
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: The files are never truncated, and bytes of rows in use are never rewritten. Only
+    // the bytes left past them by a failed append are, which nothing reads.
+    Ok(Some(Arc::new(unsafe { Mmap::map(file)? })))
+}
+
+/// The name of the directory holding the files of a collection, with characters other than ASCII
+/// letters, digits, `-` and `_` escaped so that any collection name is a safe file name.
+pub fn collection_dir_name(collection_name: &str) -> String {
+    let mut dir_name = String::new();
+    for byte in collection_name.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            dir_name.push(byte as char);
+        } else {
+            dir_name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    dir_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(index: usize) -> Embedding {
+        let mut id = HashMap::new();
+        id.insert("unique_id".to_string(), index.to_string());
+        let mut metadata = HashMap::new();
+        metadata.insert("text".to_string(), format!("document {}", index));
+        // Some embeddings expire, so that reading them back checks their expiry times are kept.
+        let expires_at = index.is_multiple_of(2).then_some(index as u64);
+        Embedding { id, vector: vec![index as f32, 0.5], metadata: Some(metadata), expires_at, ..Default::default() }
+    }
+
+    #[test]
+    fn test_append_across_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = MmapStore::create(&dir.path().join("collection"), 2).unwrap();
+
+        let embeddings: Vec<Embedding> = (0..SEGMENT_ROWS + 10).map(embedding).collect();
+        store.append(&embeddings[..5]).unwrap();
+        store.append(&embeddings[5..]).unwrap();
+
+        assert_eq!(store.len(), embeddings.len());
+        assert_eq!(store.segments.len(), 2);
+        for index in [0, 4, 5, SEGMENT_ROWS - 1, SEGMENT_ROWS, SEGMENT_ROWS + 9] {
+            assert_eq!(store.vector(index), embeddings[index].vector.as_slice());
+            assert_eq!(store.embedding(index).unwrap(), embeddings[index]);
+            assert_eq!(store.position(&embeddings[index].id), Some(index));
+        }
+        assert_eq!(store.position(&embedding(SEGMENT_ROWS + 10).id), None);
+        assert!(store.shares_files(&store.clone()));
+
+        // Moving the files keeps the store working.
+        store.relocate(&dir.path().join("renamed")).unwrap();
+        assert!(!dir.path().join("collection").exists());
+        assert_eq!(store.embedding(SEGMENT_ROWS).unwrap(), embeddings[SEGMENT_ROWS]);
+        store.append(&[embedding(SEGMENT_ROWS + 10)]).unwrap();
+        assert_eq!(store.embedding(SEGMENT_ROWS + 10).unwrap(), embedding(SEGMENT_ROWS + 10));
+
+        // Creating the store again starts from nothing.
+        let recreated = MmapStore::create(&dir.path().join("collection"), 2).unwrap();
+        assert_eq!(recreated.len(), 0);
+        assert!(!recreated.shares_files(&store));
+        recreated.remove().unwrap();
+        assert!(!dir.path().join("collection").exists());
+    }
+
+    #[test]
+    fn test_multi_key_positions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = MmapStore::create(&dir.path().join("collection"), 2).unwrap();
+
+        // Equal ids built with their keys in another order may iterate differently, and are still found.
+        let id = |index: usize, keys: &[&str]| keys.iter().map(|key| (key.to_string(), format!("{}-{}", key, index))).collect::<HashMap<_, _>>();
+        let keys = ["tenant", "user", "document", "chunk", "page", "section"];
+        let reversed: Vec<&str> = keys.iter().rev().copied().collect();
+        let embeddings: Vec<Embedding> = (0..10).map(|index| Embedding { id: id(index, &keys), vector: vec![0.0, 0.0], ..Default::default() }).collect();
+        store.append(&embeddings).unwrap();
+
+        for index in 0..10 {
+            assert_eq!(store.position(&id(index, &reversed)), Some(index));
+        }
+        assert_eq!(store.position(&id(10, &reversed)), None);
+        assert_eq!(store.position(&id(0, &keys[1..])), None);
+    }
+
+    #[test]
+    fn test_collection_dir_name() {
+        assert_eq!(collection_dir_name("my_collection-1"), "my_collection-1");
+        assert_eq!(collection_dir_name("../a b"), "%2E%2E%2Fa%20b");
+    }
+}
@@ -1,10 +1,34 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use schemars::JsonSchema;
+use crate::data_log::DataLog;
+use crate::eviction::Usage;
+use crate::expiry::Expiries;
+use crate::semantic_cache::CacheStats;
+use crate::mmap_store::MmapStore;
+use crate::partition_index::PartitionIndex;
+use crate::segment::Segment;
+use crate::sparse_index::SparseIndex;
+use crate::text_index::TextIndex;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct CacheDB {
 	pub collections: HashMap<String, Collection>,
+	/// The collection each alias refers to. Aliases never share a name with a collection.
+	#[serde(default)]
+	pub aliases: HashMap<String, String>,
+	#[serde(skip)]
+	pub data_log: Option<DataLog>,
+	/// The directory holding the files of collections with `Storage::Mmap`.
+	#[serde(skip)]
+	pub collections_dir: Option<std::path::PathBuf>,
+	/// The limits on the embeddings of every collection together.
+	#[serde(skip)]
+	pub limits: Limits,
+	/// The quotas of each tenant, on the collections in its namespace.
+	#[serde(skip)]
+	pub quotas: HashMap<String, Quota>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema, PartialEq)]
@@ -17,14 +41,203 @@ pub struct SimilarityResult {
 pub struct Collection {
 	pub dimension: usize,
 	pub distance: Distance,
+	/// The mutable segment receiving inserts, sealed into `sealed` once it holds `SEGMENT_CAPACITY` embeddings.
 	#[serde(default)]
 	pub embeddings: Vec<Embedding>,
+	/// The sealed segments, holding the embeddings inserted before those in `embeddings`, oldest first.
+	#[serde(skip)]
+	pub sealed: Vec<Arc<Segment>>,
+	/// The positions of deleted embeddings, which searches skip until compaction drops them.
+	#[serde(skip)]
+	pub tombstones: HashSet<usize>,
+	/// When the embeddings with a TTL expire, until they are deleted.
+	#[serde(skip)]
+	pub expiries: Expiries,
+	/// How the embeddings that aren't deleted are used, which orders them for eviction.
+	#[serde(skip)]
+	pub usage: Usage,
+	/// The estimated size of the embeddings that aren't deleted, which memory limits apply to.
+	#[serde(skip)]
+	pub live_bytes: usize,
+	/// The hits and misses of cache lookups on the collection.
+	#[serde(skip)]
+	pub cache_stats: CacheStats,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub text_index: Option<TextIndex>,
+	#[serde(flatten)]
+	pub options: CollectionOptions,
+	#[serde(skip)]
+	pub sparse_index: SparseIndex,
+	/// The positions of the embeddings by the value of the partition key, if the collection has one.
+	#[serde(skip)]
+	pub partition_index: PartitionIndex,
+	/// The embeddings of a collection with `Storage::Mmap`, which keeps `embeddings` empty.
+	#[serde(skip)]
+	pub store: Option<MmapStore>,
 }
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema, PartialEq)]
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, JsonSchema, PartialEq)]
+pub struct CollectionOptions {
+	#[serde(default)]
+	pub vector_type: VectorType,
+	/// Dense vector fields stored alongside the default vector, by name.
+	#[serde(default, skip_serializing_if = "HashMap::is_empty")]
+	pub vectors: HashMap<String, VectorParams>,
+	#[serde(default, skip_serializing_if = "Storage::is_memory")]
+	pub storage: Storage,
+	/// How many seconds after insertion embeddings expire, unless they set their own TTL.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub ttl_secs: Option<u64>,
+	/// The metadata field grouping the embeddings into partitions, which every embedding must set.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub partition_key: Option<String>,
+	#[serde(flatten)]
+	pub limits: Limits,
+}
+
+/// Caps on the embeddings of a collection, or of the whole database, and what happens to writes
+/// that would go over them.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct Limits {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub max_vectors: Option<usize>,
+	/// The estimated size of the embeddings, as counted for `Embedding::estimated_memory_bytes`.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub max_memory_bytes: Option<usize>,
+	/// What to do once a limit is reached. Collections without one follow the policy of the database.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub eviction_policy: Option<EvictionPolicy>,
+}
+
+/// How room is made for writes that would go over a limit.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+	/// Reject the write.
+	#[default]
+	RejectWrites,
+	/// Evict the embeddings least recently returned by a search, or inserted if never returned.
+	Lru,
+	/// Evict the embeddings returned by the fewest searches, the least recently used first among equals.
+	Lfu,
+	/// Evict the embeddings inserted first.
+	Fifo,
+}
+
+impl EvictionPolicy {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			EvictionPolicy::RejectWrites => "reject_writes",
+			EvictionPolicy::Lru => "lru",
+			EvictionPolicy::Lfu => "lfu",
+			EvictionPolicy::Fifo => "fifo",
+		}
+	}
+}
+
+impl std::str::FromStr for EvictionPolicy {
+	type Err = String;
+
+	fn from_str(policy: &str) -> Result<Self, Self::Err> {
+		match policy {
+			"reject_writes" => Ok(EvictionPolicy::RejectWrites),
+			"lru" => Ok(EvictionPolicy::Lru),
+			"lfu" => Ok(EvictionPolicy::Lfu),
+			"fifo" => Ok(EvictionPolicy::Fifo),
+			_ => Err(format!("Unknown eviction policy '{}'", policy)),
+		}
+	}
+}
+
+/// Caps on the collections of a tenant. Writes over them are rejected rather than evicting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+	pub max_collections: Option<usize>,
+	pub max_vectors: Option<usize>,
+	pub max_memory_bytes: Option<usize>,
+}
+
+/// Changes to the settings of an existing collection, applied together. Settings the embeddings
+/// depend on, such as the dimension and distance, can't be changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct CollectionChanges {
+	/// The storage to move the embeddings to.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub storage: Option<Storage>,
+	/// The metadata field to index for text search, replacing the text index if there is one.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub text_index_field: Option<String>,
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub drop_text_index: bool,
+	/// The limits replacing those of the collection.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub limits: Option<Limits>,
+}
+
+/// Where the embeddings of a collection are kept.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub enum Storage {
+	/// On the heap.
+	#[default]
+	#[serde(rename = "memory")]
+	Memory,
+	/// In memory-mapped files on disk, paged in by the OS as they're searched. Only dense
+	/// collections without named vectors can use it.
+	#[serde(rename = "mmap")]
+	Mmap,
+}
+
+impl Storage {
+	pub fn is_memory(&self) -> bool {
+		*self == Storage::Memory
+	}
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct VectorParams {
+	pub dimension: usize,
+	pub distance: Distance,
+}
+
+/// The kind of vector the embeddings of a collection carry.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub enum VectorType {
+	/// `Embedding.vector`, with exactly `dimension` values.
+	#[default]
+	#[serde(rename = "dense")]
+	Dense,
+	/// `Embedding.sparse_vector`, with indices below `dimension` and scored by dot product.
+	#[serde(rename = "sparse")]
+	Sparse,
+	/// `Embedding.multivector`, one or more token vectors of `dimension` values scored by MaxSim.
+	#[serde(rename = "multi")]
+	Multi,
+}
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, JsonSchema, PartialEq)]
 pub struct Embedding {
 	pub id: HashMap<String, String>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
 	pub vector: Vec<f32>,
 	pub metadata: Option<HashMap<String, String>>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sparse_vector: Option<SparseVector>,
+	#[serde(default, skip_serializing_if = "HashMap::is_empty")]
+	pub vectors: HashMap<String, Vec<f32>>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub multivector: Vec<Vec<f32>>,
+	/// How many seconds after insertion the embedding expires, overriding the TTL of the collection.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub ttl_secs: Option<u64>,
+	/// When the embedding expires, in seconds since the Unix epoch. Set from the TTL on insertion.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub expires_at: Option<u64>,
+}
+
+/// A sparse vector given as parallel lists of non-zero dimensions and their values.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, JsonSchema, PartialEq)]
+pub struct SparseVector {
+	pub indices: Vec<u32>,
+	pub values: Vec<f32>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
@@ -50,11 +263,62 @@ pub enum Error {
 	#[error("Collection doesn't exist")]
 	NotFound,
 
+	#[error("Embedding doesn't exist")]
+	EmbeddingNotFound,
+
+	#[error("At least one positive embedding id is required")]
+	NoPositiveIds,
+
+	#[error("Text index already exists")]
+	TextIndexUniqueViolation,
+
+	#[error("Collection has no text index")]
+	TextIndexNotFound,
+
+	#[error("A query vector or query text is required")]
+	EmptyQuery,
+
 	#[error("The dimension of the vector doesn't match the dimension of the collection")]
 	DimensionMismatch,
 
-	#[error("Failed to initialize the logger")]
-    LoggerInitializationError,
+	#[error("The vector type doesn't match the vector type of the collection")]
+	VectorTypeMismatch,
+
+	#[error("Sparse vectors need as many values as indices, with no index repeated")]
+	InvalidSparseVector,
+
+	#[error("The distance isn't supported for the vector type of the collection")]
+	UnsupportedDistance,
+
+	#[error("Named vector doesn't exist")]
+	VectorNotFound,
+
+	#[error("Failed to write to the data log")]
+	DataLog,
+
+	#[error("Memory-mapped storage only supports dense collections without named vectors")]
+	UnsupportedStorage,
+
+	#[error("Failed to access the files of the collection")]
+	Storage,
+
+	#[error("Alias doesn't exist")]
+	AliasNotFound,
+
+	#[error("Aliases and collections can't share a name")]
+	AliasConflict,
+
+	#[error("The text index can't be both replaced and dropped")]
+	ConflictingChanges,
+
+	#[error("The write would go over the vector or memory limit")]
+	LimitExceeded,
+
+	#[error("The embedding doesn't set the partition key of the collection in its metadata")]
+	MissingPartitionKey,
+
+	#[error("Collection has no partition key")]
+	NotPartitioned,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
@@ -62,6 +326,8 @@ pub struct CreateCollectionStruct{
     pub collection_name: String,
     pub dimension: usize,
     pub distance: Distance,
+    #[serde(flatten)]
+    pub options: CollectionOptions,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
@@ -76,6 +342,27 @@ pub struct CollectionHandlerStruct{
 	pub collection_name: String,
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct CacheLookupStruct{
+	pub collection_name: String,
+	pub vector: Vec<f32>,
+	/// Minimum similarity (cosine, dot) or maximum distance (euclidean) of a hit.
+	pub threshold: f32,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct CachePutStruct{
+	pub collection_name: String,
+	/// The key of the entry, such as the prompt.
+	pub key: String,
+	pub vector: Vec<f32>,
+	/// What lookups return on a hit, such as the response.
+	pub payload: HashMap<String, String>,
+	/// How many seconds the entry lives, overriding the TTL of the collection.
+	#[serde(default)]
+	pub ttl_secs: Option<u64>,
+}
+
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
 
@@ -84,9 +371,156 @@ pub struct BatchInsertEmbeddingsStruct{
 	pub embeddings: Vec<Embedding>,
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct DeleteEmbeddingsStruct{
+	pub collection_name: String,
+	pub ids: Vec<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct RenameCollectionStruct{
+	pub collection_name: String,
+	pub new_name: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct CloneCollectionStruct{
+	pub collection_name: String,
+	pub new_name: String,
+	/// Metadata values every copied embedding must have. Every embedding is copied without it.
+	#[serde(default)]
+	pub filter: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct AlterCollectionStruct{
+	pub collection_name: String,
+	#[serde(flatten)]
+	pub changes: CollectionChanges,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct UpdateAliasesStruct{
+	pub actions: Vec<AliasAction>,
+}
+
+/// A change to the aliases, applied together with the other actions of a request or not at all.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AliasAction {
+	/// Point `alias` at `collection_name`, replacing the collection it referred to before, if any.
+	CreateAlias { alias: String, collection_name: String },
+	DeleteAlias { alias: String },
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
 pub struct GetSimilarityStruct{
 	pub collection_name: String,
+	#[serde(default)]
 	pub query_vector: Vec<f32>,
-	pub k: usize
+	#[serde(default)]
+	pub sparse_query_vector: Option<SparseVector>,
+	#[serde(default)]
+	pub multi_query_vector: Option<Vec<Vec<f32>>>,
+	pub k: usize,
+	#[serde(flatten)]
+	pub params: SearchParams,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct RecommendStruct{
+	pub collection_name: String,
+	pub positive: Vec<HashMap<String, String>>,
+	#[serde(default)]
+	pub negative: Vec<HashMap<String, String>>,
+	pub k: usize,
+	#[serde(flatten)]
+	pub params: SearchParams,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct CreateTextIndexStruct{
+	pub collection_name: String,
+	pub field: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct HybridSearchStruct{
+	pub collection_name: String,
+	#[serde(default)]
+	pub query_vector: Option<Vec<f32>>,
+	#[serde(default)]
+	pub query_text: Option<String>,
+	pub k: usize,
+	#[serde(default)]
+	pub fusion: Fusion,
+	#[serde(flatten)]
+	pub params: SearchParams,
+}
+
+/// How the vector and BM25 rankings of a hybrid search are combined.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Fusion {
+	/// Reciprocal rank fusion: each ranking contributes `1 / (rank_constant + rank)`.
+	Rrf {
+		#[serde(default = "default_rank_constant")]
+		rank_constant: f32,
+	},
+	/// Min-max normalized scores, weighted `vector_weight` for vectors and `1 - vector_weight` for text.
+	Weighted {
+		#[serde(default = "default_vector_weight")]
+		vector_weight: f32,
+	},
+}
+
+impl Default for Fusion {
+	fn default() -> Self {
+		Fusion::Rrf { rank_constant: default_rank_constant() }
+	}
+}
+
+fn default_rank_constant() -> f32 {
+	60.0
+}
+
+fn default_vector_weight() -> f32 {
+	0.5
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema, PartialEq)]
+pub struct SearchParams {
+	/// Number of top results to skip before the `k` returned, for paging deeper results.
+	#[serde(default)]
+	pub offset: usize,
+	/// Minimum similarity (cosine, dot) or maximum distance (euclidean) a result must reach.
+	#[serde(default)]
+	pub score_threshold: Option<f32>,
+	#[serde(default = "default_true")]
+	pub with_vector: bool,
+	#[serde(default = "default_true")]
+	pub with_metadata: bool,
+	/// Named vector to search instead of the default vector.
+	#[serde(default)]
+	pub using: Option<String>,
+	/// Value of the partition key to search within, for collections with one.
+	#[serde(default)]
+	pub partition: Option<String>,
+}
+
+impl Default for SearchParams {
+	fn default() -> Self {
+		Self {
+			offset: 0,
+			score_threshold: None,
+			with_vector: true,
+			with_metadata: true,
+			using: None,
+			partition: None,
+		}
+	}
+}
+
+fn default_true() -> bool {
+	true
 }
\ No newline at end of file
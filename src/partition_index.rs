@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+/// The positions of the embeddings of a collection grouped by the value of its partition key.
+///
+/// Searches within a partition only score the positions of its group, so their cost depends on the
+/// size of the partition rather than on the size of the collection. Compaction stores each group
+/// as one run of positions, so that a partition is read from consecutive rows.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartitionIndex {
+	partitions: HashMap<String, Vec<usize>>,
+}
+
+impl PartitionIndex {
+	/// Add the embedding stored at `index` to the partition `value`.
+	///
+	/// Embeddings are added in position order, which keeps each group sorted.
+	pub fn add(&mut self, index: usize, value: &str) {
+		self.partitions.entry(value.to_string()).or_default().push(index);
+	}
+
+	/// The positions of the embeddings in the partition `value`, in ascending order.
+	pub fn positions(&self, value: &str) -> &[usize] {
+		self.partitions.get(value).map_or(&[], Vec::as_slice)
+	}
+
+	/// The positions of each partition, ordered by partition value.
+	pub fn groups(&self) -> Vec<&[usize]> {
+		let mut groups: Vec<_> = self.partitions.iter().collect();
+		groups.sort_unstable_by_key(|(value, _)| value.as_str());
+		groups.into_iter().map(|(_, positions)| positions.as_slice()).collect()
+	}
+}
+
+/// Whether `index` is among `positions`, or there is no partition to be in.
+pub fn contains(positions: Option<&[usize]>, index: usize) -> bool {
+	positions.is_none_or(|positions| positions.binary_search(&index).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_positions() {
+		let mut partition_index = PartitionIndex::default();
+		partition_index.add(0, "alice");
+		partition_index.add(1, "bob");
+		partition_index.add(2, "alice");
+
+		assert_eq!(partition_index.positions("alice"), &[0, 2]);
+		assert_eq!(partition_index.positions("bob"), &[1]);
+		assert!(partition_index.positions("carol").is_empty());
+		assert_eq!(partition_index.groups(), [&[0, 2][..], &[1]]);
+
+		assert!(contains(Some(partition_index.positions("alice")), 2));
+		assert!(!contains(Some(partition_index.positions("alice")), 1));
+		assert!(contains(None, 1));
+	}
+}
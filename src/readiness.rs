@@ -0,0 +1,118 @@
+use crate::response::GenericResponse;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use warp::{http::StatusCode, reject::Reject, Filter, Rejection, Reply};
+
+/// Whether the database is ready to serve requests, and how far its restore has come.
+///
+/// The server listens while the database is restored in the background, so that liveness probes
+/// pass and restore progress can be watched on `/readyz`.
+#[derive(Debug, Default)]
+pub struct RestoreProgress {
+    ready: AtomicBool,
+    entries_total: AtomicUsize,
+    entries_replayed: AtomicUsize,
+    errors_skipped: AtomicUsize,
+    collections_loaded: AtomicUsize,
+}
+
+/// A point-in-time copy of `RestoreProgress`, as returned by `/readyz`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RestoreStatus {
+    pub ready: bool,
+    pub entries_total: usize,
+    pub entries_replayed: usize,
+    pub errors_skipped: usize,
+    pub collections_loaded: usize,
+}
+
+impl RestoreProgress {
+    pub fn set_entries_total(&self, entries: usize) {
+        self.entries_total.store(entries, Ordering::Relaxed);
+    }
+
+    /// Count one replayed log entry, `applied` or skipped, and the collections loaded so far.
+    pub fn entry_replayed(&self, applied: bool, collections_loaded: usize) {
+        self.entries_replayed.fetch_add(1, Ordering::Relaxed);
+        if !applied {
+            self.errors_skipped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.collections_loaded.store(collections_loaded, Ordering::Relaxed);
+    }
+
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    pub fn status(&self) -> RestoreStatus {
+        RestoreStatus {
+            ready: self.is_ready(),
+            entries_total: self.entries_total.load(Ordering::Relaxed),
+            entries_replayed: self.entries_replayed.load(Ordering::Relaxed),
+            errors_skipped: self.errors_skipped.load(Ordering::Relaxed),
+            collections_loaded: self.collections_loaded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NotReady;
+
+impl Reject for NotReady {}
+
+/// A filter rejecting requests with `NotReady` until the database has been restored.
+pub fn require_ready(progress: Arc<RestoreProgress>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let ready = progress.is_ready();
+            async move {
+                if ready {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(NotReady))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Turn `NotReady` rejections into 503 responses, passing other rejections through.
+pub async fn handle_not_ready(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<NotReady>().is_none() {
+        return Err(rejection);
+    }
+    let response = GenericResponse {
+        status: "error".to_string(),
+        message: "The database is still being restored".to_string(),
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::SERVICE_UNAVAILABLE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_require_ready() {
+        let progress = Arc::new(RestoreProgress::default());
+        let filter = require_ready(progress.clone()).map(|| "ok").recover(handle_not_ready);
+
+        progress.set_entries_total(2);
+        progress.entry_replayed(true, 1);
+        progress.entry_replayed(false, 1);
+        let response = warp::test::request().reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        progress.set_ready();
+        let response = warp::test::request().reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let status = progress.status();
+        assert_eq!(status, RestoreStatus { ready: true, entries_total: 2, entries_replayed: 2, errors_skipped: 1, collections_loaded: 1 });
+    }
+}
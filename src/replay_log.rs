@@ -1,64 +1,260 @@
-use std::fs::File;
-use std::io::{BufReader, BufRead};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufRead, Write};
 use regex::Regex;
 use std::error::Error;
-use crate::model::{CacheDB, Distance, Embedding};
+use crate::model::{CacheDB, CollectionOptions, Distance, Embedding};
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
+use std::path::Path;
+use crate::data_log::{count_records, read_records, recover_torn_tail, DataLog, Durability};
+use crate::metrics::RESTORE_DURATION;
+use crate::readiness::RestoreProgress;
+use log::{info, warn};
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+
+/// What to do with log entries that can't be restored.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreMode {
+    /// Refuse to start, leaving the logs as they are.
+    Strict,
+    /// Skip them, moving them from the data log to the quarantine file.
+    #[default]
+    Lenient,
+}
 
-pub fn restore_db_from_logs(db: Arc<Mutex<CacheDB>>) -> Result<(), String> {
-    // let db = Arc::new(Mutex::new(CacheDB::new()));
-    let file = File::open("output.log").map_err(|e| e.to_string())?;
-    let reader = BufReader::new(file);
+impl std::str::FromStr for RestoreMode {
+    type Err = String;
 
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "strict" => Ok(RestoreMode::Strict),
+            "lenient" => Ok(RestoreMode::Lenient),
+            _ => Err(format!("Unknown restore mode '{}'", mode)),
+        }
+    }
+}
 
-    let mut log_content = String::new();
-    for line in reader.lines() {
-        let line = line.map_err(|e| e.to_string())?;
-        log_content.push_str(&line);
+/// The outcome of replaying a log.
+#[derive(Debug, Default, PartialEq)]
+pub struct RestoreReport {
+    /// Entries that were replayed into the database.
+    pub applied: usize,
+    /// Entries of the legacy log that aren't changes, such as request logs.
+    pub skipped: usize,
+    pub failed: Vec<RestoreFailure>,
+}
+
+/// A log entry that couldn't be restored.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RestoreFailure {
+    /// The line the entry starts on.
+    pub line: usize,
+    pub reason: String,
+    /// The entry as written in the log.
+    pub entry: String,
+}
+
+/// Restore the database when `restore` is given, then attach the data log and mark the database ready.
+///
+/// The data log at `data_log_path` is replayed when it exists. Otherwise the legacy log at
+/// `legacy_log_path` is replayed and migrated into a new data log holding a snapshot of it.
+/// Entries that can't be restored fail the restore in strict mode. In lenient mode they're
+/// appended to `quarantine_path` and dropped from the data log. A record torn by a crash in the
+/// middle of an append is cut off the data log first, whether or not the database is restored.
+///
+/// # Returns
+///
+/// The report of the restore, or an error if it failed or the data log can't be opened, in which
+/// case the database never becomes ready.
+pub fn load_db(
+    db: &Arc<Mutex<CacheDB>>,
+    progress: &RestoreProgress,
+    restore: Option<RestoreMode>,
+    data_log_path: &Path,
+    legacy_log_path: &Path,
+    quarantine_path: &Path,
+    durability: Durability,
+) -> Result<RestoreReport, String> {
+    let restore_start = Instant::now();
+    let data_log_exists = data_log_path.exists();
+    if data_log_exists {
+        let torn_bytes = recover_torn_tail(data_log_path)
+            .map_err(|e| format!("Failed to recover data log '{}': {}", data_log_path.display(), e))?;
+        if torn_bytes > 0 {
+            warn!("Cut off a record of {} bytes torn by a crash at the end of data log '{}'", torn_bytes, data_log_path.display());
+        }
+    }
+    let migrate_legacy_log = restore.is_some() && !data_log_exists && legacy_log_path.exists();
+    let mut report = RestoreReport::default();
+
+    if let Some(mode) = restore {
+        let source = if migrate_legacy_log { legacy_log_path } else { data_log_path };
+        if data_log_exists {
+            report = restore_db_from_data_log(db, data_log_path, progress)
+                .map_err(|e| format!("Failed to restore from data log '{}': {}", data_log_path.display(), e))?;
+        } else if migrate_legacy_log {
+            info!("Migrating legacy log '{}' to data log '{}'", legacy_log_path.display(), data_log_path.display());
+            report = restore_db_from_logs(db.clone(), legacy_log_path, progress)
+                .map_err(|e| format!("Failed to restore from legacy log '{}': {}", legacy_log_path.display(), e))?;
+        }
+
+        RESTORE_DURATION.set(restore_start.elapsed().as_secs_f64());
+        info!(
+            "Restored {} collections from '{}' in {:?}: {} entries applied, {} skipped, {} failed",
+            progress.status().collections_loaded,
+            source.display(),
+            restore_start.elapsed(),
+            report.applied,
+            report.skipped,
+            report.failed.len()
+        );
+
+        if let Some(first) = report.failed.first() {
+            match mode {
+                RestoreMode::Strict => {
+                    return Err(format!(
+                        "Refusing to start in strict restore mode: {} entries of '{}' couldn't be restored, the first on line {}: {}",
+                        report.failed.len(),
+                        source.display(),
+                        first.line,
+                        first.reason
+                    ));
+                }
+                RestoreMode::Lenient => {
+                    quarantine(quarantine_path, source, &report.failed)
+                        .map_err(|e| format!("Failed to write quarantine file '{}': {}", quarantine_path.display(), e))?;
+                    warn!("Moved {} entries that couldn't be restored to '{}'", report.failed.len(), quarantine_path.display());
+                }
+            }
+        }
+    }
+
+    let mut db_lock = db.lock().map_err(|e| e.to_string())?;
+    // A migrated data log is only created once complete, so an interrupted migration is retried,
+    // and a data log with quarantined records is replaced by a snapshot without them.
+    let data_log = if migrate_legacy_log || !report.failed.is_empty() {
+        let records = db_lock.snapshot_records().map_err(|e| e.to_string())?;
+        DataLog::create(data_log_path, &records)
+    } else {
+        DataLog::open(data_log_path)
+    };
+    let data_log = data_log.map_err(|e| format!("Failed to open data log '{}': {}", data_log_path.display(), e))?;
+    db_lock.set_data_log(data_log.with_durability(durability));
+    drop(db_lock);
+
+    progress.set_ready();
+    Ok(report)
+}
+
+/// Append `failures` of the log at `source` to the quarantine file, one JSON object per line.
+fn quarantine(quarantine_path: &Path, source: &Path, failures: &[RestoreFailure]) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(quarantine_path)?;
+    let mut lines = Vec::new();
+    for failure in failures {
+        serde_json::to_writer(&mut lines, &serde_json::json!({ "source": source, "failure": failure }))?;
+        lines.push(b'\n');
+    }
+    file.write_all(&lines)?;
+    file.sync_all()
+}
+
+/// Replay the data log at `data_log` into `db`, reporting the records that can't be read or applied.
+///
+/// The database is locked for each record only, so it can be inspected while the restore runs.
+pub fn restore_db_from_data_log(db: &Mutex<CacheDB>, data_log: &Path, progress: &RestoreProgress) -> Result<RestoreReport, String> {
+    // The records are counted first and then replayed as they are read, so that the log is never held in memory whole.
+    progress.set_entries_total(count_records(data_log).map_err(|e| e.to_string())?);
+    let mut report = RestoreReport::default();
+    for read in read_records(data_log).map_err(|e| e.to_string())? {
+        let (line, record) = read.map_err(|e| e.to_string())?;
+        let mut db_lock = db.lock().map_err(|e| e.to_string())?;
+        let failure = match record {
+            Ok(record) => {
+                let entry = serde_json::to_string(&record).unwrap_or_default();
+                db_lock.apply(record).err().map(|err| RestoreFailure { line, reason: err.to_string(), entry })
+            }
+            Err(unreadable) => Some(RestoreFailure { line, reason: unreadable.error, entry: unreadable.text }),
+        };
+        progress.entry_replayed(failure.is_none(), db_lock.collections.len());
+        match failure {
+            Some(failure) => {
+                warn!("Data log record on line {} couldn't be restored: {}", line, failure.reason);
+                report.failed.push(failure);
+            }
+            None => report.applied += 1,
+        }
     }
+    Ok(report)
+}
 
-    let log_entries = split_by_date(&log_content);
+/// Replay a diagnostic log written before the data log existed, as found in `output.log` files of
+/// earlier versions.
+pub fn restore_db_from_logs(db: Arc<Mutex<CacheDB>>, log_file: &Path, progress: &RestoreProgress) -> Result<RestoreReport, String> {
+    let log_entries = read_log_entries(log_file).map_err(|e| e.to_string())?;
+    progress.set_entries_total(log_entries.len());
 
-    for entry in log_entries {
-        if entry.contains("Created new collection") {
-            let _restored_db = parse_and_create_collection(&entry, db.clone());
+    let mut report = RestoreReport::default();
+    for (line, entry) in log_entries {
+        let restored = if entry.contains("Created new collection") {
+            parse_and_create_collection(&entry, db.clone())
         }
         else if entry.contains("successfully inserted into collection") {
-            let _restored_db = parse_and_insert_embeddings(&entry, db.clone());
+            parse_and_insert_embeddings(&entry, db.clone())
         }
         else if entry.contains("successfully updated to collection") {
-            let _restored_db = parse_and_update_collection(&entry, db.clone());
+            parse_and_update_collection(&entry, db.clone())
+        }
+        else if entry.contains("Created text index") {
+            parse_and_create_text_index(&entry, db.clone())
         }
         else if entry.contains("Deleted collection") {
-            let _restored_db = parse_and_delete_collection(&entry, db.clone());
+            parse_and_delete_collection(&entry, db.clone())
+        }
+        else {
+            // Diagnostic lines other than changes are expected in this log.
+            report.skipped += 1;
+            let collections_loaded = db.lock().map_err(|e| e.to_string())?.collections.len();
+            progress.entry_replayed(true, collections_loaded);
+            continue;
+        };
+        let collections_loaded = db.lock().map_err(|e| e.to_string())?.collections.len();
+        progress.entry_replayed(restored.is_ok(), collections_loaded);
+        match restored {
+            Ok(()) => report.applied += 1,
+            Err(err) => {
+                warn!("Legacy log entry on line {} couldn't be restored: {}", line, err);
+                report.failed.push(RestoreFailure { line, reason: err.to_string(), entry });
+            }
         }
     }
-    Ok(())
+    Ok(report)
 }
 
-fn split_by_date(log: &str) -> Vec<String> {
-    let re = Regex::new(r"\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}").unwrap();
-    let mut entries: Vec<String> = Vec::new();
-    let mut start = 0;
-    for mat in re.find_iter(log) {
-        let end = mat.start();
-        if start != end {
-            entries.push(log[start..end].trim().to_string());
+/// Read the entries of a legacy log with the lines they start on. An entry starts with a
+/// timestamp and runs until the next line starting with one.
+fn read_log_entries(log_file: &Path) -> std::io::Result<Vec<(usize, String)>> {
+    let entry_start = Regex::new(r"^\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}").unwrap();
+    let reader = BufReader::new(File::open(log_file)?);
+    let mut entries: Vec<(usize, String)> = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        match entries.last_mut() {
+            Some((_, entry)) if !entry_start.is_match(&line) => entry.push_str(&line),
+            _ => entries.push((number + 1, line)),
         }
-
-        start = end;
     }
-    if start < log.len() {
-        entries.push(log[start..].trim().to_string());
+    for (_, entry) in &mut entries {
+        *entry = entry.trim().to_string();
     }
-
-    entries
+    entries.retain(|(_, entry)| !entry.is_empty());
+    Ok(entries)
 }
 
 pub fn parse_and_create_collection(log_line :&str, db: Arc<Mutex<CacheDB>>) -> Result<(), Box<dyn Error>> {
     let re = Regex::new(
-        r"Created new collection with name: '([^']+)', dimension: '(\d+)', distance: '([^']+)'",
+        r"Created new collection with name: '([^']+)', dimension: '(\d+)', distance: '([^']+)'(?:, options: (\{.*\}))?",
     )?;
 
     if let Some(caps) = re.captures(log_line) {
@@ -73,11 +269,17 @@ pub fn parse_and_create_collection(log_line :&str, db: Arc<Mutex<CacheDB>>) -> R
             _ => return Err("Unknown distance type".into()),
         };
 
+        // Collections logged before options existed use the defaults.
+        let options: CollectionOptions = match caps.get(4) {
+            Some(m) => serde_json::from_str(m.as_str())?,
+            None => CollectionOptions::default(),
+        };
+
         let mut db = db.lock().unwrap();
-        db.create_collection(collection_name, collection_dimension, distance)?;
+        db.create_collection(collection_name, collection_dimension, distance, options)?;
     }
     else {
-        eprintln!("Log line format is incorrect: {}", log_line);
+        return Err("Log line format is incorrect".into());
     }
     
     Ok(())
@@ -85,6 +287,20 @@ pub fn parse_and_create_collection(log_line :&str, db: Arc<Mutex<CacheDB>>) -> R
 
 
 pub fn parse_and_insert_embeddings(log_line: &str, db: Arc<Mutex<CacheDB>>) -> Result<(), Box<dyn Error>> {
+    let json_re = Regex::new(
+        r#"Embedding: '(\{.*\})', successfully inserted into collection '([^']*)'$"#
+    )?;
+
+    if let Some(caps) = json_re.captures(log_line) {
+        let embedding: Embedding = serde_json::from_str(caps.get(1).map_or("", |m| m.as_str()))?;
+        let collection_name = caps.get(2).map_or("", |m| m.as_str());
+
+        let mut db = db.lock().map_err(|e| format!("Failed to lock the database: {}", e))?;
+        db.insert_into_collection(collection_name, embedding)?;
+        return Ok(());
+    }
+
+    // Embeddings were logged in their debug format before the JSON format was introduced.
     let re = Regex::new(
         r#"Embedding: 'Embedding \{ id: \{"unique_id": "(\d+)"\}, vector: \[([0-9.,\s]+)\], metadata: Some\(\{(.*?)\}\) \}', successfully inserted into collection '([^']*)'"#
     )?;
@@ -119,19 +335,34 @@ pub fn parse_and_insert_embeddings(log_line: &str, db: Arc<Mutex<CacheDB>>) -> R
             id,
             vector,
             metadata,
+            ..Default::default()
         };
 
         let mut db = db.lock().map_err(|e| format!("Failed to lock the database: {}", e))?;
         db.insert_into_collection(&collection_name, embedding)?;
     } 
     else {
-        eprintln!("Log line format is incorrect: {}", log_line);
+        return Err("Log line format is incorrect".into());
     }
 
     Ok(())
 }
 
 pub fn parse_and_update_collection(log_line: &str, db: Arc<Mutex<CacheDB>>) -> Result<(), Box<dyn Error>> {
+    let json_re = Regex::new(
+        r#"Embedding: '(\[(?:\{.*\})?\])' successfully updated to collection '([^']*)'$"#
+    )?;
+
+    if let Some(caps) = json_re.captures(log_line) {
+        let new_embeddings: Vec<Embedding> = serde_json::from_str(caps.get(1).map_or("", |m| m.as_str()))?;
+        let collection_name = caps.get(2).map_or("", |m| m.as_str());
+
+        let mut db = db.lock().map_err(|e| format!("Failed to lock the database: {}", e))?;
+        db.update_collection(collection_name, new_embeddings)?;
+        return Ok(());
+    }
+
+    // Embeddings were logged in their debug format before the JSON format was introduced.
     let re = Regex::new(
         r#"Embedding: '\[(.*?)\]' successfully updated to collection '([^']*)'"#
     )?;    
@@ -176,6 +407,7 @@ pub fn parse_and_update_collection(log_line: &str, db: Arc<Mutex<CacheDB>>) -> R
                 id,
                 vector,
                 metadata,
+                ..Default::default()
             });
         }
 
@@ -183,13 +415,28 @@ pub fn parse_and_update_collection(log_line: &str, db: Arc<Mutex<CacheDB>>) -> R
         db.update_collection(&collection_name, new_embeddings)?;
     } 
     else {
-        eprintln!("Log line format is incorrect: {}", log_line);
+        return Err("Log line format is incorrect".into());
     }
 
     Ok(())
 }
 
 
+pub fn parse_and_create_text_index(log_line: &str, db: Arc<Mutex<CacheDB>>) -> Result<(), Box<dyn Error>> {
+    let re = Regex::new(r#"Created text index on collection '([^']*)' for metadata field '([^']*)'"#)?;
+
+    if let Some(caps) = re.captures(log_line) {
+        let collection_name = caps.get(1).map_or("", |m| m.as_str());
+        let field = caps.get(2).map_or("", |m| m.as_str()).to_string();
+
+        let mut db = db.lock().map_err(|e| format!("Failed to lock the database: {}", e))?;
+        db.create_text_index(collection_name, field)?;
+    } else {
+        return Err("Log line format is incorrect".into());
+    }
+    Ok(())
+}
+
 pub fn parse_and_delete_collection(log_line: &str, db: Arc<Mutex<CacheDB>>) -> Result<(), Box<dyn Error>> {
     let re = Regex::new(r#"Deleted collection: '([^']*)'"#)?;
 
@@ -197,10 +444,10 @@ pub fn parse_and_delete_collection(log_line: &str, db: Arc<Mutex<CacheDB>>) -> R
         let collection_name = caps.get(1).map_or("", |m| m.as_str());
 
         let mut db = db.lock().map_err(|e| format!("Failed to lock the database: {}", e))?;
-        db.delete_collection(&collection_name)?;
+        db.delete_collection(collection_name)?;
 
     } else {
-        eprintln!("Log line format is incorrect: {}", log_line);
+        return Err("Log line format is incorrect".into());
     }
     Ok(())
 }
@@ -208,6 +455,7 @@ pub fn parse_and_delete_collection(log_line: &str, db: Arc<Mutex<CacheDB>>) -> R
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model::{AliasAction, CollectionChanges, EvictionPolicy, Limits, SearchParams, SparseVector, Storage};
     use std::sync::{Arc, Mutex};
     use tempfile::NamedTempFile;
     use std::io::Write;
@@ -220,10 +468,9 @@ mod tests {
         let mut temp_file = NamedTempFile::new().expect("failed to create temp file");
         writeln!(temp_file, "2024-09-10 23:28:48 [INFO] Created new collection with name: 'test_collection', dimension: '3', distance: 'Euclidean'").unwrap();
         writeln!(temp_file, "2024-09-10 23:28:48 [INFO] Created new collection with name: 'test_collection_1', dimension: '3', distance: 'Euclidean'").unwrap();
-        let log_entry = format!(
-            "2024-09-10 23:28:48 [INFO] Embedding: 'Embedding {{ id: {{\"unique_id\": \"0\"}}, vector: [1.0, 1.0, 1.0], metadata: Some({{\"page\": \"1\", \"text\": \"This is a test metadata text\"}}) }}', successfully inserted into collection 'test_collection'"
-        );
+        let log_entry = "2024-09-10 23:28:48 [INFO] Embedding: 'Embedding { id: {\"unique_id\": \"0\"}, vector: [1.0, 1.0, 1.0], metadata: Some({\"page\": \"1\", \"text\": \"This is a test metadata text\"}) }', successfully inserted into collection 'test_collection'";
         writeln!(temp_file, "{}", log_entry).unwrap();
+        writeln!(temp_file, "2024-09-10 23:28:48 [INFO] Created text index on collection 'test_collection' for metadata field 'text'").unwrap();
         writeln!(temp_file, "2024-09-10 23:28:48 [INFO] Deleted collection: 'test_collection_1'").unwrap();
         let db = Arc::new(Mutex::new(CacheDB::new()));
 
@@ -233,7 +480,7 @@ mod tests {
         copy(&mut temp_file, &mut output_file).expect("failed to copy temp file to output.log");
         fs::remove_file(temp_path).expect("failed to remove temp file");
 
-        let result = restore_db_from_logs(db.clone());
+        let result = restore_db_from_logs(db.clone(), Path::new("output.log"), &RestoreProgress::default());
 
         assert!(result.is_ok());
 
@@ -248,14 +495,286 @@ mod tests {
             id,
             vector: vec![1.0, 1.0, 1.0],
             metadata: Some(metadata),
+            ..Default::default()
         };
 
         let db_lock = db.lock().unwrap();
         let collection = db_lock.collections.get("test_collection").expect("Collection 'test_collection' not found");
-        assert!(db_lock.collections.get("test_collection_1").is_none());
+        assert!(!db_lock.collections.contains_key("test_collection_1"));
         assert_eq!(collection.embeddings.len(), 1);
         assert_eq!(collection.embeddings[0], expected_embedding);
+        assert_eq!(collection.text_index.as_ref().map(|index| index.field.as_str()), Some("text"));
 
         std::fs::remove_file("output.log").expect("failed to remove temp log file");
     }
+
+    #[test]
+    fn test_replay_json_embeddings() {
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+
+        parse_and_create_collection(
+            r#"2024-09-10 23:28:48 [INFO] Created new collection with name: 'sparse_collection', dimension: '100', distance: 'DotProduct', options: {"vector_type":"sparse"}"#,
+            db.clone(),
+        ).unwrap();
+        parse_and_insert_embeddings(
+            r#"2024-09-10 23:28:48 [INFO] Embedding: '{"id":{"unique_id":"a"},"metadata":{"text":"it's here"},"sparse_vector":{"indices":[3,42],"values":[0.5,1.5]}}', successfully inserted into collection 'sparse_collection'"#,
+            db.clone(),
+        ).unwrap();
+        parse_and_update_collection(
+            r#"2024-09-10 23:28:48 [INFO] Embedding: '[{"id":{"unique_id":"b"},"metadata":null,"sparse_vector":{"indices":[42],"values":[2.0]}}]' successfully updated to collection 'sparse_collection'"#,
+            db.clone(),
+        ).unwrap();
+
+        let db_lock = db.lock().unwrap();
+        let collection = db_lock.collections.get("sparse_collection").expect("Collection 'sparse_collection' not found");
+        assert_eq!(collection.embeddings.len(), 2);
+        assert_eq!(collection.embeddings[0].metadata.as_ref().unwrap()["text"], "it's here");
+
+        let query = SparseVector { indices: vec![42], values: vec![1.0] };
+        let results = collection.get_sparse_similarity(&query, 2, &SearchParams::default()).unwrap();
+        let scores: Vec<f32> = results.iter().map(|r| r.score).collect();
+        assert_eq!(scores, vec![2.0, 1.5]);
+    }
+
+    #[test]
+    fn test_restore_db_from_data_log() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let data_log_path = dir.path().join("data.log");
+
+        let mut db = CacheDB::new();
+        db.set_data_log(crate::data_log::DataLog::open(&data_log_path).unwrap());
+        db.create_collection("test_collection".to_string(), 2, Distance::Cosine, CollectionOptions::default()).unwrap();
+        db.create_collection("test_collection_1".to_string(), 2, Distance::Cosine, CollectionOptions::default()).unwrap();
+        let embeddings: Vec<Embedding> = (0..3)
+            .map(|i| {
+                let mut id = HashMap::new();
+                id.insert("unique_id".to_string(), i.to_string());
+                let mut metadata = HashMap::new();
+                metadata.insert("text".to_string(), format!("document {}", i));
+                Embedding { id, vector: vec![1.0, i as f32], metadata: Some(metadata), ..Default::default() }
+            })
+            .collect();
+        db.update_collection("test_collection", embeddings[..2].to_vec()).unwrap();
+        db.insert_into_collection("test_collection", embeddings[2].clone()).unwrap();
+        db.create_text_index("test_collection", "text".to_string()).unwrap();
+        db.delete_collection("test_collection_1").unwrap();
+        db.delete_embeddings("test_collection", vec![embeddings[0].id.clone()]).unwrap();
+        let alias = AliasAction::CreateAlias { alias: "live".to_string(), collection_name: "test_collection".to_string() };
+        db.update_aliases(vec![alias]).unwrap();
+        let filter = HashMap::from([("text".to_string(), "document 2".to_string())]);
+        db.clone_collection("live", "copy".to_string(), Some(filter)).unwrap();
+        db.rename_collection("copy", "renamed".to_string()).unwrap();
+        db.alter_collection("renamed", CollectionChanges { drop_text_index: true, ..CollectionChanges::default() }).unwrap();
+        let expired = |unique_id: &str| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![0.0, 1.0], expires_at: Some(1), ..Default::default() }
+        };
+        db.update_collection("renamed", vec![expired("swept"), expired("lingering")]).unwrap();
+        crate::expiry::sweep(&mut db);
+        db.insert_into_collection("renamed", expired("lingering")).unwrap();
+        let limits = Limits { max_vectors: Some(2), max_memory_bytes: None, eviction_policy: Some(EvictionPolicy::Lru) };
+        db.alter_collection("renamed", CollectionChanges { limits: Some(limits), ..CollectionChanges::default() }).unwrap();
+        db.collections["renamed"].get_similarity(&[1.0, 2.0], 1, &SearchParams::default()).unwrap();
+        db.insert_into_collection("renamed", expired("evicting")).unwrap();
+        assert_eq!(db.collections["renamed"].stored_count(), 2);
+
+        // Failed changes aren't recorded.
+        assert!(db.update_collection("test_collection", embeddings.clone()).is_err());
+
+        let restored = Mutex::new(CacheDB::new());
+        let progress = RestoreProgress::default();
+        let report = restore_db_from_data_log(&restored, &data_log_path, &progress).unwrap();
+        assert_eq!(report, RestoreReport { applied: 17, skipped: 0, failed: Vec::new() });
+        let restored = restored.into_inner().unwrap();
+        assert_eq!(restored.collections, db.collections);
+        assert_eq!(restored.aliases, db.aliases);
+        assert!(restored.collections["test_collection"].text_index.is_some());
+        assert_eq!(restored.collections["renamed"].all_embeddings().unwrap(), db.collections["test_collection"].all_embeddings().unwrap()[1..]);
+        assert!(restored.collections["renamed"].text_index.is_none());
+        let status = progress.status();
+        assert_eq!((status.entries_total, status.entries_replayed, status.errors_skipped, status.collections_loaded), (17, 17, 0, 2));
+
+        // A snapshot restores to the same embeddings, keeping the normalized vectors as they are and
+        // leaving out the deleted ones.
+        db.snapshot().unwrap();
+        db.sync_data_log().unwrap();
+        let restored = Mutex::new(CacheDB::new());
+        restore_db_from_data_log(&restored, &data_log_path, &RestoreProgress::default()).unwrap();
+        let restored = restored.into_inner().unwrap();
+        let mut names: Vec<&String> = restored.collections.keys().collect();
+        names.sort();
+        assert_eq!(names, ["renamed", "test_collection"]);
+        assert_eq!(restored.aliases, db.aliases);
+        let (collection, expected) = (&restored.collections["test_collection"], &db.collections["test_collection"]);
+        assert!(collection.tombstones.is_empty());
+        assert_eq!(collection.all_embeddings(), expected.all_embeddings());
+        assert_eq!(collection.text_index.as_ref().map(|index| &index.field), expected.text_index.as_ref().map(|index| &index.field));
+        assert_eq!(std::fs::read_to_string(&data_log_path).unwrap().lines().count(), 6);
+    }
+
+
+    #[test]
+    fn test_restore_upserts() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let data_log_path = dir.path().join("data.log");
+        let embedding = |unique_id: &str, x: f32| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![x, 1.0], ..Default::default() }
+        };
+
+        let mut db = CacheDB::new();
+        db.set_data_log(crate::data_log::DataLog::open(&data_log_path).unwrap());
+        db.create_collection("test_collection".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+        db.update_collection("test_collection", vec![embedding("0", 0.0), embedding("1", 1.0)]).unwrap();
+        db.upsert_embeddings("test_collection", vec![embedding("1", 5.0), embedding("2", 2.0)]).unwrap();
+        assert_eq!(db.upsert_embeddings("test_collection", vec![embedding("3", 3.0), embedding("3", 4.0)]), Err(crate::model::Error::UniqueViolation));
+        assert_eq!(db.collections["test_collection"].all_embeddings().unwrap(), vec![embedding("0", 0.0), embedding("1", 5.0), embedding("2", 2.0)]);
+
+        // The replaced embedding is logged as deleted before the insert.
+        let restored = Mutex::new(CacheDB::new());
+        let report = restore_db_from_data_log(&restored, &data_log_path, &RestoreProgress::default()).unwrap();
+        assert_eq!(report, RestoreReport { applied: 4, skipped: 0, failed: Vec::new() });
+        assert_eq!(restored.into_inner().unwrap().collections, db.collections);
+    }
+
+    #[test]
+    fn test_restore_clone_of_embeddings_expired_since() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let data_log_path = dir.path().join("data.log");
+        let expires_at = crate::expiry::unix_now() + 1;
+        let embedding = |unique_id: &str| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![1.0, 0.0], expires_at: Some(expires_at), ..Default::default() }
+        };
+
+        // The embedding is cloned while live, and swept from both collections once it expires.
+        let mut db = CacheDB::new();
+        db.set_data_log(crate::data_log::DataLog::open(&data_log_path).unwrap());
+        db.create_collection("test_collection".to_string(), 2, Distance::Euclidean, CollectionOptions::default()).unwrap();
+        db.insert_into_collection("test_collection", embedding("0")).unwrap();
+        db.clone_collection("test_collection", "copy".to_string(), None).unwrap();
+        while crate::expiry::unix_now() <= expires_at {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        crate::expiry::sweep(&mut db);
+        assert_eq!(db.collections["copy"].stored_count(), 0);
+
+        // Replayed after it expired, the clone still copies the embedding, so the sweep replays too.
+        let restored = Mutex::new(CacheDB::new());
+        let report = restore_db_from_data_log(&restored, &data_log_path, &RestoreProgress::default()).unwrap();
+        assert_eq!(report, RestoreReport { applied: 5, skipped: 0, failed: Vec::new() });
+        assert_eq!(restored.into_inner().unwrap().collections, db.collections);
+    }
+
+    #[test]
+    fn test_restore_snapshot_of_expiring_mmap_collection() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let data_log_path = dir.path().join("data.log");
+        let now = crate::expiry::unix_now();
+        let embedding = |unique_id: &str, expires_at: u64| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![1.0, 0.0], expires_at: Some(expires_at), ..Default::default() }
+        };
+
+        let mut db = CacheDB::new();
+        db.set_collections_dir(dir.path().join("collections")).unwrap();
+        db.set_data_log(crate::data_log::DataLog::open(&data_log_path).unwrap());
+        let options = CollectionOptions { storage: Storage::Mmap, ttl_secs: Some(60), ..CollectionOptions::default() };
+        db.create_collection("test_collection".to_string(), 2, Distance::Euclidean, options).unwrap();
+        db.update_collection("test_collection", vec![embedding("expired", now - 1), embedding("live", now + 60)]).unwrap();
+        db.snapshot().unwrap();
+        db.sync_data_log().unwrap();
+
+        // The expiry times are read back from the files, so the expired embedding stays expired.
+        let mut restored = CacheDB::new();
+        restored.set_collections_dir(dir.path().join("restored")).unwrap();
+        let restored = Mutex::new(restored);
+        restore_db_from_data_log(&restored, &data_log_path, &RestoreProgress::default()).unwrap();
+        let restored = restored.into_inner().unwrap();
+        let collection = &restored.collections["test_collection"];
+        assert_eq!(collection.embedding_count(), 1);
+        assert_eq!(collection.all_embeddings(), db.collections["test_collection"].all_embeddings());
+        assert_eq!(collection.stored_embeddings(), db.collections["test_collection"].stored_embeddings());
+    }
+
+    #[test]
+    fn test_load_db_migrates_legacy_log() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let data_log_path = dir.path().join("data.log");
+        let legacy_log_path = dir.path().join("output.log");
+        let mut legacy_log = File::create(&legacy_log_path).unwrap();
+        writeln!(legacy_log, "2024-09-10 23:28:48 [INFO] Created new collection with name: 'test_collection', dimension: '3', distance: 'Euclidean'").unwrap();
+        writeln!(legacy_log, "2024-09-10 23:28:48 [INFO] Deleted collection: 'missing_collection'").unwrap();
+        writeln!(legacy_log, "2024-09-10 23:28:49 [INFO] Server::run; addr=0.0.0.0:8000").unwrap();
+
+        let quarantine_path = dir.path().join("quarantine.log");
+
+        // Strict mode refuses the entry that can't be replayed, without migrating anything.
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let progress = RestoreProgress::default();
+        let result = load_db(&db, &progress, Some(RestoreMode::Strict), &data_log_path, &legacy_log_path, &quarantine_path, Durability::Always);
+        assert!(result.unwrap_err().contains("line 2"));
+        assert!(!progress.is_ready());
+        assert!(!data_log_path.exists());
+
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let progress = RestoreProgress::default();
+        let report = load_db(&db, &progress, Some(RestoreMode::Lenient), &data_log_path, &legacy_log_path, &quarantine_path, Durability::Always).unwrap();
+        assert_eq!((report.applied, report.skipped), (1, 1));
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].line, 2);
+        assert_eq!(report.failed[0].entry, "2024-09-10 23:28:48 [INFO] Deleted collection: 'missing_collection'");
+
+        let status = progress.status();
+        assert!(status.ready);
+        assert_eq!((status.entries_replayed, status.errors_skipped, status.collections_loaded), (3, 1, 1));
+
+        let quarantined: serde_json::Value = serde_json::from_str(&fs::read_to_string(&quarantine_path).unwrap()).unwrap();
+        assert_eq!(quarantined["failure"]["line"], 2);
+        assert_eq!(quarantined["source"], legacy_log_path.to_str().unwrap());
+
+        // The migrated collection is in the new data log, which is preferred from now on.
+        let restored = Arc::new(Mutex::new(CacheDB::new()));
+        load_db(&restored, &RestoreProgress::default(), Some(RestoreMode::Strict), &data_log_path, &legacy_log_path, &quarantine_path, Durability::Always).unwrap();
+        assert!(restored.lock().unwrap().collections.contains_key("test_collection"));
+    }
+
+    #[test]
+    fn test_load_db_quarantines_data_log_records() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let data_log_path = dir.path().join("data.log");
+        let legacy_log_path = dir.path().join("output.log");
+        let quarantine_path = dir.path().join("quarantine.log");
+        let mut data_log = File::create(&data_log_path).unwrap();
+        writeln!(data_log, r#"{{"op":"create_collection","name":"test_collection","dimension":2,"distance":"dot"}}"#).unwrap();
+        writeln!(data_log, r#"{{"op":"insert","collection_name":"test_collection","embeddings":[{{"id":{{"unique_id":"0"}},"vector":[1.0]}}]}}"#).unwrap();
+        writeln!(data_log, r#"{{"op":"insert""#).unwrap();
+        let original = fs::read_to_string(&data_log_path).unwrap();
+
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let result = load_db(&db, &RestoreProgress::default(), Some(RestoreMode::Strict), &data_log_path, &legacy_log_path, &quarantine_path, Durability::Always);
+        assert!(result.unwrap_err().contains("2 entries"));
+        assert_eq!(fs::read_to_string(&data_log_path).unwrap(), original);
+        assert!(!quarantine_path.exists());
+
+        let db = Arc::new(Mutex::new(CacheDB::new()));
+        let report = load_db(&db, &RestoreProgress::default(), Some(RestoreMode::Lenient), &data_log_path, &legacy_log_path, &quarantine_path, Durability::Always).unwrap();
+        assert_eq!(report.applied, 1);
+        let lines: Vec<usize> = report.failed.iter().map(|failure| failure.line).collect();
+        assert_eq!(lines, vec![2, 3]);
+        assert!(report.failed[0].reason.contains("dimension"));
+        assert_eq!(report.failed[1].entry, r#"{"op":"insert""#);
+        assert_eq!(fs::read_to_string(&quarantine_path).unwrap().lines().count(), 2);
+
+        // The quarantined records are no longer in the data log.
+        let report = load_db(&Arc::new(Mutex::new(CacheDB::new())), &RestoreProgress::default(), Some(RestoreMode::Strict), &data_log_path, &legacy_log_path, &quarantine_path, Durability::Always).unwrap();
+        assert_eq!(report, RestoreReport { applied: 1, skipped: 0, failed: Vec::new() });
+
+        // A record torn by a crash isn't a failure, even in strict mode.
+        fs::OpenOptions::new().append(true).open(&data_log_path).unwrap().write_all(br#"{"op":"delete_coll"#).unwrap();
+        let report = load_db(&Arc::new(Mutex::new(CacheDB::new())), &RestoreProgress::default(), Some(RestoreMode::Strict), &data_log_path, &legacy_log_path, &quarantine_path, Durability::Always).unwrap();
+        assert_eq!(report, RestoreReport { applied: 1, skipped: 0, failed: Vec::new() });
+        assert!(fs::read_to_string(&data_log_path).unwrap().ends_with("}\n"));
+    }
+
 }
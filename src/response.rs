@@ -1,4 +1,5 @@
 use serde::Serialize;
+use std::collections::HashMap;
 
 #[derive(Serialize)]
 pub struct CreateCollectionResponse {
@@ -11,3 +12,23 @@ pub struct GenericResponse {
     pub status: String,
     pub message: String,
 }
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CacheLookupResponse {
+    pub hit: bool,
+    /// The id of the entry found, which holds its key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<HashMap<String, String>>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CacheStatsResponse {
+    pub collection_name: String,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
@@ -0,0 +1,69 @@
+use crate::id_index::IdIndex;
+use crate::model::Embedding;
+use std::collections::HashMap;
+
+/// The number of embeddings the mutable segment of a collection holds before it is sealed.
+pub const SEGMENT_CAPACITY: usize = 4096;
+
+/// The number of embeddings the mutable segment of a collection holds before compaction seals it early.
+pub const MIN_SEAL_LEN: usize = SEGMENT_CAPACITY / 4;
+
+/// An immutable run of a collection's embeddings.
+///
+/// Sealed segments are shared with a running compaction, which reads them without holding the
+/// database lock. Their ids are indexed so that lookups don't scan the embeddings.
+#[derive(Debug, PartialEq)]
+pub struct Segment {
+    embeddings: Vec<Embedding>,
+    /// The rows of the embeddings by id. The latest row with an id is the only one that may not be deleted.
+    ids: IdIndex,
+}
+
+impl Segment {
+    pub fn new(embeddings: Vec<Embedding>) -> Self {
+        let mut ids = IdIndex::default();
+        for (row, embedding) in embeddings.iter().enumerate() {
+            ids.insert(&embedding.id, row);
+        }
+        Self { embeddings, ids }
+    }
+
+    pub fn embeddings(&self) -> &[Embedding] {
+        &self.embeddings
+    }
+
+    pub fn len(&self) -> usize {
+        self.embeddings.len()
+    }
+
+    /// The row of the latest embedding with the given id.
+    pub fn position(&self, id: &HashMap<String, String>) -> Option<usize> {
+        self.ids.position(id, |row| self.embeddings[row].id == *id)
+    }
+
+    /// An estimate of the memory the segment takes, counting its embeddings and id index.
+    pub fn estimated_memory_bytes(&self) -> usize {
+        self.embeddings.iter().map(Embedding::estimated_memory_bytes).sum::<usize>() + self.ids.estimated_memory_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segment_positions() {
+        let embedding = |unique_id: &str, value: f32| {
+            let id = HashMap::from([("unique_id".to_string(), unique_id.to_string())]);
+            Embedding { id, vector: vec![value], ..Default::default() }
+        };
+        // A deleted embedding may be inserted again, and lookups find the later one.
+        let segment = Segment::new(vec![embedding("a", 1.0), embedding("b", 2.0), embedding("a", 3.0)]);
+
+        assert_eq!(segment.len(), 3);
+        assert_eq!(segment.position(&embedding("a", 0.0).id), Some(2));
+        assert_eq!(segment.position(&embedding("b", 0.0).id), Some(1));
+        assert_eq!(segment.position(&embedding("c", 0.0).id), None);
+        assert_eq!(segment.embeddings()[2].vector, vec![3.0]);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::metrics::CACHE_LOOKUPS;
+use crate::model::{CacheDB, Embedding, Error, SearchParams, SimilarityResult};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The id field cache entries are stored under.
+pub const KEY_FIELD: &str = "key";
+
+/// The hits and misses of the cache lookups on a collection since the server started.
+///
+/// Lookups only hold a shared reference to the collection, so the counts are atomic.
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Clone for CacheStats {
+    fn clone(&self) -> Self {
+        Self { hits: AtomicU64::new(self.hits()), misses: AtomicU64::new(self.misses()) }
+    }
+}
+
+impl PartialEq for CacheStats {
+    fn eq(&self, other: &Self) -> bool {
+        (self.hits(), self.misses()) == (other.hits(), other.misses())
+    }
+}
+
+impl CacheStats {
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The share of lookups that were hits, 0 before any lookup.
+    pub fn hit_rate(&self) -> f64 {
+        let (hits, misses) = (self.hits(), self.misses());
+        if hits + misses == 0 { 0.0 } else { hits as f64 / (hits + misses) as f64 }
+    }
+
+    fn record(&self, hit: bool) {
+        let count = if hit { &self.hits } else { &self.misses };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Look up the cached entry most similar to `query`, if it is similar enough.
+///
+/// # Arguments
+///
+/// * `db`: The database holding the collection.
+/// * `collection_name`: The name or alias of the collection used as a cache.
+/// * `query`: The vector of the prompt to look up.
+/// * `threshold`: The minimum similarity (cosine, dot) or maximum distance (euclidean) of a hit.
+///
+/// # Returns
+///
+/// A result containing the entry on a hit and `None` on a miss, or an error if the collection
+/// wasn't found or can't be searched with `query`. Only lookups without errors are counted.
+pub fn lookup(db: &CacheDB, collection_name: &str, query: &[f32], threshold: f32) -> Result<Option<SimilarityResult>, Error> {
+    let collection = db.get_collection(collection_name).ok_or(Error::NotFound)?;
+    let params = SearchParams { score_threshold: Some(threshold), with_vector: false, ..SearchParams::default() };
+    let hit = collection.get_similarity(query, 1, &params)?.into_iter().next();
+
+    collection.cache_stats.record(hit.is_some());
+    let result = if hit.is_some() { "hit" } else { "miss" };
+    CACHE_LOOKUPS.with_label_values(&[db.resolve(collection_name), result]).inc();
+    Ok(hit)
+}
+
+/// Cache `payload` under `key`, to be returned by lookups similar to `vector`.
+///
+/// Entries are embeddings with `key` as their id and `payload` as their metadata, so they expire,
+/// get evicted and are persisted like any other. A key can be cached again once its entry expired.
+///
+/// # Arguments
+///
+/// * `db`: The database holding the collection.
+/// * `collection_name`: The name or alias of the collection used as a cache.
+/// * `key`: The key of the entry, such as the prompt.
+/// * `vector`: The vector of the prompt.
+/// * `payload`: What to return on a hit, such as the response.
+/// * `ttl_secs`: How many seconds the entry lives, overriding the TTL of the collection.
+///
+/// # Returns
+///
+/// A result indicating success, or an error if the entry can't be inserted.
+pub fn put(
+    db: &mut CacheDB,
+    collection_name: &str,
+    key: String,
+    vector: Vec<f32>,
+    payload: HashMap<String, String>,
+    ttl_secs: Option<u64>,
+) -> Result<(), Error> {
+    let id = HashMap::from([(KEY_FIELD.to_string(), key)]);
+    db.insert_into_collection(collection_name, Embedding { id, vector, metadata: Some(payload), ttl_secs, ..Default::default() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CollectionOptions, Distance};
+
+    #[test]
+    fn test_lookup_and_put() {
+        let mut db = CacheDB::new();
+        db.create_collection("prompts".to_string(), 2, Distance::Cosine, CollectionOptions::default()).unwrap();
+        let payload = HashMap::from([("response".to_string(), "Paris".to_string())]);
+
+        assert_eq!(lookup(&db, "prompts", &[1.0, 0.0], 0.9), Ok(None));
+        put(&mut db, "prompts", "capital of France?".to_string(), vec![1.0, 0.1], payload.clone(), Some(60)).unwrap();
+        assert_eq!(put(&mut db, "prompts", "capital of France?".to_string(), vec![1.0, 0.1], payload.clone(), None), Err(Error::EmbeddingUniqueViolation));
+
+        let hit = lookup(&db, "prompts", &[1.0, 0.0], 0.9).unwrap().unwrap();
+        assert_eq!(hit.embedding.id[KEY_FIELD], "capital of France?");
+        assert_eq!(hit.embedding.metadata, Some(payload));
+        assert!(hit.embedding.vector.is_empty());
+        assert_eq!(lookup(&db, "prompts", &[0.0, 1.0], 0.9), Ok(None));
+        assert_eq!(lookup(&db, "prompts", &[1.0], 0.9), Err(Error::DimensionMismatch));
+        assert_eq!(lookup(&db, "missing", &[1.0, 0.0], 0.9), Err(Error::NotFound));
+
+        let stats = &db.collections["prompts"].cache_stats;
+        assert_eq!((stats.hits(), stats.misses()), (1, 2));
+        assert!((stats.hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(CacheStats::default().hit_rate(), 0.0);
+    }
+}
@@ -0,0 +1,79 @@
+use log::{info, warn};
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Resolve once the process is asked to stop, by SIGTERM or Ctrl-C.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl-C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                warn!("Failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl-C, shutting down"),
+        _ = terminate => info!("Received SIGTERM, shutting down"),
+    }
+}
+
+/// Resolve once `shutdown` is set to true, or never if its sender is dropped first.
+pub async fn requested(mut shutdown: watch::Receiver<bool>) {
+    if shutdown.wait_for(|stop| *stop).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Run a server started with `requested(shutdown)` as its graceful shutdown signal.
+///
+/// Once shutdown is requested the server stops accepting connections, and in-flight requests get
+/// up to `drain_timeout` to finish before they are abandoned.
+pub async fn run_until_drained<S>(server: S, shutdown: watch::Receiver<bool>, drain_timeout: Duration)
+where
+    S: Future<Output = ()>,
+{
+    tokio::pin!(server);
+    tokio::select! {
+        _ = &mut server => return,
+        _ = requested(shutdown) => {}
+    }
+    if tokio::time::timeout(drain_timeout, server).await.is_err() {
+        warn!("Requests still in flight after the shutdown timeout of {:?} were abandoned", drain_timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_run_until_drained() {
+        let (stop, shutdown) = watch::channel(false);
+
+        // A server finishing on its own isn't waited on.
+        run_until_drained(async {}, shutdown.clone(), Duration::from_secs(60)).await;
+
+        // A server that never drains is abandoned after the timeout.
+        let _ = stop.send(true);
+        let start = Instant::now();
+        run_until_drained(std::future::pending(), shutdown, Duration::from_millis(50)).await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
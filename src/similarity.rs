@@ -1,12 +1,15 @@
-use crate::model::Distance;
+use crate::model::{Distance, Fusion};
 
 use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
 
 
 pub fn get_cache_attr(metric: Distance, vec: &[f32]) -> f32 {
 	match metric {
 		// Dot product doesn't allow any caching
-		Distance::DotProduct | Distance::Euclidean => 0.0,
+		Distance::DotProduct => 0.0,
+		// Precompute the sum of squares of the vector
+		Distance::Euclidean => vec.iter().map(|&x| x.powi(2)).sum::<f32>(),
 		// Precompute the magnitude of the vector
 		Distance::Cosine => vec.iter().map(|&x| x.powi(2)).sum::<f32>().sqrt(),
 	}
@@ -20,6 +23,11 @@ pub fn get_distance_fn(metric: Distance) -> impl Fn(&[f32], &[f32], f32) -> f32
 	}
 }
 
+/// Whether lower scores rank better for the metric, i.e. it measures distance rather than similarity.
+pub fn is_distance(metric: Distance) -> bool {
+	metric == Distance::Euclidean
+}
+
 fn euclidian_distance(a: &[f32], b: &[f32], a_sum_squares: f32) -> f32 {
 	let mut cross_terms = 0.0;
 	let mut b_sum_squares = 0.0;
@@ -39,16 +47,77 @@ fn dot_product(a: &[f32], b: &[f32], _: f32) -> f32 {
 	a.iter().zip(b).fold(0.0, |acc, (x, y)| acc + x * y)
 }
 
+/// Late interaction similarity between two bags of token vectors: for each query token, the best dot product
+/// with any document token, summed over the query tokens.
+pub fn max_sim(query: &[Vec<f32>], document: &[Vec<f32>]) -> f32 {
+	query
+		.iter()
+		.map(|query_token| {
+			document
+				.iter()
+				.map(|document_token| dot_product(query_token, document_token, 0.0))
+				.fold(f32::NEG_INFINITY, f32::max)
+		})
+		.sum()
+}
+
 pub fn normalize(vec: &[f32]) -> Vec<f32> {
 	let magnitude = (vec.iter().fold(0.0, |acc, &val| val.mul_add(val, acc))).sqrt();
 
-	if magnitude > std::f32::EPSILON {
+	if magnitude > f32::EPSILON {
 		vec.iter().map(|&val| val / magnitude).collect()
 	} else {
 		vec.to_vec()
 	}
 }
 
+/// Keep the `limit` highest scores, best first.
+pub fn top_k(scores: impl IntoIterator<Item = ScoreIndex>, limit: usize) -> Vec<ScoreIndex> {
+	// Use a binary heap to efficiently find the top results.
+	let mut heap = BinaryHeap::new();
+	for score_index in scores {
+		// Only keep top results in the heap.
+		if heap.len() < limit || score_index < *heap.peek().unwrap() {
+			heap.push(score_index);
+			if heap.len() > limit {
+				heap.pop();
+			}
+		}
+	}
+	heap.into_sorted_vec()
+}
+
+/// Combine a vector ranking and a text ranking, both best first, into one ranking.
+pub fn fuse(fusion: Fusion, vector: &[ScoreIndex], text: &[ScoreIndex]) -> Vec<ScoreIndex> {
+	let mut fused: HashMap<usize, f32> = HashMap::new();
+
+	match fusion {
+		Fusion::Rrf { rank_constant } => {
+			for ranking in [vector, text] {
+				for (rank, score_index) in ranking.iter().enumerate() {
+					*fused.entry(score_index.index).or_insert(0.0) += 1.0 / (rank_constant + rank as f32 + 1.0);
+				}
+			}
+		}
+		Fusion::Weighted { vector_weight } => {
+			for (ranking, weight) in [(vector, vector_weight), (text, 1.0 - vector_weight)] {
+				// Scores from different rankings aren't comparable, so rescale each to [0, 1] first.
+				let max = ranking.iter().map(|s| s.score).fold(f32::NEG_INFINITY, f32::max);
+				let min = ranking.iter().map(|s| s.score).fold(f32::INFINITY, f32::min);
+				for score_index in ranking {
+					let normalized = if max > min { (score_index.score - min) / (max - min) } else { 1.0 };
+					*fused.entry(score_index.index).or_insert(0.0) += weight * normalized;
+				}
+			}
+		}
+	}
+
+	let mut fused: Vec<ScoreIndex> = fused.into_iter().map(|(index, score)| ScoreIndex { score, index }).collect();
+	// Ties are broken by position so that results are deterministic.
+	fused.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.index.cmp(&b.index)));
+	fused
+}
+
 pub struct ScoreIndex {
 	pub score: f32,
 	pub index: usize,
@@ -56,7 +125,7 @@ pub struct ScoreIndex {
 
 impl PartialEq for ScoreIndex {
 	fn eq(&self, other: &Self) -> bool {
-		self.score.eq(&other.score)
+		self.cmp(other) == Ordering::Equal
 	}
 }
 
@@ -64,13 +133,68 @@ impl Eq for ScoreIndex {}
 
 impl PartialOrd for ScoreIndex {
 	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-		// The comparison is intentionally reversed here to make the heap a min-heap
-		other.score.partial_cmp(&self.score)
+		Some(self.cmp(other))
 	}
 }
 
 impl Ord for ScoreIndex {
 	fn cmp(&self, other: &Self) -> Ordering {
-		self.partial_cmp(other).unwrap_or(Ordering::Equal)
+		// The comparison is intentionally reversed here to make the heap a min-heap. Ties are broken
+		// by position, so that equal scores rank the same whatever order they were scored in.
+		other.score.partial_cmp(&self.score).unwrap_or(Ordering::Equal).then(self.index.cmp(&other.index))
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ranking(indices: &[usize], scores: &[f32]) -> Vec<ScoreIndex> {
+		indices.iter().zip(scores).map(|(&index, &score)| ScoreIndex { score, index }).collect()
+	}
+
+	#[test]
+	fn test_max_sim() {
+		let query = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+		let document = vec![vec![0.5, 0.5], vec![0.9, 0.1], vec![0.0, -1.0]];
+
+		// Best match for the first query token is 0.9, for the second one 0.5
+		assert_eq!(max_sim(&query, &document), 1.4);
+	}
+
+	#[test]
+	fn test_top_k() {
+		let scores = ranking(&[0, 1, 2, 3], &[0.5, 2.0, -1.0, 1.0]);
+		let indices: Vec<usize> = top_k(scores, 2).iter().map(|s| s.index).collect();
+		assert_eq!(indices, vec![1, 3]);
+
+		// Equal scores keep the lowest positions, best first, in any input order.
+		let scores = ranking(&[3, 0, 2, 1], &[1.0, 1.0, 1.0, 1.0]);
+		let indices: Vec<usize> = top_k(scores, 3).iter().map(|s| s.index).collect();
+		assert_eq!(indices, vec![0, 1, 2]);
+	}
+
+	#[test]
+	fn test_fuse_rrf() {
+		let vector = ranking(&[1, 2, 3], &[0.9, 0.8, 0.7]);
+		let text = ranking(&[3, 1], &[7.5, 2.0]);
+
+		let fused = fuse(Fusion::Rrf { rank_constant: 60.0 }, &vector, &text);
+		let indices: Vec<usize> = fused.iter().map(|s| s.index).collect();
+		assert_eq!(indices, vec![1, 3, 2]);
+		assert_eq!(fused[0].score, 1.0 / 61.0 + 1.0 / 62.0);
+	}
+
+	#[test]
+	fn test_fuse_weighted() {
+		let vector = ranking(&[1, 2], &[1.0, 0.0]);
+		let text = ranking(&[2, 3], &[10.0, 5.0]);
+
+		let fused = fuse(Fusion::Weighted { vector_weight: 0.25 }, &vector, &text);
+		let indices: Vec<usize> = fused.iter().map(|s| s.index).collect();
+		assert_eq!(indices, vec![2, 1, 3]);
+		assert_eq!(fused[0].score, 0.75);
+		assert_eq!(fused[1].score, 0.25);
+		assert_eq!(fused[2].score, 0.0);
+	}
+}
@@ -0,0 +1,79 @@
+use crate::model::SparseVector;
+use crate::similarity::{top_k, ScoreIndex};
+
+use std::collections::HashMap;
+
+/// An inverted index from sparse vector dimensions to the embeddings with a non-zero value there.
+///
+/// Scoring a query only touches the postings of its own non-zero dimensions, so the cost depends on
+/// how many embeddings share terms with the query rather than on the size of the collection.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SparseIndex {
+	postings: HashMap<u32, Vec<(usize, f32)>>,
+}
+
+impl SparseIndex {
+	/// Index the sparse vector of the embedding stored at `index`.
+	pub fn add(&mut self, index: usize, vector: &SparseVector) {
+		for (&dimension, &value) in vector.indices.iter().zip(&vector.values) {
+			if value != 0.0 {
+				self.postings.entry(dimension).or_default().push((index, value));
+			}
+		}
+	}
+
+	/// Score embeddings against a sparse query by dot product.
+	///
+	/// Embeddings sharing no dimension with the query have a score of zero and are not returned.
+	///
+	/// # Returns
+	///
+	/// Up to `limit` embeddings accepted by `include` and reaching `score_threshold`, best first.
+	pub fn search<F>(&self, query: &SparseVector, limit: usize, score_threshold: Option<f32>, include: F) -> Vec<ScoreIndex>
+	where
+		F: Fn(usize) -> bool,
+	{
+		let mut scores: HashMap<usize, f32> = HashMap::new();
+		for (dimension, &weight) in query.indices.iter().zip(&query.values) {
+			let Some(postings) = self.postings.get(dimension) else {
+				continue;
+			};
+			for &(index, value) in postings {
+				*scores.entry(index).or_insert(0.0) += weight * value;
+			}
+		}
+
+		let scores = scores
+			.into_iter()
+			.filter(|&(index, score)| include(index) && score_threshold.is_none_or(|threshold| score >= threshold))
+			.map(|(index, score)| ScoreIndex { score, index });
+		top_k(scores, limit)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sparse(indices: &[u32], values: &[f32]) -> SparseVector {
+		SparseVector { indices: indices.to_vec(), values: values.to_vec() }
+	}
+
+	#[test]
+	fn test_sparse_dot_product() {
+		let mut index = SparseIndex::default();
+		index.add(0, &sparse(&[1, 5], &[1.0, 2.0]));
+		index.add(1, &sparse(&[5, 9], &[0.5, 4.0]));
+		index.add(2, &sparse(&[3], &[7.0]));
+
+		let query = sparse(&[5, 9], &[1.0, 1.0]);
+		let results: Vec<(usize, f32)> = index.search(&query, 10, None, |_| true).iter().map(|r| (r.index, r.score)).collect();
+		assert_eq!(results, vec![(1, 4.5), (0, 2.0)]);
+
+		let results = index.search(&query, 10, Some(3.0), |_| true);
+		assert_eq!(results.len(), 1);
+
+		let results: Vec<usize> = index.search(&query, 10, None, |index| index != 1).iter().map(|r| r.index).collect();
+		assert_eq!(results, vec![0]);
+	}
+}
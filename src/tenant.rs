@@ -0,0 +1,83 @@
+use std::convert::Infallible;
+use warp::Filter;
+
+/// Separates the tenant from the name of a collection or alias in the database, so that the
+/// collections of each tenant live in their own namespace.
+pub const SEPARATOR: char = '/';
+
+/// The path prefix selecting a tenant, followed by its name.
+const PATH_PREFIX: &str = "/tenants/";
+
+/// The name a collection or alias of `tenant` is stored under, or `name` itself outside of tenants.
+pub fn qualify(tenant: Option<&str>, name: &str) -> String {
+    match tenant {
+        Some(tenant) => format!("{}{}{}", tenant, SEPARATOR, name),
+        None => name.to_string(),
+    }
+}
+
+/// The tenant a stored collection or alias name belongs to, if any.
+pub fn tenant_of(name: &str) -> Option<&str> {
+    name.split_once(SEPARATOR).map(|(tenant, _)| tenant)
+}
+
+/// A stored collection or alias name as its tenant knows it.
+pub fn unqualify(name: &str) -> &str {
+    name.split_once(SEPARATOR).map_or(name, |(_, name)| name)
+}
+
+/// The tenant selected by a `/tenants/<tenant>/` prefix of `path`, if any.
+pub fn from_path(path: &str) -> Option<&str> {
+    let (tenant, _) = path.strip_prefix(PATH_PREFIX)?.split_once('/')?;
+    Some(tenant).filter(|tenant| !tenant.is_empty())
+}
+
+/// `path` without the `/tenants/<tenant>` prefix, so that every tenant's requests share a route.
+pub fn strip_path(path: &str) -> &str {
+    match from_path(path) {
+        Some(tenant) => &path[PATH_PREFIX.len() + tenant.len()..],
+        None => path,
+    }
+}
+
+/// A filter skipping the `/tenants/<tenant>` prefix of a path, if there is one. The tenant itself
+/// is read back from the full path by `authorized_json`.
+pub fn skip_path_prefix() -> impl Filter<Extract = (), Error = Infallible> + Clone {
+    warp::path("tenants")
+        .and(warp::path::param::<String>())
+        .map(Some)
+        .or(warp::any().map(|| None))
+        .unify()
+        .map(|_: Option<String>| ())
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_and_paths() {
+        assert_eq!(qualify(Some("team-a"), "books"), "team-a/books");
+        assert_eq!(qualify(None, "books"), "books");
+        assert_eq!(tenant_of("team-a/books"), Some("team-a"));
+        assert_eq!(tenant_of("books"), None);
+        assert_eq!(unqualify("team-a/books"), "books");
+        assert_eq!(unqualify("books"), "books");
+
+        assert_eq!(from_path("/tenants/team-a/get_similarity"), Some("team-a"));
+        assert_eq!(from_path("/tenants//get_similarity"), None);
+        assert_eq!(from_path("/get_similarity"), None);
+        assert_eq!(strip_path("/tenants/team-a/get_similarity"), "/get_similarity");
+        assert_eq!(strip_path("/get_similarity"), "/get_similarity");
+    }
+
+    #[tokio::test]
+    async fn test_skip_path_prefix() {
+        let filter = skip_path_prefix().and(warp::path!("get_similarity")).map(|| "matched");
+        for path in ["/tenants/team-a/get_similarity", "/get_similarity"] {
+            assert!(warp::test::request().path(path).matches(&filter).await, "{}", path);
+        }
+        assert!(!warp::test::request().path("/tenants/get_similarity").matches(&filter).await);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::model::Embedding;
+use crate::similarity::{top_k, ScoreIndex};
+
+use schemars::JsonSchema;
+use std::collections::HashMap;
+
+// Standard BM25 term frequency saturation and document length normalization parameters.
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// An inverted index over one metadata field of a collection's embeddings, scored with BM25.
+///
+/// Documents are identified by the position of their embedding in the collection. Only the indexed
+/// field is serialized; the postings are rebuilt from the embeddings when the index is created.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema, PartialEq)]
+pub struct TextIndex {
+	pub field: String,
+	#[serde(skip)]
+	postings: HashMap<String, Vec<(usize, u32)>>,
+	#[serde(skip)]
+	doc_lengths: Vec<u32>,
+	#[serde(skip)]
+	total_length: u64,
+	#[serde(skip)]
+	documents: usize,
+}
+
+impl TextIndex {
+	pub fn new(field: String) -> Self {
+		Self {
+			field,
+			postings: HashMap::new(),
+			doc_lengths: Vec::new(),
+			total_length: 0,
+			documents: 0,
+		}
+	}
+
+	/// Index the configured metadata field of the embedding stored at `index`.
+	///
+	/// Embeddings must be added in collection order; those without the field are counted with length zero.
+	pub fn add(&mut self, index: usize, embedding: &Embedding) {
+		debug_assert_eq!(index, self.doc_lengths.len());
+
+		let text = embedding.metadata.as_ref().and_then(|metadata| metadata.get(&self.field));
+		let Some(text) = text else {
+			self.doc_lengths.push(0);
+			return;
+		};
+
+		let tokens = tokenize(text);
+		let mut term_frequencies: HashMap<String, u32> = HashMap::new();
+		for token in &tokens {
+			*term_frequencies.entry(token.clone()).or_insert(0) += 1;
+		}
+		for (term, frequency) in term_frequencies {
+			self.postings.entry(term).or_default().push((index, frequency));
+		}
+
+		self.doc_lengths.push(tokens.len() as u32);
+		self.total_length += tokens.len() as u64;
+		self.documents += 1;
+	}
+
+	/// Score documents against a free-text query with BM25.
+	///
+	/// # Returns
+	///
+	/// Up to `limit` matching documents accepted by `include`, best first.
+	pub fn search<F>(&self, query: &str, limit: usize, include: F) -> Vec<ScoreIndex>
+	where
+		F: Fn(usize) -> bool,
+	{
+		if self.documents == 0 {
+			return Vec::new();
+		}
+
+		let documents = self.documents as f32;
+		let average_length = self.total_length as f32 / documents;
+
+		let mut terms = tokenize(query);
+		terms.sort();
+		terms.dedup();
+
+		let mut scores: HashMap<usize, f32> = HashMap::new();
+		for term in terms {
+			let Some(postings) = self.postings.get(&term) else {
+				continue;
+			};
+			let matching = postings.len() as f32;
+			let idf = ((documents - matching + 0.5) / (matching + 0.5)).ln_1p();
+
+			for &(index, frequency) in postings {
+				let frequency = frequency as f32;
+				let length_norm = K1 * (1.0 - B + B * self.doc_lengths[index] as f32 / average_length);
+				*scores.entry(index).or_insert(0.0) += idf * frequency * (K1 + 1.0) / (frequency + length_norm);
+			}
+		}
+
+		let scores = scores.into_iter().filter(|&(index, _)| include(index)).map(|(index, score)| ScoreIndex { score, index });
+		top_k(scores, limit)
+	}
+}
+
+/// Split text into lowercase alphanumeric terms.
+pub fn tokenize(text: &str) -> Vec<String> {
+	text.split(|c: char| !c.is_alphanumeric())
+		.filter(|token| !token.is_empty())
+		.map(str::to_lowercase)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn embedding(unique_id: &str, text: Option<&str>) -> Embedding {
+		let mut id = HashMap::new();
+		id.insert("unique_id".to_string(), unique_id.to_string());
+		let metadata = text.map(|text| {
+			let mut metadata = HashMap::new();
+			metadata.insert("text".to_string(), text.to_string());
+			metadata
+		});
+		Embedding { id, vector: vec![0.0], metadata, ..Default::default() }
+	}
+
+	#[test]
+	fn test_tokenize() {
+		assert_eq!(tokenize("Hello, World! rust-lang 2024"), vec!["hello", "world", "rust", "lang", "2024"]);
+	}
+
+	#[test]
+	fn test_bm25_ranking() {
+		let mut index = TextIndex::new("text".to_string());
+		let documents = [
+			Some("the quick brown fox"),
+			None,
+			Some("rust vector database in rust"),
+			Some("a vector database"),
+		];
+		for (i, text) in documents.iter().enumerate() {
+			index.add(i, &embedding(&i.to_string(), *text));
+		}
+
+		let results: Vec<usize> = index.search("Rust database", 10, |_| true).iter().map(|r| r.index).collect();
+		assert_eq!(results, vec![2, 3]);
+		let results: Vec<usize> = index.search("Rust database", 10, |index| index != 2).iter().map(|r| r.index).collect();
+		assert_eq!(results, vec![3]);
+
+		assert!(index.search("missing", 10, |_| true).is_empty());
+		assert_eq!(index.search("database", 1, |_| true).len(), 1);
+	}
+}
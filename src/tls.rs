@@ -0,0 +1,207 @@
+use log::{error, info};
+use std::fs;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use crate::shutdown::{requested, run_until_drained};
+use tokio::sync::{oneshot, watch};
+use warp::{Filter, Reply};
+
+/// How often the certificate files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Where to read the server certificate, its private key and, for mutual TLS, the CA that client
+/// certificates must be signed by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// When set, clients must present a certificate signed by this CA.
+    pub client_ca_path: Option<PathBuf>,
+}
+
+/// The PEM contents of the files of a `TlsConfig`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsMaterial {
+    pub cert: Vec<u8>,
+    pub key: Vec<u8>,
+    pub client_ca: Option<Vec<u8>>,
+}
+
+impl TlsConfig {
+    /// Build the TLS settings from optional paths.
+    ///
+    /// # Returns
+    ///
+    /// `None` when neither a certificate nor a key is configured, or an error when only one of them is.
+    pub fn from_paths(cert_path: Option<String>, key_path: Option<String>, client_ca_path: Option<String>) -> Result<Option<Self>, String> {
+        match (cert_path, key_path) {
+            (Some(cert_path), Some(key_path)) => Ok(Some(Self {
+                cert_path: cert_path.into(),
+                key_path: key_path.into(),
+                client_ca_path: client_ca_path.map(PathBuf::from),
+            })),
+            (None, None) if client_ca_path.is_none() => Ok(None),
+            _ => Err("TLS requires both a certificate and a key path".to_string()),
+        }
+    }
+
+    pub fn load(&self) -> std::io::Result<TlsMaterial> {
+        Ok(TlsMaterial {
+            cert: fs::read(&self.cert_path)?,
+            key: fs::read(&self.key_path)?,
+            client_ca: self.client_ca_path.as_ref().map(fs::read).transpose()?,
+        })
+    }
+
+    /// The modification times of the configured files, which change when any of them is replaced.
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [Some(&self.cert_path), Some(&self.key_path), self.client_ca_path.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+            .collect()
+    }
+}
+
+/// Serve `routes` on `addr`, over HTTPS when `tls` is configured and plain HTTP otherwise, until
+/// `shutdown` is set and in-flight requests are drained or `drain_timeout` passes.
+pub async fn serve<F>(routes: F, addr: SocketAddr, tls: Option<TlsConfig>, shutdown: watch::Receiver<bool>, drain_timeout: Duration)
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    match tls {
+        Some(tls) => serve_tls(routes, addr, tls, shutdown, drain_timeout).await,
+        None => {
+            let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, requested(shutdown.clone()));
+            run_until_drained(server, shutdown, drain_timeout).await;
+        }
+    }
+}
+
+/// Serve over HTTPS, restarting the server when the certificate files change.
+///
+/// If the new files can't be used, for instance because they were only partly written, the server
+/// is restarted with the previous certificates until the files change again.
+async fn serve_tls<F>(routes: F, addr: SocketAddr, tls: TlsConfig, shutdown: watch::Receiver<bool>, drain_timeout: Duration)
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let mut current = tls.load().unwrap_or_else(|err| panic!("Failed to read TLS certificates: {}", err));
+    let mut previous: Option<TlsMaterial> = None;
+
+    loop {
+        let mut modified = tls.modified();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let signal = {
+            let shutdown = shutdown.clone();
+            async move {
+                tokio::select! {
+                    _ = stopped => {}
+                    _ = requested(shutdown) => {}
+                }
+            }
+        };
+        let server = match bind_tls(routes.clone(), addr, &current, signal) {
+            Ok(server) => server,
+            Err(err) => match previous.take() {
+                Some(material) => {
+                    error!("Failed to reload TLS certificates, keeping the previous ones: {}", err);
+                    current = material;
+                    continue;
+                }
+                None => panic!("Failed to start TLS server on {}: {}", addr, err),
+            },
+        };
+        info!("Serving HTTPS on {}", addr);
+        tokio::pin!(server);
+
+        loop {
+            tokio::select! {
+                _ = &mut server => return,
+                _ = requested(shutdown.clone()) => {
+                    run_until_drained(server, shutdown, drain_timeout).await;
+                    return;
+                }
+                _ = tokio::time::sleep(RELOAD_INTERVAL) => {}
+            }
+            let now = tls.modified();
+            if now == modified {
+                continue;
+            }
+            modified = now;
+            match tls.load() {
+                Ok(material) => {
+                    previous = Some(std::mem::replace(&mut current, material));
+                    break;
+                }
+                Err(err) => error!("Failed to read changed TLS certificates: {}", err),
+            }
+        }
+
+        info!("TLS certificates changed, restarting the server");
+        let _ = stop.send(());
+        server.await;
+    }
+}
+
+fn bind_tls<F>(routes: F, addr: SocketAddr, material: &TlsMaterial, signal: impl Future<Output = ()> + Send + 'static) -> Result<impl Future<Output = ()>, warp::Error>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let mut server = warp::serve(routes).tls().cert(&material.cert).key(&material.key);
+    if let Some(client_ca) = &material.client_ca {
+        server = server.client_auth_required(client_ca);
+    }
+    let (_, server) = server.try_bind_with_graceful_shutdown(addr, signal)?;
+    Ok(server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_tls_config_from_paths() {
+        assert_eq!(TlsConfig::from_paths(None, None, None), Ok(None));
+        assert!(TlsConfig::from_paths(Some("cert.pem".to_string()), None, None).is_err());
+        assert!(TlsConfig::from_paths(None, None, Some("ca.pem".to_string())).is_err());
+
+        let config = TlsConfig::from_paths(Some("cert.pem".to_string()), Some("key.pem".to_string()), Some("ca.pem".to_string()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.client_ca_path, Some(PathBuf::from("ca.pem")));
+    }
+
+    #[test]
+    fn test_tls_material_reload() {
+        let mut cert = NamedTempFile::new().unwrap();
+        let key = NamedTempFile::new().unwrap();
+        cert.write_all(b"first").unwrap();
+        let config = TlsConfig {
+            cert_path: cert.path().to_path_buf(),
+            key_path: key.path().to_path_buf(),
+            client_ca_path: None,
+        };
+
+        let material = config.load().unwrap();
+        assert_eq!(material.cert, b"first");
+        let modified = config.modified();
+        assert_eq!(modified.len(), 2);
+
+        std::thread::sleep(Duration::from_millis(20));
+        cert.write_all(b" second").unwrap();
+        cert.flush().unwrap();
+        assert_ne!(config.modified(), modified);
+        assert_eq!(config.load().unwrap().cert, b"first second");
+
+        let missing = TlsConfig { client_ca_path: Some(PathBuf::from("/nonexistent/ca.pem")), ..config };
+        assert!(missing.load().is_err());
+    }
+}